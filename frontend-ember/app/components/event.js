@@ -23,20 +23,90 @@ export default class Event extends Component {
 
     formatRecurrence(recurrance) {
         if (recurrance) {
-            return 'Recurring';
+            return recurrance.description || 'Recurring';
         }
         return 'One-time';
     }
 
+    formatAttendance(status) {
+        const words = {
+            'ACCEPTED': 'going',
+            'DECLINED': 'not going',
+            'TENTATIVE': 'maybe',
+            'DELEGATED': 'delegated',
+        };
+        return words[status] || 'no reply yet';
+    }
+
+    isPrivate(visibility) {
+        return visibility === 'private';
+    }
+
+    icsUrl(id) {
+        return '/ics/event/' + encodeURIComponent(id) + '.ics';
+    }
+
+    // Without logging in, people confirm their address by mail to get an invite
+    async requestInvite(id) {
+        let email = prompt('Log in to subscribe, or enter your email address to get an invite:');
+        if (!email) {
+            return;
+        }
+        let response = await fetch('/api/events/' + encodeURIComponent(id) + '/request-invite', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ email }),
+        });
+        if (!response.ok) {
+            alert('Could not request an invite: ' + await response.text());
+            return;
+        }
+        alert('Check your inbox for a link to confirm your address.');
+    }
+
     formatDescription(description) {
         // TODO: stuff
         return description;
     }
 
     actions = {
-        subscribe(event) {
-            alert("Not yet! :-)");
-            console.log("Not yet! :-)");
+        async subscribe(event) {
+            // Occurrences of a series are subscribed to as the whole series
+            let id = event.recurringEventId || event.id;
+            let response = await fetch('/api/events/' + encodeURIComponent(id) + '/subscribe', {
+                method: 'POST',
+            });
+            if (response.status === 401) {
+                await this.requestInvite(id);
+                return;
+            }
+            if (!response.ok) {
+                alert('Could not subscribe: ' + await response.text());
+                return;
+            }
+            let result = await response.json();
+            alert(result.invited
+                ? 'Subscribed! An invite is on its way to your inbox.'
+                : 'Subscribed!');
+        },
+
+        async rsvp(event, status) {
+            let id = event.recurringEventId || event.id;
+            let response = await fetch('/api/events/' + encodeURIComponent(id) + '/rsvp', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ status }),
+            });
+            if (response.status === 401) {
+                alert('Log in to say whether you are coming.');
+                return;
+            }
+            if (!response.ok) {
+                alert('Could not save your answer: ' + await response.text());
+                return;
+            }
+            let { attendance } = await response.json();
+            alert(`Saved! ${attendance.going} going, ${attendance.maybe} maybe, ${attendance.notGoing} not going.`);
         }
     }
 }
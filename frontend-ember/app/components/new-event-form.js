@@ -0,0 +1,44 @@
+import Component from '@glimmer/component';
+import { action } from '@ember/object';
+import { tracked } from '@glimmer/tracking';
+
+export default class NewEventFormComponent extends Component {
+    @tracked message = '';
+
+    timeZone = Intl.DateTimeFormat().resolvedOptions().timeZone;
+
+    @action
+    async submit(submitEvent) {
+        submitEvent.preventDefault();
+        let form = new FormData(submitEvent.target);
+        let timeZone = form.get('timeZone');
+        let time = (value) => ({ dateTime: value + ':00', timeZone });
+        let recurrence = form.get('recurrence');
+        let body = {
+            summary: form.get('summary'),
+            description: form.get('description'),
+            location: form.get('location'),
+            start: time(form.get('start')),
+            end: time(form.get('end')),
+            recurrence: recurrence ? ['RRULE:' + recurrence] : [],
+            teams: form.get('teams').split(',').map((team) => team.trim()).filter((team) => team),
+            visibility: form.get('private') ? 'private' : 'public',
+        };
+        let response = await fetch('/api/events', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify(body),
+        });
+        if (response.status === 401) {
+            this.message = 'Log in to create events.';
+            return;
+        }
+        if (!response.ok) {
+            this.message = 'Could not create the event: ' + await response.text();
+            return;
+        }
+        let event = await response.json();
+        this.message = 'Created "' + event.summary + '"!';
+        submitEvent.target.reset();
+    }
+}
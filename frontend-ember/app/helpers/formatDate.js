@@ -1,10 +1,24 @@
-export default function formatDate(dateString, timezoneString) {
+// Format an event start or end: `{date}` for all-day events, `{dateTime, timeZone}` with
+// an RFC 3339 `dateTime` for timed ones, and `{dateTime}` without an offset for floating
+// times, which are shown as they are.
+export default function formatDate(time, { lastDay = false } = {}) {
+
+    if (time.date) {
+        const date = new Date(time.date);
+        // All-day events end on the day after their last day
+        if (lastDay) {
+            date.setUTCDate(date.getUTCDate() - 1);
+        }
+        return new Intl.DateTimeFormat('en-US', {
+            dateStyle: 'full',
+            timeZone: 'UTC',
+        }).format(date);
+    }
 
-    const date = new Date(dateString);
     // TODO make locale sensitive
     return new Intl.DateTimeFormat('en-US', {
         dateStyle: 'full',
-        timeStyle: 'long',
-        timeZone: timezoneString,
-    }).format(date);
-}
\ No newline at end of file
+        timeStyle: time.timeZone ? 'long' : 'short',
+        timeZone: time.timeZone,
+    }).format(new Date(time.dateTime));
+}
@@ -9,4 +9,5 @@ export default class Router extends EmberRouter {
 Router.map(function () {
   this.route('about');
   this.route('new-event');
+  this.route('event', { path: '/events/:id' });
 });
@@ -0,0 +1,11 @@
+import Route from '@ember/routing/route';
+
+export default class EventRoute extends Route {
+  async model({ id }) {
+    let response = await fetch('/api/events/' + encodeURIComponent(id));
+    if (!response.ok) {
+      throw new Error(await response.text());
+    }
+    return response.json();
+  }
+}
@@ -3,7 +3,7 @@ use oauth2::reqwest::async_http_client;
 use oauth2::TokenResponse;
 use oauth2::{
     basic::BasicClient, basic::BasicTokenType, AuthUrl, AuthorizationCode, ClientId, ClientSecret,
-    CsrfToken, EmptyExtraTokenFields, Scope, StandardTokenResponse, TokenUrl,
+    CsrfToken, EmptyExtraTokenFields, RedirectUrl, Scope, StandardTokenResponse, TokenUrl,
 };
 use reqwest::Client as ReqwestClient;
 use reqwest::Url;
@@ -33,9 +33,16 @@ struct Email {
     verified: bool,
 }
 
+#[derive(Debug, Deserialize)]
+struct GitHubUser {
+    login: String,
+}
+
 #[derive(Debug)]
 pub struct AuthenticatedUser {
     pub email: String,
+    /// The GitHub login, which is how the team roster knows people.
+    pub login: String,
 }
 
 impl From<Arc<OAuthConfig>> for Auth {
@@ -44,8 +51,10 @@ impl From<Arc<OAuthConfig>> for Auth {
         let client_secret = ClientSecret::new(config.client_secret.to_string());
         let auth_url = AuthUrl::new(config.auth_url.to_string()).unwrap();
         let token_url = TokenUrl::new(config.token_url.to_string()).unwrap();
+        let redirect_url = RedirectUrl::new(config.redirect_url.to_string()).unwrap();
 
-        let client = BasicClient::new(client_id, Some(client_secret), auth_url, Some(token_url));
+        let client = BasicClient::new(client_id, Some(client_secret), auth_url, Some(token_url))
+            .set_redirect_uri(redirect_url);
         Self { client }
     }
 }
@@ -95,23 +104,36 @@ impl Auth {
             .await
             .unwrap();
         tracing::info!("Got user email! {:?}", user_email.to_string());
+        // Without a login, people can still use the calendar by email, just not as
+        // members of their teams
+        let login = self
+            .get_authenticated_user_login(token.secret().as_str())
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("couldn't get the GitHub login: {e:?}");
+                String::new()
+            });
 
-        let authenticated_user = AuthenticatedUser { email: user_email };
+        let authenticated_user = AuthenticatedUser {
+            email: user_email,
+            login,
+        };
 
-        return Option::Some(authenticated_user);
+        Option::Some(authenticated_user)
     }
 
     // Check the CSRF state, this is to prevent CSRF attacks.
     // The state must exist, AND be the same as the one we stored in the session.
     fn validate_state(&self, response_state: String, expected_state: Option<AuthState>) -> bool {
-        if expected_state.is_none() {
+        let Some(expected_state) = expected_state else {
             tracing::error!("No CSRF token in session!");
-        } else if response_state != expected_state.unwrap().csrf_state {
+            return false;
+        };
+        if response_state != expected_state.csrf_state {
             tracing::error!("CSRF token mismatch!");
-        } else {
-            return true;
+            return false;
         }
-        return false;
+        true
     }
 
     async fn exchange_code(
@@ -143,7 +165,15 @@ impl Auth {
         }
 
         // TODO: If no email, return an error
-        return Ok("no email!".to_string());
+        Ok("no email!".to_string())
+    }
+
+    async fn get_authenticated_user_login(&self, token: &str) -> anyhow::Result<String> {
+        let response = self
+            .send_request("https://api.github.com/user", token)
+            .await?;
+        let user: GitHubUser = response.json().await?;
+        Ok(user.login)
     }
 
     async fn send_request(&self, url: &str, token: &str) -> anyhow::Result<reqwest::Response> {
@@ -159,7 +189,7 @@ impl Auth {
         match response.status() {
             reqwest::StatusCode::OK => {
                 tracing::info!("Got response from GitHub");
-                return Ok(response);
+                Ok(response)
             }
             reqwest::StatusCode::FORBIDDEN => {
                 tracing::error!("Received a 403 Forbidden response");
@@ -175,18 +205,18 @@ impl Auth {
                         // Handle the rate limit exceeded case here if needed
                     }
                 }
-                return Err(anyhow::anyhow!("Received a 403 Forbidden response"));
+                Err(anyhow::anyhow!("Received a 403 Forbidden response"))
             }
             reqwest::StatusCode::UNAUTHORIZED => {
                 tracing::error!("Received a 401 Unauthorized response");
-                return Err(anyhow::anyhow!("Received a 401 Forbidden response"));
+                Err(anyhow::anyhow!("Received a 401 Forbidden response"))
             }
             _ => {
                 tracing::error!(
                     "Received an unexpected HTTP response: {}",
                     response.status()
                 );
-                return Err(anyhow::anyhow!("Received an unexpected HTTP response"));
+                Err(anyhow::anyhow!("Received an unexpected HTTP response"))
             }
         }
     }
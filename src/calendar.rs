@@ -1,56 +1,698 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::config::Configuration;
-use google_calendar::GoogleCalendar;
-use transformer::google_to_americano;
+use crate::store::Store;
+pub use backend::CalendarBackend;
+pub use date_time::EventTime;
+pub use query::{Cursor, EventQuery};
+pub use recurrence::Recurrence;
+pub use teams::Team;
 
+pub mod backend;
+mod caldav;
+pub mod date_time;
+pub mod draft;
 mod google_calendar;
-mod invite;
+pub mod ical;
+mod ics_directory;
+pub mod invite;
+pub mod query;
+pub mod recurrence;
+pub mod teams;
 mod transformer;
 
+/// The events of the configured backend, served from the local [`Store`].
+///
+/// Reads only touch the store, which [`Calendar::sync`] keeps up to date; writes go to
+/// the backend first and are then mirrored into the store.
 pub struct Calendar {
-    config: Arc<Configuration>,
+    backend: Box<dyn CalendarBackend>,
+    store: Arc<Store>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Events {
     pub events: Vec<Event>,
+    /// Pass as `cursor` to get the next page, if there is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<Cursor>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Event {
+    pub id: String,
     pub summary: String,
     pub description: Option<String>,
     pub location: Option<String>,
+    /// A page about the event, as in the iCalendar `URL`.
+    pub url: Option<String>,
     pub creator_email: String,
     pub creator_name: String,
-    pub start_datetime: String,
-    pub start_timezone: String,
-    pub end_datetime: String,
-    pub end_timezone: String,
-    pub recurrence: bool,
+    pub start: EventTime,
+    /// For all-day events this is the day after the last one, so a multi-day event on
+    /// the 13th and 14th ends on the 15th.
+    pub end: EventTime,
+    /// Set on series masters and on each of their occurrences.
+    pub recurrence: Option<Recurrence>,
+    /// For a single occurrence of a series, the id of the series.
+    pub recurring_event_id: Option<String>,
+    /// For a single occurrence of a series, the start it has according to the series
+    /// (before any rescheduling). Identifies the occurrence like `RECURRENCE-ID`.
+    pub original_start: Option<EventTime>,
+    /// The iCalendar `STATUS`: `TENTATIVE`, `CONFIRMED` or `CANCELLED`.
+    pub status: Option<String>,
+    /// The teams whose calendars the event is on, see [`teams`].
+    #[serde(default)]
+    pub teams: Vec<String>,
+    /// The iCalendar `SEQUENCE`: the organizer bumps it with each significant change,
+    /// so that updates arriving out of order can be told apart.
+    #[serde(default)]
+    pub sequence: u32,
+    /// Who is invited, as far as the organizer told us or replies came in.
+    #[serde(default)]
+    pub attendees: Vec<Attendee>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// When the event last changed, as far as the store noticed.
+    #[serde(default)]
+    pub updated: Option<DateTime<Utc>>,
+    /// Set on the placeholders that stand in for private events the viewer can't see,
+    /// see [`Event::redacted`].
+    #[serde(default)]
+    pub redacted: bool,
+}
+
+/// Someone invited to an event, and whether they are coming.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attendee {
+    pub email: String,
+    pub name: Option<String>,
+    /// Participation status as in the iCalendar `PARTSTAT` parameter, e.g. `ACCEPTED`.
+    pub status: String,
+    /// The iCalendar `ROLE`, e.g. `REQ-PARTICIPANT` or `CHAIR`.
+    pub role: Option<String>,
+}
+
+/// How many of the attendees of an event said they are coming.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attendance {
+    pub going: usize,
+    pub maybe: usize,
+    pub not_going: usize,
+    pub no_reply: usize,
+}
+
+/// What someone says about coming to an event in the app.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Rsvp {
+    Going,
+    Maybe,
+    NotGoing,
+}
+
+impl Rsvp {
+    /// The iCalendar `PARTSTAT` saying the same.
+    pub fn status(self) -> &'static str {
+        match self {
+            Rsvp::Going => "ACCEPTED",
+            Rsvp::Maybe => "TENTATIVE",
+            Rsvp::NotGoing => "DECLINED",
+        }
+    }
+}
+
+impl Attendance {
+    pub fn of(attendees: &[Attendee]) -> Self {
+        let mut attendance = Attendance::default();
+        for attendee in attendees {
+            match attendee.status.as_str() {
+                "ACCEPTED" => attendance.going += 1,
+                "TENTATIVE" => attendance.maybe += 1,
+                "DECLINED" => attendance.not_going += 1,
+                _ => attendance.no_reply += 1,
+            }
+        }
+        attendance
+    }
+}
+
+/// How an event is pinned to the timeline.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EventKind {
+    /// Starts and ends at fixed instants, shown in a time zone.
+    Timed,
+    /// Happens at the same wall-clock time wherever you are.
+    Floating,
+    /// Takes up whole days.
+    AllDay,
+}
+
+/// Who is looking at events, to decide which private events they get to see.
+#[derive(Clone, Debug, Default)]
+pub struct Viewer {
+    /// The email of the logged in user, if there is one.
+    pub email: Option<String>,
+    /// The teams the user is on according to the roster.
+    pub teams: Vec<String>,
+}
+
+/// Who gets to see an event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Only for the members of the event's teams, as asked for with the `private` tag
+    /// or an iCalendar `CLASS` of `PRIVATE` or `CONFIDENTIAL`.
+    Private,
+}
+
+impl Visibility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Private => "private",
+        }
+    }
+}
+
+impl std::str::FromStr for Visibility {
+    type Err = anyhow::Error;
+
+    fn from_str(visibility: &str) -> anyhow::Result<Self> {
+        match visibility {
+            "public" => Ok(Visibility::Public),
+            "private" => Ok(Visibility::Private),
+            _ => anyhow::bail!("unknown visibility `{visibility}`"),
+        }
+    }
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Timed => "timed",
+            EventKind::Floating => "floating",
+            EventKind::AllDay => "allDay",
+        }
+    }
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        self.start.kind()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.status.as_deref() == Some("CANCELLED")
+    }
+
+    /// Record what `attendee` said about coming, adding them if they weren't invited.
+    /// A missing name or role leaves the known one in place.
+    pub fn set_attendance(&mut self, attendee: &Attendee) {
+        let known = self
+            .attendees
+            .iter_mut()
+            .find(|known| known.email.eq_ignore_ascii_case(&attendee.email));
+        match known {
+            Some(known) => {
+                known.status = attendee.status.clone();
+                known.name = attendee.name.clone().or(known.name.take());
+                known.role = attendee.role.clone().or(known.role.take());
+            }
+            None => self.attendees.push(attendee.clone()),
+        }
+    }
+
+    /// Whether `viewer` may see the details of the event: everyone can see public
+    /// events, and private ones are for the members of the event's teams and the people
+    /// invited to it.
+    pub fn visible_to(&self, viewer: &Viewer) -> bool {
+        if self.visibility == Visibility::Public {
+            return true;
+        }
+        if self.teams.iter().any(|team| viewer.teams.contains(team)) {
+            return true;
+        }
+        viewer.email.as_deref().is_some_and(|email| {
+            self.creator_email.eq_ignore_ascii_case(email)
+                || self
+                    .attendees
+                    .iter()
+                    .any(|attendee| attendee.email.eq_ignore_ascii_case(email))
+        })
+    }
+
+    /// A placeholder that only tells when the event is, to show as busy to those who
+    /// may not see it.
+    pub fn redacted(&self) -> Event {
+        Event {
+            id: self.id.clone(),
+            summary: "Busy".to_string(),
+            start: self.start,
+            end: self.end,
+            visibility: Visibility::Private,
+            redacted: true,
+            ..Event::default()
+        }
+    }
+
+    /// The event as `viewer` gets to see it: a placeholder if it is private to them, and
+    /// without who is coming unless they are logged in.
+    pub fn for_viewer(&self, viewer: &Viewer) -> Event {
+        if !self.visible_to(viewer) {
+            return self.redacted();
+        }
+        let mut event = self.clone();
+        if viewer.email.is_none() {
+            event.attendees.clear();
+        }
+        event
+    }
+
+    /// Whether the event spans more than one calendar day.
+    pub fn is_multi_day(&self) -> bool {
+        let (start, end) = (self.start.local(), self.end.local());
+        match self.kind() {
+            EventKind::AllDay => end - start > Duration::days(1),
+            // An event ending at midnight doesn't take up the next day
+            _ => (end - Duration::seconds(1)).date() > start.date(),
+        }
+    }
 }
 
-impl From<&Arc<Configuration>> for Calendar {
-    fn from(config: &Arc<Configuration>) -> Self {
-        Self {
-            config: config.clone(),
+impl Events {
+    /// Leave out what `viewer` may not see, see [`Event::for_viewer`].
+    pub fn redact_for(&mut self, viewer: &Viewer) {
+        for event in &mut self.events {
+            *event = event.for_viewer(viewer);
         }
     }
 }
 
+impl TryFrom<&Arc<Configuration>> for Calendar {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &Arc<Configuration>) -> anyhow::Result<Self> {
+        let store = Store::open(&config.store.path)?;
+        Ok(Self::new(backend::from_config(config)?, Arc::new(store)))
+    }
+}
+
 impl Calendar {
-    pub async fn events(&self) -> anyhow::Result<Events> {
-        // Using Google Calendar API behind the scenes
+    pub fn new(backend: Box<dyn CalendarBackend>, store: Arc<Store>) -> Self {
+        Self { backend, store }
+    }
+
+    /// Whether the backend can't store events, such as invites that come in by mail.
+    pub fn is_read_only(&self) -> bool {
+        self.backend.is_read_only()
+    }
 
-        let google_calendar = GoogleCalendar::from(&self.config.clone());
-        let g_events = google_calendar.events().await.unwrap();
+    pub fn store(&self) -> &Arc<Store> {
+        &self.store
+    }
+
+    /// The page of events (with recurring series expanded) that `query` asks for.
+    pub async fn events(&self, query: &EventQuery) -> anyhow::Result<Events> {
+        let (from, to) = query.window()?;
+        let mut events = recurrence::expand(self.store.events(from, to)?, from, to);
+        events.retain(|event| query.matches_teams(event));
+        let mut events = query.paginate(events);
+        self.add_occurrence_rsvps(&mut events.events)?;
+        Ok(events)
+    }
+
+    /// The events between `from` and `to` as they are stored, without expanding recurring
+    /// series, for exporting. Cancelled events are left out, except for cancelled
+    /// occurrences of the series that are in.
+    pub async fn series(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Event>> {
+        let mut events = self.store.events(from, to)?;
+        events.retain(|event| !event.is_cancelled() || event.recurring_event_id.is_some());
+        let series: std::collections::HashSet<String> = events
+            .iter()
+            .filter(|event| event.recurring_event_id.is_none())
+            .map(|event| event.id.clone())
+            .collect();
+        events.retain(|event| {
+            event
+                .recurring_event_id
+                .as_ref()
+                .is_none_or(|id| series.contains(id))
+        });
+        Ok(events)
+    }
+
+    /// The known teams, and any other team that events are tagged with.
+    pub async fn teams(&self) -> anyhow::Result<Vec<Team>> {
+        self.store.teams()
+    }
+
+    /// Only the teams of the roster, which are the ones that mail can be routed to.
+    pub async fn known_teams(&self) -> anyhow::Result<Vec<Team>> {
+        self.store.known_teams()
+    }
+
+    /// The event with `id`, which may also be one occurrence of a series that is only
+    /// stored as the series (see [`recurrence::instance_id`]).
+    pub async fn event(&self, id: &str) -> anyhow::Result<Option<Event>> {
+        if let Some(event) = self.store.event(id)? {
+            return Ok(Some(event));
+        }
+        let Some((series_id, start)) = recurrence::parse_instance_id(id) else {
+            return Ok(None);
+        };
+        let Some(master) = self.store.event(series_id)? else {
+            return Ok(None);
+        };
+        // Only if the series really has an occurrence then
+        let occurrences = recurrence::expand(vec![master], start, start + Duration::seconds(1));
+        let mut occurrences: Vec<Event> = occurrences
+            .into_iter()
+            .filter(|occurrence| occurrence.id == id)
+            .collect();
+        self.add_occurrence_rsvps(&mut occurrences)?;
+        Ok(occurrences.pop())
+    }
+
+    /// Apply the RSVPs to single occurrences (see [`Calendar::rsvp`]) to those of
+    /// `events` that are only stored as their series.
+    fn add_occurrence_rsvps(&self, events: &mut [Event]) -> anyhow::Result<()> {
+        let mut rsvps = std::collections::HashMap::new();
+        for event in events {
+            let (Some(series_id), Some(original)) =
+                (&event.recurring_event_id, event.original_start)
+            else {
+                continue;
+            };
+            if !rsvps.contains_key(series_id) {
+                rsvps.insert(series_id.clone(), self.store.occurrence_rsvps(series_id)?);
+            }
+            for (start, attendee) in &rsvps[series_id] {
+                if *start == original.instant() {
+                    event.set_attendance(attendee);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn create_event(&self, event: Event) -> anyhow::Result<Event> {
+        let event = self.backend.create_event(event).await?;
+        self.store.upsert_event(&event)?;
+        Ok(event)
+    }
 
-        // Transform events to Americano event format
-        let americano_events = google_to_americano(g_events);
-        Ok(americano_events)
+    pub async fn update_event(&self, id: &str, event: Event) -> anyhow::Result<Event> {
+        let event = self.backend.update_event(id, event).await?;
+        self.store.upsert_event(&event)?;
+        Ok(event)
     }
+
+    /// Add an event that came from outside the backend, such as an invite, or update it
+    /// if we already have it. Returns `None` if what we have is newer (by `SEQUENCE`),
+    /// so that updates arriving out of order or twice are harmless.
+    pub async fn import_event(&self, event: Event) -> anyhow::Result<Option<Event>> {
+        match self.store.event(&event.id)? {
+            Some(stored) if stored.sequence > event.sequence => {
+                tracing::info!(
+                    "ignoring stale update of `{}` (sequence {} < {})",
+                    event.id,
+                    event.sequence,
+                    stored.sequence
+                );
+                Ok(None)
+            }
+            Some(_) => Ok(Some(self.update_event(&event.id.clone(), event).await?)),
+            None => Ok(Some(self.create_event(event).await?)),
+        }
+    }
+
+    /// Cancel the event with this UID, or only its occurrence originally starting at
+    /// `recurrence_id`. The event stays around with a `CANCELLED` status so that a stale
+    /// request can't bring it back. Returns `None` if there was nothing (current) to
+    /// cancel.
+    pub async fn cancel_event(
+        &self,
+        uid: &str,
+        recurrence_id: Option<EventTime>,
+        sequence: u32,
+    ) -> anyhow::Result<Option<Event>> {
+        let event = match recurrence_id {
+            None => self.store.event(uid)?,
+            Some(original) => {
+                match self
+                    .store
+                    .event(&recurrence::instance_id(uid, &original.instant()))?
+                {
+                    Some(event) => Some(event),
+                    // An occurrence that wasn't changed on its own is split off the series
+                    None => self
+                        .store
+                        .event(uid)?
+                        .map(|master| recurrence::occurrence_at(&master, original)),
+                }
+            }
+        };
+        match event {
+            Some(event) => {
+                self.import_event(Event {
+                    status: Some("CANCELLED".to_string()),
+                    sequence,
+                    ..event
+                })
+                .await
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Record what `attendee` said about coming to the event `id`, returning the event
+    /// as it is now, or `None` if there is no such event.
+    pub async fn rsvp(&self, id: &str, attendee: &Attendee) -> anyhow::Result<Option<Event>> {
+        if self.store.event(id)?.is_some() {
+            self.store.set_rsvp(id, attendee)?;
+            return self.store.event(id);
+        }
+        // An occurrence that is only stored as its series keeps its RSVPs with the series
+        let Some(occurrence) = self.event(id).await? else {
+            return Ok(None);
+        };
+        let (Some(series_id), Some(original)) =
+            (&occurrence.recurring_event_id, occurrence.original_start)
+        else {
+            return Ok(None);
+        };
+        self.store
+            .set_occurrence_rsvp(series_id, &original.instant(), attendee)?;
+        self.event(id).await
+    }
+
+    pub async fn delete_event(&self, id: &str) -> anyhow::Result<()> {
+        self.backend.delete_event(id).await?;
+        self.store.delete_event(id)
+    }
+
+    /// Pull changes from the backend into the store.
+    pub async fn sync(&self) -> anyhow::Result<()> {
+        let sync_token = self.store.sync_token()?;
+        let changes = self.backend.changes(sync_token.as_deref()).await?;
+        tracing::info!(
+            "synced {} changed and {} deleted events (full: {})",
+            changes.events.len(),
+            changes.deleted.len(),
+            changes.full
+        );
+        self.store.apply_changes(&changes)
+    }
+}
+
+/// Run [`Calendar::sync`] every `interval`, logging failures.
+pub async fn sync_periodically(calendar: Arc<Calendar>, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately, and startup already synced
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if let Err(e) = calendar.sync().await {
+            tracing::error!("calendar sync failed: {e:?}");
+        }
+    }
+}
+
+#[test]
+fn test_visibility() {
+    let event = Event {
+        id: "triage@example.org".to_string(),
+        summary: "Triage".to_string(),
+        description: Some("Secret plans".to_string()),
+        creator_email: "niko@example.org".to_string(),
+        start: EventTime::parse("2024-03-06T16:00:00Z", None).unwrap(),
+        end: EventTime::parse("2024-03-06T17:00:00Z", None).unwrap(),
+        teams: vec!["lang".to_string()],
+        attendees: vec![Attendee {
+            email: "jane@example.org".to_string(),
+            name: None,
+            status: "ACCEPTED".to_string(),
+            role: None,
+        }],
+        visibility: Visibility::Private,
+        ..Event::default()
+    };
+    let public = Event {
+        visibility: Visibility::Public,
+        ..event.clone()
+    };
+    let viewer = |email: Option<&str>, teams: &[&str]| Viewer {
+        email: email.map(str::to_string),
+        teams: teams.iter().map(|team| team.to_string()).collect(),
+    };
+    let anonymous = Viewer::default();
+    let outsider = viewer(Some("amy@example.org"), &["compiler"]);
+    let member = viewer(Some("tmandry@example.org"), &["compiler", "lang"]);
+    let organizer = viewer(Some("Niko@example.org"), &[]);
+    let attendee = viewer(Some("jane@example.org"), &[]);
+
+    for viewer in [&anonymous, &outsider, &member, &organizer, &attendee] {
+        assert!(public.visible_to(viewer), "{viewer:?}");
+    }
+    assert!(!event.visible_to(&anonymous));
+    assert!(!event.visible_to(&outsider));
+    assert!(event.visible_to(&member));
+    assert!(event.visible_to(&organizer));
+    assert!(event.visible_to(&attendee));
+
+    let mut events = Events {
+        events: vec![event.clone(), public],
+        next_cursor: None,
+    };
+    events.redact_for(&anonymous);
+    // Only logged in users see who is coming
+    assert!(events.events[1].attendees.is_empty());
+    expect_test::expect![[r#"
+        {
+          "id": "triage@example.org",
+          "summary": "Busy",
+          "description": null,
+          "location": null,
+          "url": null,
+          "creatorEmail": "",
+          "creatorName": "",
+          "start": {
+            "dateTime": "2024-03-06T16:00:00Z",
+            "timeZone": "UTC"
+          },
+          "end": {
+            "dateTime": "2024-03-06T17:00:00Z",
+            "timeZone": "UTC"
+          },
+          "recurrence": null,
+          "recurringEventId": null,
+          "originalStart": null,
+          "status": null,
+          "teams": [],
+          "sequence": 0,
+          "attendees": [],
+          "visibility": "private",
+          "updated": null,
+          "redacted": true
+        }"#]]
+    .assert_eq(&serde_json::to_string_pretty(&events.events[0]).unwrap());
+    assert_eq!(events.events[1].summary, "Triage");
+
+    let mut events = Events {
+        events: vec![event],
+        next_cursor: None,
+    };
+    events.redact_for(&member);
+    assert!(!events.events[0].redacted);
+    assert_eq!(events.events[0].attendees.len(), 1);
+}
+
+#[tokio::test]
+async fn test_event_by_id() {
+    let store = Arc::new(Store::in_memory().unwrap());
+    let backend = Box::new(ics_directory::IcsDirectory::new(std::env::temp_dir()));
+    let calendar = Calendar::new(backend, store.clone());
+    let master = Event {
+        id: "triage@example.org".to_string(),
+        summary: "Triage".to_string(),
+        start: EventTime::parse("2024-03-06T11:00:00", Some("America/New_York")).unwrap(),
+        end: EventTime::parse("2024-03-06T12:00:00", Some("America/New_York")).unwrap(),
+        recurrence: Recurrence::from_lines([
+            "RRULE:FREQ=WEEKLY;BYDAY=WE",
+            "EXDATE:20240313T150000Z",
+        ]),
+        ..Event::default()
+    };
+    store.upsert_event(&master).unwrap();
+
+    let stored = calendar.event(&master.id).await.unwrap().unwrap();
+    assert_eq!(stored.recurring_event_id, None);
+    // Occurrences aren't stored, but have ids of their own
+    let occurrence = calendar
+        .event("triage@example.org_20240320T150000Z")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(occurrence.start.to_string(), "2024-03-20T11:00:00-04:00");
+    assert_eq!(
+        occurrence.recurring_event_id.as_deref(),
+        Some("triage@example.org")
+    );
+    assert_eq!(occurrence.recurrence, master.recurrence);
+    // Not when the series doesn't have one then
+    for id in [
+        "triage@example.org_20240313T150000Z",
+        "triage@example.org_20240320T160000Z",
+        "standup_20240320T150000Z",
+    ] {
+        assert!(calendar.event(id).await.unwrap().is_none(), "{id}");
+    }
+
+    // Saying you're coming to one occurrence is only about that one
+    let jane = Attendee {
+        email: "jane@example.org".to_string(),
+        name: None,
+        status: "ACCEPTED".to_string(),
+        role: None,
+    };
+    let rsvped = calendar
+        .rsvp("triage@example.org_20240320T150000Z", &jane)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(rsvped.attendees[0].status, "ACCEPTED");
+    let query = EventQuery {
+        from: Some("2024-03-18T00:00:00Z".parse().unwrap()),
+        to: Some("2024-03-30T00:00:00Z".parse().unwrap()),
+        ..EventQuery::default()
+    };
+    let attendees: Vec<usize> = calendar
+        .events(&query)
+        .await
+        .unwrap()
+        .events
+        .iter()
+        .map(|event| event.attendees.len())
+        .collect();
+    assert_eq!(attendees, [1, 0]);
+    assert!(calendar
+        .rsvp("triage@example.org_20240313T150000Z", &jane)
+        .await
+        .unwrap()
+        .is_none());
 }
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::calendar::Event;
+use crate::config::{BackendConfig, Configuration};
+
+use super::caldav::CalDavCalendar;
+use super::google_calendar::GoogleCalendar;
+use super::ics_directory::IcsDirectory;
+
+/// A place where calendar events are stored.
+///
+/// Eventageous doesn't care which calendar software holds the events, as long as
+/// it can list them and (ideally) write them back. Each provider translates its
+/// own representation into the Eventageous [`Event`] model.
+///
+/// Event ids are opaque to callers and only need to be stable for a given backend.
+/// Recurring series are returned unexpanded: one event carrying the recurrence, plus
+/// one event per occurrence that was changed or cancelled on its own.
+#[async_trait]
+pub trait CalendarBackend: Send + Sync {
+    /// List the events between `from` and `to`, where `None` leaves that side open.
+    /// Recurring series are listed if they started before `to`, whether or not they
+    /// have occurrences in the window.
+    async fn list_events(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<Event>>;
+
+    /// Fetch a single event by id, returning `None` if the backend doesn't know it.
+    async fn get_event(&self, id: &str) -> anyhow::Result<Option<Event>>;
+
+    /// Store a new event, returning it as the backend saw it (e.g. with its assigned id).
+    async fn create_event(&self, event: Event) -> anyhow::Result<Event>;
+
+    /// Replace the event with the given id.
+    async fn update_event(&self, id: &str, event: Event) -> anyhow::Result<Event>;
+
+    /// Remove the event with the given id.
+    async fn delete_event(&self, id: &str) -> anyhow::Result<()>;
+
+    /// Whether the backend refuses all writes, so that nothing can be added to it.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Fetch what changed since the sync that returned `sync_token`.
+    ///
+    /// Backends without incremental sync return everything they have, marked `full`.
+    async fn changes(&self, sync_token: Option<&str>) -> anyhow::Result<Changes> {
+        if sync_token.is_some() {
+            tracing::info!("backend has no incremental sync, fetching everything");
+        }
+        Ok(Changes {
+            events: self.list_events(None, None).await?,
+            deleted: vec![],
+            full: true,
+            sync_token: None,
+        })
+    }
+}
+
+/// A batch of changes pulled from a backend.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Events that are new or were modified.
+    pub events: Vec<Event>,
+    /// Ids of events that were removed.
+    pub deleted: Vec<String>,
+    /// Whether `events` is the complete set, so that anything missing from it is gone.
+    pub full: bool,
+    /// Token for fetching the next batch of changes, if the backend supports it.
+    pub sync_token: Option<String>,
+}
+
+/// Create the backend selected in the configuration.
+pub fn from_config(config: &Arc<Configuration>) -> anyhow::Result<Box<dyn CalendarBackend>> {
+    Ok(match &config.backend {
+        BackendConfig::Google => Box::new(GoogleCalendar::from(config)),
+        BackendConfig::CalDav {
+            url,
+            username,
+            password,
+        } => Box::new(CalDavCalendar::new(
+            url,
+            username.clone(),
+            password.clone(),
+        )?),
+        BackendConfig::IcsDirectory { path } => Box::new(IcsDirectory::new(path)),
+    })
+}
@@ -0,0 +1,389 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::header::{CONTENT_TYPE, IF_MATCH, IF_NONE_MATCH};
+use reqwest::{Method, RequestBuilder, StatusCode, Url};
+
+use crate::calendar::Event;
+
+use super::backend::CalendarBackend;
+use super::ical::{event_to_ics, events_from_ics, format_utc, replace_in_ics};
+use super::recurrence;
+
+const DAV_NS: &str = "DAV:";
+const CALDAV_NS: &str = "urn:ietf:params:xml:ns:caldav";
+
+/// A calendar collection on a CalDAV server (RFC 4791), e.g. Nextcloud or Radicale.
+///
+/// Events are stored one per resource, named after their UID, which also holds the
+/// changed occurrences of a series. Writes use the resource's ETag so that we never
+/// clobber a change made by another client.
+pub struct CalDavCalendar {
+    client: reqwest::Client,
+    url: Url,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// A calendar object resource returned by the server.
+#[derive(Debug, PartialEq)]
+struct Resource {
+    href: String,
+    etag: Option<String>,
+    calendar_data: String,
+}
+
+impl CalDavCalendar {
+    pub fn new(
+        url: &str,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> anyhow::Result<Self> {
+        // Resource names are resolved relative to the collection, so it must end in a slash
+        let mut url = Url::parse(url)?;
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url,
+            username,
+            password,
+        })
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.username {
+            Some(username) => request.basic_auth(username, self.password.as_ref()),
+            None => request,
+        }
+    }
+
+    /// Run a `calendar-query` REPORT with the given VEVENT filter.
+    async fn query(&self, event_filter: &str) -> anyhow::Result<Vec<Resource>> {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<C:calendar-query xmlns:D="{DAV_NS}" xmlns:C="{CALDAV_NS}">
+  <D:prop>
+    <D:getetag/>
+    <C:calendar-data/>
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        {event_filter}
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#
+        );
+
+        let response = self
+            .request(Method::from_bytes(b"REPORT")?, self.url.clone())
+            .header("Depth", "1")
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(body)
+            .send()
+            .await?;
+
+        if response.status() != StatusCode::MULTI_STATUS {
+            anyhow::bail!("calendar query failed: {response:?}");
+        }
+
+        parse_multistatus(&response.text().await?)
+    }
+
+    async fn find(&self, uid: &str) -> anyhow::Result<Option<Resource>> {
+        // `text-match` looks for substrings unless told otherwise, and servers that don't
+        // know `match-type` may still do so
+        let filter = format!(
+            r#"<C:prop-filter name="UID"><C:text-match collation="i;octet" match-type="equals">{}</C:text-match></C:prop-filter>"#,
+            escape_xml(uid)
+        );
+        Ok(resource_with_uid(self.query(&filter).await?, uid))
+    }
+
+    /// The resource holding the event `id`, which for an occurrence of a series is the
+    /// one of the series.
+    async fn find_event(&self, id: &str) -> anyhow::Result<Option<Resource>> {
+        if let Some(resource) = self.find(id).await? {
+            return Ok(Some(resource));
+        }
+        match recurrence::parse_instance_id(id) {
+            Some((series_id, _)) => self.find(series_id).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn put(
+        &self,
+        url: Url,
+        id: &str,
+        calendar_data: String,
+        etag: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let request = self
+            .request(Method::PUT, url)
+            .header(CONTENT_TYPE, "text/calendar; charset=utf-8")
+            .body(calendar_data);
+        let request = match etag {
+            Some(etag) => request.header(IF_MATCH, etag),
+            None => request.header(IF_NONE_MATCH, "*"),
+        };
+
+        let response = request.send().await?;
+        match response.status() {
+            StatusCode::PRECONDITION_FAILED => {
+                anyhow::bail!("event `{id}` was modified on the server")
+            }
+            status if status.is_success() => Ok(()),
+            _ => anyhow::bail!("storing event failed: {response:?}"),
+        }
+    }
+}
+
+#[async_trait]
+impl CalendarBackend for CalDavCalendar {
+    async fn list_events(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<Event>> {
+        // The server expands recurring events to check them against the range
+        let filter = match (from, to) {
+            (None, None) => String::new(),
+            (from, to) => {
+                let attribute = |name: &str, time: Option<DateTime<Utc>>| match time {
+                    Some(time) => format!(r#" {name}="{}""#, format_utc(&time)),
+                    None => String::new(),
+                };
+                format!(
+                    "<C:time-range{}{}/>",
+                    attribute("start", from),
+                    attribute("end", to)
+                )
+            }
+        };
+
+        let mut events = vec![];
+        for resource in self.query(&filter).await? {
+            match events_from_ics(&resource.calendar_data) {
+                Ok(parsed) => events.extend(parsed),
+                Err(e) => tracing::error!("skipping unparseable resource {}: {e}", resource.href),
+            }
+        }
+        events.sort_by_key(|event| event.start.instant());
+        Ok(events)
+    }
+
+    async fn get_event(&self, id: &str) -> anyhow::Result<Option<Event>> {
+        let Some(resource) = self.find_event(id).await? else {
+            return Ok(None);
+        };
+        let events = events_from_ics(&resource.calendar_data)?;
+        Ok(events.into_iter().find(|event| event.id == id))
+    }
+
+    async fn create_event(&self, mut event: Event) -> anyhow::Result<Event> {
+        if event.id.is_empty() {
+            event.id = format!("{}@eventageous", uuid::Uuid::new_v4());
+        }
+        // A changed occurrence shares the UID of its series, so it goes into its resource
+        if let Some(series_id) = &event.recurring_event_id {
+            let Some(resource) = self.find(series_id).await? else {
+                anyhow::bail!("no event with id `{series_id}`");
+            };
+            let calendar_data = replace_in_ics(&resource.calendar_data, &event.id, Some(&event))?;
+            let url = self.url.join(&resource.href)?;
+            let etag = resource.etag.as_deref().unwrap_or("*");
+            self.put(url, &event.id, calendar_data, Some(etag)).await?;
+            return Ok(event);
+        }
+        let url = self
+            .url
+            .join(&format!("{}.ics", urlencoding::encode(&event.id)))?;
+        self.put(url, &event.id, event_to_ics(&event)?, None)
+            .await?;
+        Ok(event)
+    }
+
+    async fn update_event(&self, id: &str, mut event: Event) -> anyhow::Result<Event> {
+        let Some(resource) = self.find_event(id).await? else {
+            anyhow::bail!("no event with id `{id}`");
+        };
+        event.id = id.to_string();
+        // Only replace this event, not the changed occurrences stored along with it
+        let calendar_data = replace_in_ics(&resource.calendar_data, id, Some(&event))?;
+        let url = self.url.join(&resource.href)?;
+        // Servers are required to return an ETag, but fall back to an unconditional write
+        let etag = resource.etag.as_deref().unwrap_or("*");
+        self.put(url, id, calendar_data, Some(etag)).await?;
+        Ok(event)
+    }
+
+    async fn delete_event(&self, id: &str) -> anyhow::Result<()> {
+        let Some(resource) = self.find_event(id).await? else {
+            anyhow::bail!("no event with id `{id}`");
+        };
+        let url = self.url.join(&resource.href)?;
+        // Removing a changed occurrence leaves the rest of its series
+        let calendar_data = replace_in_ics(&resource.calendar_data, id, None)?;
+        if !events_from_ics(&calendar_data)?.is_empty() {
+            let etag = resource.etag.as_deref().unwrap_or("*");
+            return self.put(url, id, calendar_data, Some(etag)).await;
+        }
+        let mut request = self.request(Method::DELETE, url);
+        if let Some(etag) = &resource.etag {
+            request = request.header(IF_MATCH, etag);
+        }
+
+        let response = request.send().await?;
+        match response.status() {
+            StatusCode::PRECONDITION_FAILED => {
+                anyhow::bail!("event `{id}` was modified on the server")
+            }
+            status if status.is_success() => Ok(()),
+            _ => anyhow::bail!("deleting event failed: {response:?}"),
+        }
+    }
+}
+
+/// Pull the calendar object resources out of a `207 Multi-Status` response body.
+fn parse_multistatus(body: &str) -> anyhow::Result<Vec<Resource>> {
+    let document = roxmltree::Document::parse(body)?;
+    let mut resources = vec![];
+
+    for response in document
+        .descendants()
+        .filter(|node| node.has_tag_name((DAV_NS, "response")))
+    {
+        let find_text = |ns: &str, name: &str| {
+            response
+                .descendants()
+                .find(|node| node.has_tag_name((ns, name)))
+                .and_then(|node| node.text())
+                .map(|text| text.to_string())
+        };
+
+        let Some(href) = find_text(DAV_NS, "href") else {
+            continue;
+        };
+        // Resources without calendar data are e.g. the collection itself
+        let Some(calendar_data) = find_text(CALDAV_NS, "calendar-data") else {
+            continue;
+        };
+        resources.push(Resource {
+            href,
+            etag: find_text(DAV_NS, "getetag"),
+            calendar_data,
+        });
+    }
+
+    Ok(resources)
+}
+
+/// The resource among `resources` that holds the event with `uid`, if any.
+fn resource_with_uid(resources: Vec<Resource>, uid: &str) -> Option<Resource> {
+    resources.into_iter().find(|resource| {
+        events_from_ics(&resource.calendar_data).is_ok_and(|events| {
+            events
+                .iter()
+                .any(|event| event.recurring_event_id.as_ref().unwrap_or(&event.id) == uid)
+        })
+    })
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[test]
+fn test_parse_multistatus() {
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<multistatus xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <response>
+    <href>/user/calendar/</href>
+    <propstat><prop><getetag>"collection"</getetag></prop><status>HTTP/1.1 200 OK</status></propstat>
+  </response>
+  <response>
+    <href>/user/calendar/triage.ics</href>
+    <propstat>
+      <prop>
+        <getetag>"abc123"</getetag>
+        <C:calendar-data>BEGIN:VCALENDAR
+END:VCALENDAR</C:calendar-data>
+      </prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+</multistatus>"#;
+
+    assert_eq!(
+        parse_multistatus(body).unwrap(),
+        vec![Resource {
+            href: "/user/calendar/triage.ics".to_string(),
+            etag: Some("\"abc123\"".to_string()),
+            calendar_data: "BEGIN:VCALENDAR\nEND:VCALENDAR".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn test_resource_with_uid() {
+    let resource = |href: &str, uid: &str| Resource {
+        href: href.to_string(),
+        etag: None,
+        calendar_data: format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:test\r\nBEGIN:VEVENT\r\nUID:{uid}\r\n\
+             DTSTAMP:20240301T000000Z\r\nDTSTART:20240306T160000Z\r\n\
+             DTEND:20240306T170000Z\r\nSUMMARY:Triage\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n"
+        ),
+    };
+    // A server that matched a substring of the UID
+    let resources = || vec![resource("abc-2.ics", "abc-2"), resource("abc.ics", "abc")];
+    assert_eq!(
+        resource_with_uid(resources(), "abc").unwrap().href,
+        "abc.ics"
+    );
+    assert_eq!(resource_with_uid(resources(), "ab"), None);
+}
+
+// Run a local Radicale (`python -m radicale --storage-filesystem-folder=/tmp/radicale
+// --auth-type=none`), create a calendar collection, and point `CALDAV_TEST_URL` at it.
+#[tokio::test]
+#[ignore = "needs a CalDAV server, see CALDAV_TEST_URL"]
+async fn test_caldav_round_trip() {
+    let url = std::env::var("CALDAV_TEST_URL").unwrap();
+    let calendar = CalDavCalendar::new(&url, None, None).unwrap();
+
+    let start = Utc::now() + chrono::Duration::days(1);
+    let end = start + chrono::Duration::hours(1);
+    let event = Event {
+        id: String::new(),
+        summary: "CalDAV test".to_string(),
+        description: None,
+        location: None,
+        creator_email: "test@example.org".to_string(),
+        creator_name: "Test".to_string(),
+        start: super::EventTime::Zoned(start.with_timezone(&chrono_tz::UTC)),
+        end: super::EventTime::Zoned(end.with_timezone(&chrono_tz::UTC)),
+        ..Event::default()
+    };
+
+    let created = calendar.create_event(event).await.unwrap();
+    let listed = calendar.list_events(None, None).await.unwrap();
+    assert!(listed.iter().any(|e| e.id == created.id));
+
+    let mut changed = calendar.get_event(&created.id).await.unwrap().unwrap();
+    changed.summary = "CalDAV test (updated)".to_string();
+    calendar.update_event(&created.id, changed).await.unwrap();
+    let fetched = calendar.get_event(&created.id).await.unwrap().unwrap();
+    assert_eq!(fetched.summary, "CalDAV test (updated)");
+
+    calendar.delete_event(&created.id).await.unwrap();
+    assert!(calendar.get_event(&created.id).await.unwrap().is_none());
+}
@@ -0,0 +1,312 @@
+//! When events happen: instants in an IANA time zone, floating local times and dates.
+
+use std::fmt;
+
+use chrono::{
+    DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::calendar::EventKind;
+
+/// The start or end of an event.
+///
+/// In JSON this has the shape of Google's `EventDateTime`: `{"dateTime": "...", "timeZone":
+/// "..."}` with an RFC 3339 `dateTime` for timed events, just `{"dateTime": "..."}` without an
+/// offset for floating ones, and `{"date": "2024-03-13"}` for all-day events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "EventDate", into = "EventDate")]
+pub enum EventTime {
+    /// A fixed instant, shown in the zone it was given in.
+    Zoned(DateTime<Tz>),
+    /// A wall-clock time that is the same in every zone.
+    Floating(NaiveDateTime),
+    /// A whole day.
+    Date(NaiveDate),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventDate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date_time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time_zone: Option<String>,
+}
+
+impl Default for EventTime {
+    fn default() -> Self {
+        EventTime::Zoned(Tz::UTC.timestamp_opt(0, 0).unwrap())
+    }
+}
+
+impl TryFrom<EventDate> for EventTime {
+    type Error = anyhow::Error;
+
+    fn try_from(date: EventDate) -> anyhow::Result<Self> {
+        match (date.date, date.date_time) {
+            (_, Some(date_time)) => EventTime::parse(&date_time, date.time_zone.as_deref()),
+            (Some(date), None) => Ok(EventTime::Date(date)),
+            (None, None) => anyhow::bail!("expected a `date` or a `dateTime`"),
+        }
+    }
+}
+
+impl From<EventTime> for EventDate {
+    fn from(time: EventTime) -> Self {
+        match time {
+            EventTime::Date(date) => EventDate {
+                date: Some(date),
+                date_time: None,
+                time_zone: None,
+            },
+            time => EventDate {
+                date: None,
+                date_time: Some(time.to_string()),
+                time_zone: time.zone().map(|zone| zone.name().to_string()),
+            },
+        }
+    }
+}
+
+/// The RFC 3339 form for timed events, e.g. `2024-03-06T11:00:00-05:00` or
+/// `2024-03-13T16:00:00Z`, and the same without an offset for floating times.
+impl fmt::Display for EventTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventTime::Zoned(date_time) => {
+                let formatted = date_time.to_rfc3339_opts(SecondsFormat::Secs, true);
+                f.write_str(&formatted)
+            }
+            EventTime::Floating(local) => write!(f, "{}", local.format("%Y-%m-%dT%H:%M:%S")),
+            EventTime::Date(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+        }
+    }
+}
+
+impl EventTime {
+    /// Parse a date (`2024-03-13`), an RFC 3339 date-time, or a local date-time without
+    /// an offset, which is placed in `time_zone` if there is one and floating otherwise.
+    ///
+    /// An instant with an offset is shown in `time_zone`, or in UTC if there is none.
+    pub fn parse(date_time: &str, time_zone: Option<&str>) -> anyhow::Result<Self> {
+        let zone = match time_zone.filter(|name| !name.is_empty()) {
+            Some(name) => {
+                Some(zone(name).ok_or_else(|| anyhow::anyhow!("unknown time zone `{name}`"))?)
+            }
+            None => None,
+        };
+
+        if let Ok(date) = NaiveDate::parse_from_str(date_time, "%Y-%m-%d") {
+            return Ok(EventTime::Date(date));
+        }
+        if let Ok(instant) = DateTime::parse_from_rfc3339(date_time) {
+            let zone = zone.unwrap_or(Tz::UTC);
+            return Ok(EventTime::Zoned(instant.with_timezone(&zone)));
+        }
+        let local = NaiveDateTime::parse_from_str(date_time, "%Y-%m-%dT%H:%M:%S")
+            .map_err(|e| anyhow::anyhow!("malformed date-time `{date_time}`: {e}"))?;
+        Ok(match zone {
+            Some(zone) => EventTime::Zoned(in_zone(local, zone)),
+            None => EventTime::Floating(local),
+        })
+    }
+
+    pub fn kind(&self) -> EventKind {
+        match self {
+            EventTime::Zoned(_) => EventKind::Timed,
+            EventTime::Floating(_) => EventKind::Floating,
+            EventTime::Date(_) => EventKind::AllDay,
+        }
+    }
+
+    /// The zone of a timed event.
+    pub fn zone(&self) -> Option<Tz> {
+        match self {
+            EventTime::Zoned(date_time) => Some(date_time.timezone()),
+            _ => None,
+        }
+    }
+
+    /// The instant this refers to, for ordering and time windows. Floating times are
+    /// taken as UTC, and dates as midnight UTC.
+    pub fn instant(&self) -> DateTime<Utc> {
+        match self {
+            EventTime::Zoned(date_time) => date_time.with_timezone(&Utc),
+            EventTime::Floating(local) => local.and_utc(),
+            EventTime::Date(date) => date.and_time(Default::default()).and_utc(),
+        }
+    }
+
+    /// The wall-clock time, with dates at midnight.
+    pub fn local(&self) -> NaiveDateTime {
+        match self {
+            EventTime::Zoned(date_time) => date_time.naive_local(),
+            EventTime::Floating(local) => *local,
+            EventTime::Date(date) => date.and_time(Default::default()),
+        }
+    }
+
+    /// The same kind of time (and zone) at another wall-clock time.
+    pub fn with_local(&self, local: NaiveDateTime) -> Self {
+        match self {
+            EventTime::Zoned(date_time) => EventTime::Zoned(in_zone(local, date_time.timezone())),
+            EventTime::Floating(_) => EventTime::Floating(local),
+            EventTime::Date(_) => EventTime::Date(local.date()),
+        }
+    }
+
+    /// Move by `duration`; timed events move in real time, so across a DST change the
+    /// wall-clock time shifts.
+    pub fn shifted(&self, duration: Duration) -> Self {
+        match self {
+            EventTime::Zoned(date_time) => EventTime::Zoned(*date_time + duration),
+            EventTime::Floating(local) => EventTime::Floating(*local + duration),
+            EventTime::Date(date) => EventTime::Date(*date + Duration::days(duration.num_days())),
+        }
+    }
+}
+
+/// Place a wall-clock time in `zone`. Times that happen twice when the clocks go back
+/// take the first one, and times skipped when the clocks go forward are moved past the
+/// gap, like most calendar clients do.
+pub fn in_zone(local: NaiveDateTime, zone: Tz) -> DateTime<Tz> {
+    match zone.from_local_datetime(&local) {
+        LocalResult::Single(date_time) | LocalResult::Ambiguous(date_time, _) => date_time,
+        LocalResult::None => zone
+            .from_local_datetime(&(local + Duration::hours(1)))
+            .earliest()
+            .unwrap_or_else(|| zone.from_utc_datetime(&local)),
+    }
+}
+
+/// Find the IANA zone for a time zone name as calendars write them: IANA names, names
+/// behind a vendor prefix (`/mozilla.org/20050126_1/America/New_York`) and the Windows
+/// names used by Outlook and Exchange (`Eastern Standard Time`).
+pub fn zone(name: &str) -> Option<Tz> {
+    let name = name.trim().trim_matches('"');
+    if let Ok(zone) = name.parse() {
+        return Some(zone);
+    }
+    if let Some(zone) = WINDOWS_ZONES
+        .iter()
+        .find(|(windows, _)| windows.eq_ignore_ascii_case(name))
+        .and_then(|(_, iana)| iana.parse().ok())
+    {
+        return Some(zone);
+    }
+    // Try the last two or three path segments, e.g. `America/Argentina/Buenos_Aires`
+    let segments: Vec<&str> = name.split('/').collect();
+    (2..=3)
+        .rev()
+        .filter_map(|n| segments.len().checked_sub(n))
+        .find_map(|skip| segments[skip..].join("/").parse().ok())
+}
+
+/// A fixed offset from UTC as the `Etc/GMT` zone with that offset, if there is one. Note
+/// that the sign of those names is inverted: `Etc/GMT+5` is five hours behind UTC.
+pub fn fixed_offset_zone(seconds: i32) -> Option<Tz> {
+    if seconds % 3600 != 0 {
+        return None;
+    }
+    match -seconds / 3600 {
+        0 => Some(Tz::UTC),
+        hours => format!("Etc/GMT{hours:+}").parse().ok(),
+    }
+}
+
+/// The Windows zone names we are most likely to see, from CLDR's `windowsZones.xml`.
+const WINDOWS_ZONES: &[(&str, &str)] = &[
+    ("Dateline Standard Time", "Etc/GMT+12"),
+    ("Hawaiian Standard Time", "Pacific/Honolulu"),
+    ("Alaskan Standard Time", "America/Anchorage"),
+    ("Pacific Standard Time", "America/Los_Angeles"),
+    ("Mountain Standard Time", "America/Denver"),
+    ("US Mountain Standard Time", "America/Phoenix"),
+    ("Central Standard Time", "America/Chicago"),
+    ("Eastern Standard Time", "America/New_York"),
+    ("Atlantic Standard Time", "America/Halifax"),
+    ("E. South America Standard Time", "America/Sao_Paulo"),
+    ("UTC", "Etc/UTC"),
+    ("GMT Standard Time", "Europe/London"),
+    ("Greenwich Standard Time", "Atlantic/Reykjavik"),
+    ("W. Europe Standard Time", "Europe/Berlin"),
+    ("Central Europe Standard Time", "Europe/Budapest"),
+    ("Romance Standard Time", "Europe/Paris"),
+    ("Central European Standard Time", "Europe/Warsaw"),
+    ("E. Europe Standard Time", "Europe/Chisinau"),
+    ("FLE Standard Time", "Europe/Kiev"),
+    ("GTB Standard Time", "Europe/Bucharest"),
+    ("Russian Standard Time", "Europe/Moscow"),
+    ("Israel Standard Time", "Asia/Jerusalem"),
+    ("India Standard Time", "Asia/Kolkata"),
+    ("China Standard Time", "Asia/Shanghai"),
+    ("Singapore Standard Time", "Asia/Singapore"),
+    ("Tokyo Standard Time", "Asia/Tokyo"),
+    ("Korea Standard Time", "Asia/Seoul"),
+    ("AUS Eastern Standard Time", "Australia/Sydney"),
+    ("New Zealand Standard Time", "Pacific/Auckland"),
+];
+
+#[test]
+fn test_parse_and_serialize() {
+    let times = [
+        EventTime::parse("2024-03-13T16:00:00Z", None).unwrap(),
+        EventTime::parse("2024-03-06T11:00:00", Some("America/New_York")).unwrap(),
+        EventTime::parse("2024-03-06T16:00:00Z", Some("Europe/Berlin")).unwrap(),
+        EventTime::parse("2024-03-06T09:00:00", None).unwrap(),
+        EventTime::parse("2024-03-06", None).unwrap(),
+        // Clocks went forward at 2am
+        EventTime::parse("2024-03-10T02:30:00", Some("America/New_York")).unwrap(),
+    ];
+    expect_test::expect![[r#"
+        [
+            "{\"dateTime\":\"2024-03-13T16:00:00Z\",\"timeZone\":\"UTC\"}",
+            "{\"dateTime\":\"2024-03-06T11:00:00-05:00\",\"timeZone\":\"America/New_York\"}",
+            "{\"dateTime\":\"2024-03-06T17:00:00+01:00\",\"timeZone\":\"Europe/Berlin\"}",
+            "{\"dateTime\":\"2024-03-06T09:00:00\"}",
+            "{\"date\":\"2024-03-06\"}",
+            "{\"dateTime\":\"2024-03-10T03:30:00-04:00\",\"timeZone\":\"America/New_York\"}",
+        ]
+    "#]]
+    .assert_debug_eq(&times.map(|time| serde_json::to_string(&time).unwrap()));
+
+    for time in times {
+        let json = serde_json::to_string(&time).unwrap();
+        assert_eq!(serde_json::from_str::<EventTime>(&json).unwrap(), time);
+    }
+}
+
+#[test]
+fn test_zone_names() {
+    let zones = [
+        "Europe/Berlin",
+        "/mozilla.org/20050126_1/America/New_York",
+        "/citadel.org/20190103_1/America/Argentina/Buenos_Aires",
+        "Eastern Standard Time",
+        "Not a zone",
+    ]
+    .map(|name| zone(name).map(|zone| zone.name()));
+    expect_test::expect![[r#"
+        [
+            Some(
+                "Europe/Berlin",
+            ),
+            Some(
+                "America/New_York",
+            ),
+            Some(
+                "America/Argentina/Buenos_Aires",
+            ),
+            Some(
+                "America/New_York",
+            ),
+            None,
+        ]
+    "#]]
+    .assert_debug_eq(&zones);
+    assert_eq!(fixed_offset_zone(-5 * 3600).unwrap().name(), "Etc/GMT+5");
+}
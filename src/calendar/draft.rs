@@ -0,0 +1,227 @@
+//! What the events API takes to create or change an event, the checks the result has
+//! to pass before it goes to the backend, and who may make changes.
+
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::calendar::recurrence::RecurrenceRule;
+use crate::calendar::{teams, Event, EventTime, Recurrence, Team, Visibility};
+use crate::roster::TeamRoster;
+
+/// Longer summaries don't fit anywhere they are shown.
+pub const MAX_SUMMARY_LENGTH: usize = 200;
+
+/// The fields of an event that people set. Creating an event needs at least a summary,
+/// start and end; changing one only needs what changes.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EventDraft {
+    pub summary: Option<String>,
+    /// An empty string removes the description; likewise for `location` and `url`.
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub url: Option<String>,
+    pub start: Option<EventTime>,
+    pub end: Option<EventTime>,
+    /// iCalendar lines, e.g. `["RRULE:FREQ=WEEKLY;BYDAY=WE"]`; an empty list makes the
+    /// event a one-time one.
+    pub recurrence: Option<Vec<String>>,
+    pub teams: Option<Vec<String>>,
+    pub visibility: Option<Visibility>,
+}
+
+impl EventDraft {
+    /// Fill in `event` with what the draft sets, and check that the result makes
+    /// sense. Teams have to be `known`. Returns everything that is wrong, if anything.
+    pub fn apply(self, event: &mut Event, known: &[Team]) -> Result<(), Vec<String>> {
+        let mut problems = vec![];
+        let non_empty = |text: String| Some(text.trim().to_string()).filter(|t| !t.is_empty());
+
+        if let Some(summary) = self.summary {
+            event.summary = summary.trim().to_string();
+        }
+        if let Some(description) = self.description {
+            event.description = non_empty(description);
+        }
+        if let Some(location) = self.location {
+            event.location = non_empty(location);
+        }
+        if let Some(url) = self.url {
+            event.url = non_empty(url);
+        }
+        if let Some(start) = self.start {
+            event.start = start;
+        }
+        if let Some(end) = self.end {
+            event.end = end;
+        }
+        if let Some(lines) = self.recurrence {
+            event.recurrence = recurrence(&lines, &mut problems);
+        }
+        if let Some(tags) = self.teams {
+            event.teams.clear();
+            for tag in tags {
+                match teams::normalize(&tag) {
+                    Some(team) if !known.iter().any(|known| known.name == team) => {
+                        problems.push(format!("unknown team `{team}`"))
+                    }
+                    Some(team) if !event.teams.contains(&team) => event.teams.push(team),
+                    Some(_) => {}
+                    None => problems.push(format!("`{tag}` is not a team name")),
+                }
+            }
+        }
+        if let Some(visibility) = self.visibility {
+            event.visibility = visibility;
+        }
+
+        // Whatever changed, the event as a whole has to make sense
+        if event.summary.is_empty() {
+            problems.push("the summary is empty".to_string());
+        }
+        if event.summary.chars().count() > MAX_SUMMARY_LENGTH {
+            problems.push(format!(
+                "the summary is longer than {MAX_SUMMARY_LENGTH} characters"
+            ));
+        }
+        if event.start.kind() != event.end.kind() {
+            problems.push("the start and end have to be both dates or both times".to_string());
+        } else if event.end.instant() <= event.start.instant() {
+            problems.push("the event has to end after it starts".to_string());
+        }
+        if let Some(url) = &event.url {
+            let valid =
+                reqwest::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+            if !valid {
+                problems.push(format!("`{url}` is not a web address"));
+            }
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(problems),
+        }
+    }
+}
+
+/// Whether the user with `email` and GitHub `login` may change or delete `event`: only
+/// its creator and the leads of its teams may.
+pub fn may_edit(
+    event: &Event,
+    email: Option<&str>,
+    login: Option<&str>,
+    roster: &TeamRoster,
+) -> bool {
+    let creator = email.is_some_and(|email| event.creator_email.eq_ignore_ascii_case(email));
+    let lead =
+        login.is_some_and(|login| event.teams.iter().any(|team| roster.is_lead(login, team)));
+    creator || lead
+}
+
+/// The recurrence that `lines` describe, noting any that we can't expand.
+fn recurrence(lines: &[String], problems: &mut Vec<String>) -> Option<Recurrence> {
+    if lines.is_empty() {
+        return None;
+    }
+    for line in lines {
+        let (name, value) = line.split_once(':').unwrap_or((line, ""));
+        let name = name.split(';').next().unwrap_or_default();
+        match name {
+            "RRULE" => {
+                if let Err(e) = RecurrenceRule::from_str(value) {
+                    problems.push(format!("unsupported rule `{value}`: {e}"));
+                }
+            }
+            "RDATE" | "EXDATE" => {}
+            _ => problems.push(format!("expected an RRULE, RDATE or EXDATE, not `{line}`")),
+        }
+    }
+    let recurrence = Recurrence::from_lines(lines.iter().map(String::as_str));
+    if recurrence.is_none() {
+        problems.push("a recurrence needs an RRULE or RDATE".to_string());
+    }
+    recurrence
+}
+
+#[test]
+fn test_apply() {
+    let known = [Team {
+        name: "lang".to_string(),
+        description: None,
+    }];
+    let draft: EventDraft = serde_json::from_str(
+        r#"{
+            "summary": " Lang triage ",
+            "start": {"dateTime": "2024-03-06T11:00:00", "timeZone": "America/New_York"},
+            "end": {"dateTime": "2024-03-06T12:00:00", "timeZone": "America/New_York"},
+            "recurrence": ["RRULE:FREQ=WEEKLY;BYDAY=WE"],
+            "teams": ["Lang", "lang"]
+        }"#,
+    )
+    .unwrap();
+    let mut event = Event::default();
+    draft.apply(&mut event, &known).unwrap();
+    assert_eq!(event.summary, "Lang triage");
+    assert_eq!(event.teams, ["lang"]);
+    assert_eq!(
+        event.recurrence.as_ref().unwrap().description,
+        "Weekly on Wednesday"
+    );
+
+    // A change only has to mention what changes, but the result is checked as a whole
+    let draft: EventDraft = serde_json::from_str(
+        r#"{
+            "end": {"date": "2024-03-07"},
+            "recurrence": ["RRULE:FREQ=HOURLY"],
+            "teams": ["compiler", "lang/types"],
+            "url": "javascript:alert(1)"
+        }"#,
+    )
+    .unwrap();
+    let problems = draft.apply(&mut event.clone(), &known).unwrap_err();
+    expect_test::expect![[r#"
+        [
+            "unsupported rule `FREQ=HOURLY`: unsupported frequency `HOURLY`",
+            "unknown team `compiler`",
+            "`lang/types` is not a team name",
+            "the start and end have to be both dates or both times",
+            "`javascript:alert(1)` is not a web address",
+        ]
+    "#]]
+    .assert_debug_eq(&problems);
+
+    let draft = EventDraft {
+        end: Some(EventTime::parse("2024-03-06T10:00:00-05:00", None).unwrap()),
+        recurrence: Some(vec![]),
+        ..EventDraft::default()
+    };
+    let problems = draft.apply(&mut event.clone(), &known).unwrap_err();
+    assert_eq!(problems, ["the event has to end after it starts"]);
+
+    // Unknown time zones don't get past parsing
+    let draft = serde_json::from_str::<EventDraft>(
+        r#"{"start": {"dateTime": "2024-03-06T11:00:00", "timeZone": "Mars/Olympus"}}"#,
+    );
+    assert!(draft.is_err());
+}
+
+#[test]
+fn test_may_edit() {
+    let roster = TeamRoster::load(std::path::Path::new("test_data/team")).unwrap();
+    let event = Event {
+        creator_email: "jane@example.org".to_string(),
+        teams: vec!["wg-async".to_string()],
+        ..Event::default()
+    };
+    assert!(may_edit(&event, Some("Jane@example.org"), None, &roster));
+    assert!(may_edit(&event, None, Some("tmandry"), &roster));
+    // Members that don't lead the team can't
+    assert!(!may_edit(
+        &event,
+        Some("niko@alum.mit.edu"),
+        Some("nikomatsakis"),
+        &roster
+    ));
+    assert!(!may_edit(&event, None, None, &roster));
+}
@@ -1,33 +1,63 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use chrono::{Datelike, Utc};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use urlencoding::encode;
 
+use crate::calendar;
 use crate::config::Configuration;
 
+use super::backend::{CalendarBackend, Changes};
+use super::transformer::{google_event_to_americano, google_to_americano, ValidationReport};
+
 pub struct GoogleCalendar {
     config: Arc<Configuration>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Events {
     pub summary: Option<String>,
     pub description: Option<String>,
     pub items: Vec<Event>,
+    pub next_page_token: Option<String>,
+    pub next_sync_token: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Event {
+    pub id: String,
+    // Deleted events in an incremental sync only carry their id and status
+    pub status: Option<String>,
+    #[serde(default)]
     pub summary: String,
     pub description: Option<String>,
     pub location: Option<String>,
+    #[serde(default)]
     pub creator: Creator,
     pub start: Option<EventDate>,
     pub end: Option<EventDate>,
+    /// `RRULE`, `RDATE` and `EXDATE` lines of a recurring event.
+    pub recurrence: Option<Vec<String>>,
     pub recurring_event_id: Option<String>,
     pub original_start_time: Option<EventDate>,
+    pub extended_properties: Option<ExtendedProperties>,
+    pub sequence: Option<u32>,
+    #[serde(default)]
+    pub attendees: Vec<Attendee>,
+    /// `default`, `public`, `private` or `confidential`.
+    pub visibility: Option<String>,
+}
+
+/// Key-value pairs that Google keeps for other applications. We keep an event's teams as
+/// a comma-separated `teams` in the shared ones.
+#[derive(Default, Debug, Deserialize, Serialize)]
+pub struct ExtendedProperties {
+    #[serde(default)]
+    pub shared: HashMap<String, String>,
 }
 
 #[derive(Default, Debug, Deserialize, Serialize)]
@@ -37,6 +67,19 @@ pub struct Creator {
     pub display_name: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attendee {
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    /// `needsAction`, `declined`, `tentative` or `accepted`.
+    pub response_status: Option<String>,
+    #[serde(default)]
+    pub optional: bool,
+    #[serde(default)]
+    pub organizer: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EventDate {
@@ -54,39 +97,191 @@ impl From<&Arc<Configuration>> for GoogleCalendar {
 }
 
 impl GoogleCalendar {
-    pub async fn events(&self) -> anyhow::Result<Events> {
-        // Limit the time range for a year for the moment to see future events for the next year,
-        // with a max result count of 500 for this result page
-        // TODO: better query and filtering
-        let start_date = Utc::now();
-        let start_date_formatted = start_date.to_rfc3339();
-        let time_min = encode(&start_date_formatted);
-
-        let end_date = start_date
-            .with_year(start_date.year() + 1)
-            .expect("Failed to add one year");
-        let end_date_formatted = end_date.to_rfc3339();
-        let time_max = encode(&end_date_formatted);
-
-        // TODO: singleEvents returns a bunch of isntances with the startDate of the original instance,
-        // needs to be updated to use originalStartDate for recurrences (has recurringEventId)
+    fn calendar_url(&self) -> String {
+        format!(
+            "https://www.googleapis.com/calendar/v3/calendars/{}/events",
+            encode(&self.config.google_calendar_id)
+        )
+    }
 
-        let endpoint = format!(
-            "https://www.googleapis.com/calendar/v3/calendars/{}/events?key={}&singleEvents=true&orderby=starttime&timeMin={}&timeMax={}&maxResults=500",
-            self.config.google_calendar_id, self.config.google_api_key, time_min, time_max
+    /// Fetch the events between `from` and `to`, following `nextPageToken` through all
+    /// pages of results.
+    pub async fn events(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<Event>> {
+        // Recurring events come back as the series plus any modified occurrences
+        // (with recurringEventId and originalStartTime), see `recurrence::expand`
+        let mut endpoint = format!(
+            "{}?key={}&singleEvents=false&maxResults=2500",
+            self.calendar_url(),
+            self.config.google_api_key,
         );
+        if let Some(from) = from {
+            endpoint.push_str(&format!("&timeMin={}", encode(&from.to_rfc3339())));
+        }
+        if let Some(to) = to {
+            endpoint.push_str(&format!("&timeMax={}", encode(&to.to_rfc3339())));
+        }
+
+        let mut events = vec![];
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut page_endpoint = endpoint.clone();
+            if let Some(page_token) = &page_token {
+                page_endpoint.push_str(&format!("&pageToken={}", encode(page_token)));
+            }
+
+            let response = reqwest::get(page_endpoint).await?;
+
+            if !response.status().is_success() {
+                anyhow::bail!("accesing calendar data failed: {response:?}");
+            }
 
-        //tracing::info!("{}", endpoint);
+            let json_body = response.text().await?;
+            let page: Events = serde_json::from_str(&json_body)?;
+            events.extend(page.items);
+            match page.next_page_token {
+                Some(next) => page_token = Some(next),
+                None => return Ok(events),
+            }
+        }
+    }
+
+    pub async fn event(&self, id: &str) -> anyhow::Result<Option<Event>> {
+        let endpoint = format!(
+            "{}/{}?key={}",
+            self.calendar_url(),
+            encode(id),
+            self.config.google_api_key
+        );
 
         let response = reqwest::get(endpoint).await?;
 
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
         if !response.status().is_success() {
-            //anyhow::bail!("accesing calendar data failed: {response:?}");
-            panic!("accesing calendar data failed: {response:?}");
+            anyhow::bail!("accesing calendar event failed: {response:?}");
         }
 
         let json_body = response.text().await?;
-        //tracing::info!("{}", json_body);
-        Ok(serde_json::from_str(&json_body)?)
+        Ok(Some(serde_json::from_str(&json_body)?))
+    }
+
+    /// Fetch all pages of changes since `sync_token`, or every event if there is none.
+    ///
+    /// Returns `None` if Google no longer accepts the token and a full sync is needed.
+    async fn sync(&self, sync_token: Option<&str>) -> anyhow::Result<Option<Changes>> {
+        let mut changes = Changes {
+            full: sync_token.is_none(),
+            ..Changes::default()
+        };
+        let mut page_token: Option<String> = None;
+        let mut report = ValidationReport::default();
+
+        loop {
+            // Google refuses sync tokens for queries with a time range or ordering,
+            // so this fetches the whole calendar
+            let mut endpoint = format!(
+                "{}?key={}&singleEvents=false&maxResults=2500",
+                self.calendar_url(),
+                self.config.google_api_key
+            );
+            if let Some(sync_token) = sync_token {
+                endpoint.push_str(&format!("&syncToken={}", encode(sync_token)));
+            }
+            if let Some(page_token) = &page_token {
+                endpoint.push_str(&format!("&pageToken={}", encode(page_token)));
+            }
+
+            let response = reqwest::get(endpoint).await?;
+            if response.status() == reqwest::StatusCode::GONE {
+                return Ok(None);
+            }
+            if !response.status().is_success() {
+                anyhow::bail!("syncing calendar data failed: {response:?}");
+            }
+
+            let json_body = response.text().await?;
+            let page: Events = serde_json::from_str(&json_body)?;
+            for g_event in &page.items {
+                // A cancelled occurrence of a series is kept, so that it hides the occurrence
+                if g_event.status.as_deref() == Some("cancelled")
+                    && g_event.recurring_event_id.is_none()
+                {
+                    changes.deleted.push(g_event.id.clone());
+                } else if let Some(event) = google_event_to_americano(g_event, &mut report) {
+                    changes.events.push(event);
+                }
+            }
+
+            match page.next_page_token {
+                Some(next) => page_token = Some(next),
+                None => {
+                    report.log();
+                    changes.sync_token = page.next_sync_token;
+                    return Ok(Some(changes));
+                }
+            }
+        }
+    }
+}
+
+// An API key only grants read access to a public calendar, so writes are refused
+// here rather than sent to Google to fail there.
+#[async_trait]
+impl CalendarBackend for GoogleCalendar {
+    async fn list_events(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<calendar::Event>> {
+        let g_events = self.events(from, to).await?;
+        let (events, report) = google_to_americano(&g_events);
+        report.log();
+        Ok(events)
+    }
+
+    async fn get_event(&self, id: &str) -> anyhow::Result<Option<calendar::Event>> {
+        let Some(g_event) = self.event(id).await? else {
+            return Ok(None);
+        };
+        let mut report = ValidationReport::default();
+        let event = google_event_to_americano(&g_event, &mut report);
+        report.log();
+        Ok(event)
+    }
+
+    async fn create_event(&self, _event: calendar::Event) -> anyhow::Result<calendar::Event> {
+        anyhow::bail!("the Google Calendar backend is read-only")
+    }
+
+    async fn update_event(
+        &self,
+        _id: &str,
+        _event: calendar::Event,
+    ) -> anyhow::Result<calendar::Event> {
+        anyhow::bail!("the Google Calendar backend is read-only")
+    }
+
+    async fn delete_event(&self, _id: &str) -> anyhow::Result<()> {
+        anyhow::bail!("the Google Calendar backend is read-only")
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn changes(&self, sync_token: Option<&str>) -> anyhow::Result<Changes> {
+        if let Some(changes) = self.sync(sync_token).await? {
+            return Ok(changes);
+        }
+        tracing::info!("sync token expired, fetching everything");
+        match self.sync(None).await? {
+            Some(changes) => Ok(changes),
+            None => anyhow::bail!("full sync of the calendar was refused"),
+        }
     }
 }
@@ -0,0 +1,1070 @@
+//! Conversions between iCalendar (RFC 5545) data and Eventageous events.
+//!
+//! Any backend that stores raw `VCALENDAR` objects (CalDAV servers, `.ics` files)
+//! goes through here so that they all produce the same [`Event`] shape.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use chrono::{Offset, TimeZone as TimeZoneTrait};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use ics::components::Property;
+use ics::parameters::{PartStat, Role, TzIDParam, Value, CN};
+use ics::properties::{
+    Categories, Class, Description, Location, Method, Organizer, RRule, Sequence, Status, Summary,
+    TzName, URL,
+};
+use ics::{Daylight, ICalendar, Standard, TimeZone};
+
+use crate::calendar::date_time::{self, in_zone};
+use crate::calendar::{teams, Attendee, Event, EventTime, Recurrence, Visibility};
+
+use super::recurrence::instance_id;
+
+const PRODID: &str = "-//Eventageous//Eventageous//EN";
+
+/// Parse every `VEVENT` in an iCalendar document into an [`Event`].
+///
+/// Events without a UID or start are skipped.
+pub fn events_from_ics(ics: &str) -> anyhow::Result<Vec<Event>> {
+    let ics = unfold(ics);
+    let calendar = match icalendar::parser::read_calendar(&ics) {
+        Ok(c) => c,
+        Err(e) => {
+            anyhow::bail!("could not parse calendar: {e}")
+        }
+    };
+    Ok(events_from_calendar(&calendar))
+}
+
+/// Unfold the lines of an iCalendar document for the parser. It doesn't know quoted
+/// parameter values, which is how values with a `:` or `;` in them are written (such as
+/// `SENT-BY="mailto:..."`), so these lose their quotes and have those characters
+/// percent-encoded instead, for [`PropertyExt::param`] to decode.
+pub fn unfold(ics: &str) -> String {
+    let ics = icalendar::parser::unfold(ics);
+    let mut output = String::with_capacity(ics.len());
+    for line in ics.split_inclusive('\n') {
+        let mut quoted = false;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                ':' | ';' | '%' if quoted => output.push_str(&format!("%{:02X}", c as u32)),
+                // The value starts after the first colon outside quotes
+                ':' => {
+                    output.push(c);
+                    output.push_str(chars.as_str());
+                    break;
+                }
+                c => output.push(c),
+            }
+        }
+    }
+    output
+}
+
+/// Convert the `VEVENT`s of a parsed calendar, resolving TZIDs against its `VTIMEZONE`s.
+pub fn events_from_calendar(calendar: &icalendar::parser::Calendar<'_>) -> Vec<Event> {
+    let zones = Zones::from_components(&calendar.components);
+    calendar
+        .components
+        .iter()
+        .filter(|component| component.name == "VEVENT")
+        .filter_map(|component| event_from_component(component, &zones))
+        .collect()
+}
+
+/// Convert a single `VEVENT` component into an [`Event`].
+pub fn event_from_component(
+    event: &icalendar::parser::Component<'_>,
+    zones: &Zones,
+) -> Option<Event> {
+    let start = event.date_time("DTSTART", zones)?;
+    // Without DTEND, an all-day event takes up its day and anything else takes no time
+    let end = match (event.date_time("DTEND", zones), start) {
+        (Some(end), _) => end,
+        (None, EventTime::Date(date)) => EventTime::Date(date.succ_opt()?),
+        (None, start) => start,
+    };
+
+    let (creator_email, creator_name) = match event.find_prop("ORGANIZER") {
+        Some(organizer) => {
+            let email = strip_mailto(organizer.val.as_str()).to_string();
+            let name = organizer.param("CN").unwrap_or_else(|| email.clone());
+            (email, name)
+        }
+        None => (String::new(), String::new()),
+    };
+
+    let uid = event.property("UID")?;
+    let recurrence_lines = event.recurrence_lines();
+    let recurrence =
+        Recurrence::from_lines_in(recurrence_lines.iter().map(String::as_str), |tzid| {
+            Some(zones.get(tzid))
+        });
+
+    // An occurrence of a series that was changed on its own shares the series' UID
+    let (id, recurring_event_id, original_start) = match event.date_time("RECURRENCE-ID", zones) {
+        Some(original) => {
+            let id = instance_id(&uid, &original.instant());
+            (id, Some(uid), Some(original))
+        }
+        None => (uid, None, None),
+    };
+
+    Some(Event {
+        id,
+        summary: event.text("SUMMARY").unwrap_or_default(),
+        description: event.text("DESCRIPTION"),
+        location: event.text("LOCATION"),
+        url: event.property("URL"),
+        creator_email,
+        creator_name,
+        start,
+        end,
+        recurrence,
+        recurring_event_id,
+        original_start,
+        status: event.property("STATUS"),
+        teams: teams::teams_from_tags(event.categories().iter().map(String::as_str)),
+        sequence: event.sequence(),
+        attendees: event.attendees(),
+        visibility: match event.property("CLASS").as_deref() {
+            Some("PRIVATE" | "CONFIDENTIAL") => Visibility::Private,
+            _ => Visibility::Public,
+        },
+        updated: None,
+        redacted: false,
+    })
+}
+
+/// Render an [`Event`] as a standalone `VCALENDAR` document.
+pub fn event_to_ics(event: &Event) -> anyhow::Result<String> {
+    let mut calendar = ICalendar::new("2.0", PRODID);
+    calendar.add_event(vevent(event, &Utc::now()));
+    Ok(calendar.to_string())
+}
+
+/// Replace the `VEVENT` of the event `id` in an iCalendar document with `event`, or
+/// add it if the document doesn't have it yet. Everything else is kept as it was, such
+/// as the changed occurrences of a series and the `VTIMEZONE`s they use.
+///
+/// Without an `event`, the event is removed instead, along with the changed
+/// occurrences of a series.
+pub fn replace_in_ics(ics: &str, id: &str, event: Option<&Event>) -> anyhow::Result<String> {
+    enum Part<'a> {
+        Line(&'a str),
+        Vevent(Vec<&'a str>),
+    }
+    let mut parts = vec![];
+    let mut current: Option<Vec<&str>> = None;
+    for line in ics.lines() {
+        match &mut current {
+            Some(lines) => {
+                lines.push(line);
+                if line.eq_ignore_ascii_case("END:VEVENT") {
+                    parts.push(Part::Vevent(current.take().expect("in a VEVENT")));
+                }
+            }
+            None if line.eq_ignore_ascii_case("BEGIN:VEVENT") => current = Some(vec![line]),
+            None => parts.push(Part::Line(line)),
+        }
+    }
+    let Some(end) = parts.iter().rposition(
+        |part| matches!(part, Part::Line(line) if line.eq_ignore_ascii_case("END:VCALENDAR")),
+    ) else {
+        anyhow::bail!("not a calendar");
+    };
+
+    // Parse each VEVENT on its own, along with the VTIMEZONEs around it
+    let render = |parts: &[Part], only: Option<usize>| {
+        let mut lines = vec![];
+        for (i, part) in parts.iter().enumerate() {
+            match part {
+                Part::Line(line) => lines.push(*line),
+                Part::Vevent(vevent) if only.is_none_or(|only| only == i) => lines.extend(vevent),
+                Part::Vevent(_) => {}
+            }
+        }
+        lines.join("\r\n") + "\r\n"
+    };
+    let mut replaced = vec![];
+    for (i, part) in parts.iter().enumerate() {
+        if let Part::Vevent(_) = part {
+            let found = events_from_ics(&render(&parts, Some(i)))?
+                .into_iter()
+                .any(|found| {
+                    found.id == id
+                        || (event.is_none() && found.recurring_event_id.as_deref() == Some(id))
+                });
+            if found {
+                replaced.push(i);
+            }
+        }
+    }
+
+    let rendered = event.map(|event| vevent(event, &Utc::now()).to_string());
+    let at = replaced.first().copied().unwrap_or(end);
+    let mut output = vec![];
+    for (i, part) in parts.into_iter().enumerate() {
+        if i == at {
+            if let Some(rendered) = &rendered {
+                output.push(Part::Vevent(rendered.lines().collect()));
+            }
+        }
+        if !replaced.contains(&i) {
+            output.push(part);
+        }
+    }
+    Ok(render(&output, None))
+}
+
+/// A feed of `events` that calendar apps can subscribe to, with a `VTIMEZONE` for each
+/// zone the events use covering the years from `from` to `to`. Series are kept as
+/// recurrence rules, with their changed occurrences next to them.
+pub fn feed_to_ics(name: &str, events: &[Event], from: DateTime<Utc>, to: DateTime<Utc>) -> String {
+    let mut calendar = calendar_of(events, from, to);
+    calendar.push(Property::new(
+        "X-WR-CALNAME",
+        ics::escape_text(name.to_string()),
+    ));
+    calendar.to_string()
+}
+
+/// A single event to download into a calendar app, with a `VTIMEZONE` for each zone it
+/// uses covering the years from `from` to `to`.
+pub fn download_to_ics(event: &Event, from: DateTime<Utc>, to: DateTime<Utc>) -> String {
+    calendar_of(std::slice::from_ref(event), from, to).to_string()
+}
+
+fn calendar_of<'a>(events: &'a [Event], from: DateTime<Utc>, to: DateTime<Utc>) -> ICalendar<'a> {
+    let mut calendar = ICalendar::new("2.0", PRODID);
+    let mut zones: Vec<Tz> = events.iter().flat_map(zones_of).collect();
+    zones.sort_by_key(|zone| zone.name());
+    zones.dedup();
+    for zone in zones {
+        calendar.add_timezone(vtimezone(zone, from, to));
+    }
+    for event in events {
+        // Without a known change time, any time will do as long as it doesn't change
+        let dtstamp = event.updated.unwrap_or(DateTime::<Utc>::UNIX_EPOCH);
+        let mut vevent = vevent(event, &dtstamp);
+        if let Some(updated) = &event.updated {
+            vevent.push(Property::new("LAST-MODIFIED", format_utc(updated)));
+        }
+        calendar.add_event(vevent);
+    }
+    calendar
+}
+
+/// An iTIP (RFC 5546) message with `method` about `event`, such as a `REQUEST` inviting
+/// its attendees or a `CANCEL` telling them it's off.
+pub fn itip_to_ics(event: &Event, method: &str) -> anyhow::Result<String> {
+    let mut calendar = ICalendar::new("2.0", PRODID);
+    calendar.push(Method::new(method.to_string()));
+    calendar.add_event(vevent(event, &Utc::now()));
+    Ok(calendar.to_string())
+}
+
+fn vevent<'a>(event: &'a Event, dtstamp: &DateTime<Utc>) -> ics::Event<'a> {
+    let dtstamp = format_utc(dtstamp);
+    let uid = event.recurring_event_id.as_ref().unwrap_or(&event.id);
+    let mut vevent = ics::Event::new(uid.clone(), dtstamp);
+
+    vevent.push(Summary::new(ics::escape_text(event.summary.clone())));
+    if let Some(description) = &event.description {
+        vevent.push(Description::new(ics::escape_text(description.clone())));
+    }
+    if let Some(location) = &event.location {
+        vevent.push(Location::new(ics::escape_text(location.clone())));
+    }
+    if event.visibility == Visibility::Private {
+        vevent.push(Class::private());
+    }
+    if let Some(url) = &event.url {
+        vevent.push(URL::new(url.clone()));
+    }
+    if let Some(organizer) = organizer_property(event) {
+        vevent.push(organizer);
+    }
+    for attendee in &event.attendees {
+        vevent.push(attendee_property(attendee));
+    }
+
+    vevent.push(date_time_property("DTSTART", &event.start));
+    vevent.push(date_time_property("DTEND", &event.end));
+
+    if let Some(status) = &event.status {
+        vevent.push(Status::new(status.clone()));
+    }
+    if event.sequence > 0 {
+        vevent.push(Sequence::new(event.sequence.to_string()));
+    }
+    if !event.teams.is_empty() {
+        vevent.push(Categories::new(event.teams.join(",")));
+    }
+    if let Some(original) = &event.original_start {
+        vevent.push(date_time_property("RECURRENCE-ID", original));
+    }
+    // Only the series has the rules, not its changed occurrences
+    if let (Some(recurrence), None) = (&event.recurrence, &event.original_start) {
+        if let Some(rule) = &recurrence.rule {
+            vevent.push(RRule::new(rule.clone()));
+        }
+        for date in &recurrence.dates {
+            vevent.push(date_time_property("RDATE", date));
+        }
+        for date in &recurrence.exception_dates {
+            vevent.push(date_time_property("EXDATE", date));
+        }
+    }
+    vevent
+}
+
+/// An iTIP (RFC 5546) `REPLY` to the organizer of `event`, in which `attendee` says
+/// whether they are coming.
+pub fn reply_to_ics(event: &Event, attendee: &Attendee) -> anyhow::Result<String> {
+    let dtstamp = format_utc(&Utc::now());
+    let uid = event.recurring_event_id.as_ref().unwrap_or(&event.id);
+    let mut vevent = ics::Event::new(uid.clone(), dtstamp);
+
+    let Some(organizer) = organizer_property(event) else {
+        anyhow::bail!("can't reply to `{}`, it has no organizer", event.id);
+    };
+    vevent.push(organizer);
+    vevent.push(attendee_property(attendee));
+    vevent.push(Summary::new(ics::escape_text(event.summary.clone())));
+    vevent.push(date_time_property("DTSTART", &event.start));
+    vevent.push(date_time_property("DTEND", &event.end));
+    if event.sequence > 0 {
+        vevent.push(Sequence::new(event.sequence.to_string()));
+    }
+    if let Some(original) = &event.original_start {
+        vevent.push(date_time_property("RECURRENCE-ID", original));
+    }
+
+    let mut calendar = ICalendar::new("2.0", PRODID);
+    calendar.push(Method::new("REPLY"));
+    calendar.add_event(vevent);
+    Ok(calendar.to_string())
+}
+
+fn organizer_property(event: &Event) -> Option<Organizer<'_>> {
+    if event.creator_email.is_empty() {
+        return None;
+    }
+    let mut organizer = Organizer::new(format!("mailto:{}", event.creator_email));
+    if !event.creator_name.is_empty() {
+        organizer.add(CN::new(event.creator_name.clone()));
+    }
+    Some(organizer)
+}
+
+fn attendee_property(attendee: &Attendee) -> ics::properties::Attendee<'_> {
+    let mut property = ics::properties::Attendee::new(format!("mailto:{}", attendee.email));
+    if let Some(name) = &attendee.name {
+        property.add(CN::new(name.clone()));
+    }
+    if let Some(role) = &attendee.role {
+        property.add(Role::new(role.clone()));
+    }
+    property.add(PartStat::new(attendee.status.clone()));
+    property
+}
+
+/// Format an instant the way iCalendar expects UTC date-times, e.g. `20240313T160000Z`.
+pub fn format_utc(date_time: &DateTime<Utc>) -> String {
+    date_time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// The zones, other than UTC, that the times of `event` are in.
+fn zones_of(event: &Event) -> Vec<Tz> {
+    let recurrence_dates = event
+        .recurrence
+        .iter()
+        .flat_map(|recurrence| recurrence.dates.iter().chain(&recurrence.exception_dates));
+    [&event.start, &event.end]
+        .into_iter()
+        .chain(&event.original_start)
+        .chain(recurrence_dates)
+        .filter_map(EventTime::zone)
+        .filter(|zone| *zone != Tz::UTC)
+        .collect()
+}
+
+/// A `VTIMEZONE` for `zone`: the offset in effect at `from`, and each change of offset
+/// until `to`.
+fn vtimezone(zone: Tz, from: DateTime<Utc>, to: DateTime<Utc>) -> TimeZone<'static> {
+    let offset_at = |instant: DateTime<Utc>| zone.offset_from_utc_datetime(&instant.naive_utc());
+    let seconds = |offset: &<Tz as TimeZoneTrait>::Offset| offset.fix().local_minus_utc();
+
+    // Changes of offset, found day by day and then narrowed down to the second
+    let mut changes = vec![(from, offset_at(from))];
+    let mut day = from;
+    while day < to {
+        let next = day + chrono::Duration::days(1);
+        if seconds(&offset_at(next)) != seconds(&offset_at(day)) {
+            let (mut before, mut after) = (day, next);
+            while after - before > chrono::Duration::seconds(1) {
+                let middle = before + (after - before) / 2;
+                match seconds(&offset_at(middle)) == seconds(&offset_at(day)) {
+                    true => before = middle,
+                    false => after = middle,
+                }
+            }
+            changes.push((after, offset_at(after)));
+        }
+        day = next;
+    }
+
+    let (mut standards, mut daylights) = (vec![], vec![]);
+    let mut previous = seconds(&changes[0].1);
+    for (start, offset) in changes {
+        // The onset is in local time as it was before the change
+        let local = start + chrono::Duration::seconds(previous.into());
+        let dtstart = local.format("%Y%m%dT%H%M%S").to_string();
+        let (offset_from, offset_to) = (format_offset(previous), format_offset(seconds(&offset)));
+        let name = TzName::new(offset.abbreviation().to_string());
+        previous = seconds(&offset);
+        if offset.dst_offset().is_zero() {
+            let mut standard = Standard::new(dtstart, offset_from, offset_to);
+            standard.push(name);
+            standards.push(standard);
+        } else {
+            let mut daylight = Daylight::new(dtstart, offset_from, offset_to);
+            daylight.push(name);
+            daylights.push(daylight);
+        }
+    }
+    let mut timezone = match standards.is_empty() {
+        false => TimeZone::standard(zone.name(), standards.remove(0)),
+        true => TimeZone::daylight(zone.name(), daylights.remove(0)),
+    };
+    for standard in standards {
+        timezone.add_standard(standard);
+    }
+    for daylight in daylights {
+        timezone.add_daylight(daylight);
+    }
+    timezone
+}
+
+/// A UTC offset as in `TZOFFSETFROM`, e.g. `-0500`.
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+/// A date-time property: UTC instants with a `Z`, other zones as a local time with
+/// their TZID, floating times bare, and dates with `VALUE=DATE`.
+fn date_time_property<'a>(name: &'a str, time: &EventTime) -> Property<'a> {
+    let local = |local: &NaiveDateTime| local.format("%Y%m%dT%H%M%S").to_string();
+    match time {
+        EventTime::Zoned(date_time) if date_time.timezone() == Tz::UTC => {
+            Property::new(name, format_utc(&date_time.with_timezone(&Utc)))
+        }
+        EventTime::Zoned(date_time) => {
+            let mut property = Property::new(name, local(&date_time.naive_local()));
+            property.add(TzIDParam::new(date_time.timezone().name()));
+            property
+        }
+        EventTime::Floating(date_time) => Property::new(name, local(date_time)),
+        EventTime::Date(date) => {
+            let mut property = Property::new(name, date.format("%Y%m%d").to_string());
+            property.add(Value::DATE);
+            property
+        }
+    }
+}
+
+/// Convert an iCalendar date-time value (`20240313T160000Z`, a local `20240306T110000` in
+/// `zone` or floating without one) or date (`20240313`) into an [`EventTime`].
+pub(crate) fn event_time(value: &str, zone: Option<Tz>) -> Option<EventTime> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return Some(EventTime::Date(date));
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        let date_time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        return Some(EventTime::Zoned(
+            date_time.and_utc().with_timezone(&Tz::UTC),
+        ));
+    }
+    let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    Some(match zone {
+        Some(zone) => EventTime::Zoned(in_zone(local, zone)),
+        None => EventTime::Floating(local),
+    })
+}
+
+/// The time zones a calendar refers to by TZID.
+///
+/// TZIDs are usually IANA names, possibly with a vendor prefix, or Windows names; for
+/// anything else we use the zone named by the `VTIMEZONE`'s `X-LIC-LOCATION`, or a
+/// fixed zone with its standard offset.
+#[derive(Debug, Default)]
+pub struct Zones {
+    defined: HashMap<String, Tz>,
+}
+
+impl Zones {
+    pub fn from_components(components: &[icalendar::parser::Component<'_>]) -> Self {
+        let mut defined = HashMap::new();
+        for component in components.iter().filter(|c| c.name == "VTIMEZONE") {
+            let Some(tzid) = component.property("TZID") else {
+                continue;
+            };
+            let zone = date_time::zone(&tzid)
+                .or_else(|| date_time::zone(&component.property("X-LIC-LOCATION")?))
+                .or_else(|| standard_offset_zone(component));
+            match zone {
+                Some(zone) => {
+                    defined.insert(tzid, zone);
+                }
+                None => tracing::info!("could not resolve VTIMEZONE `{tzid}`"),
+            }
+        }
+        Zones { defined }
+    }
+
+    /// The zone for a TZID, falling back to UTC for ones we can't make sense of.
+    pub fn get(&self, tzid: &str) -> Tz {
+        let zone = self
+            .defined
+            .get(tzid)
+            .copied()
+            .or_else(|| date_time::zone(tzid));
+        zone.unwrap_or_else(|| {
+            tracing::info!("unknown TZID `{tzid}`, using UTC");
+            Tz::UTC
+        })
+    }
+}
+
+/// The `TZOFFSETTO` of the `STANDARD` part of a `VTIMEZONE`, e.g. `-0500`, as a zone.
+fn standard_offset_zone(vtimezone: &icalendar::parser::Component<'_>) -> Option<Tz> {
+    let standard = vtimezone
+        .components
+        .iter()
+        .find(|component| component.name == "STANDARD")?;
+    let offset = standard.property("TZOFFSETTO")?;
+    let (sign, digits) = match offset.split_at(1) {
+        ("-", digits) => (-1, digits),
+        ("+", digits) => (1, digits),
+        _ => return None,
+    };
+    let hours: i32 = digits.get(0..2)?.parse().ok()?;
+    let minutes: i32 = digits.get(2..4)?.parse().ok()?;
+    date_time::fixed_offset_zone(sign * (hours * 3600 + minutes * 60))
+}
+
+fn strip_mailto(value: &str) -> &str {
+    value
+        .strip_prefix("mailto:")
+        .or_else(|| value.strip_prefix("MAILTO:"))
+        .unwrap_or(value)
+}
+
+/// Undo the TEXT escaping from RFC 5545 section 3.3.11.
+fn unescape_text(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => output.push('\n'),
+            Some(other) => output.push(other),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+pub(crate) trait CalendarExt {
+    fn property(&self, name: &str) -> Option<String>;
+}
+
+impl CalendarExt for icalendar::parser::Calendar<'_> {
+    fn property(&self, name: &str) -> Option<String> {
+        for property in &self.properties {
+            if property.name == name {
+                return Some(property.val.as_str().to_string());
+            }
+        }
+        None
+    }
+}
+
+pub(crate) trait ComponentExt {
+    fn property(&self, name: &str) -> Option<String>;
+
+    /// Like `property`, but with TEXT escapes removed.
+    fn text(&self, name: &str) -> Option<String> {
+        self.property(name).map(|value| unescape_text(&value))
+    }
+
+    /// A date-time property, with its TZID looked up in `zones`.
+    fn date_time(&self, name: &str, zones: &Zones) -> Option<EventTime>;
+
+    /// The `RRULE`, `RDATE` and `EXDATE` properties as `NAME;PARAM=value:value` lines.
+    fn recurrence_lines(&self) -> Vec<String>;
+
+    /// Every value of every `CATEGORIES` property, which are comma-separated lists.
+    fn categories(&self) -> Vec<String>;
+
+    /// The `ATTENDEE`s, with their `CN`, `ROLE` and `PARTSTAT` (`NEEDS-ACTION` when
+    /// missing).
+    fn attendees(&self) -> Vec<Attendee>;
+
+    /// The address of the `ORGANIZER`.
+    fn organizer(&self) -> Option<String> {
+        self.property("ORGANIZER")
+            .map(|organizer| strip_mailto(&organizer).to_string())
+    }
+
+    /// The address in the `SENT-BY` of the `ORGANIZER`, who sends on their behalf.
+    fn organizer_sent_by(&self) -> Option<String>;
+
+    /// The `SEQUENCE`, which is 0 when missing.
+    fn sequence(&self) -> u32 {
+        self.property("SEQUENCE")
+            .and_then(|sequence| sequence.trim().parse().ok())
+            .unwrap_or(0)
+    }
+}
+
+impl ComponentExt for icalendar::parser::Component<'_> {
+    fn property(&self, name: &str) -> Option<String> {
+        for property in &self.properties {
+            if property.name == name {
+                return Some(property.val.as_str().to_string());
+            }
+        }
+        None
+    }
+
+    fn date_time(&self, name: &str, zones: &Zones) -> Option<EventTime> {
+        let property = self.find_prop(name)?;
+        let zone = property.param("TZID").map(|tzid| zones.get(&tzid));
+        event_time(property.val.as_str(), zone)
+    }
+
+    fn organizer_sent_by(&self) -> Option<String> {
+        let sent_by = self.find_prop("ORGANIZER")?.param("SENT-BY")?;
+        Some(strip_mailto(&sent_by).to_string())
+    }
+
+    fn recurrence_lines(&self) -> Vec<String> {
+        self.properties
+            .iter()
+            .filter(|property| ["RRULE", "RDATE", "EXDATE"].contains(&property.name.as_str()))
+            .map(|property| {
+                let params: String = property
+                    .params
+                    .iter()
+                    .filter_map(|param| {
+                        let value = param.val.as_ref()?;
+                        Some(format!(";{}={}", param.key.as_str(), value.as_str()))
+                    })
+                    .collect();
+                let name = property.name.as_str();
+                format!("{name}{params}:{}", property.val.as_str())
+            })
+            .collect()
+    }
+
+    fn categories(&self) -> Vec<String> {
+        self.properties
+            .iter()
+            .filter(|property| property.name == "CATEGORIES")
+            .flat_map(|property| property.val.as_str().split(','))
+            .map(unescape_text)
+            .collect()
+    }
+
+    fn attendees(&self) -> Vec<Attendee> {
+        self.properties
+            .iter()
+            .filter(|property| property.name == "ATTENDEE")
+            .map(|property| Attendee {
+                email: strip_mailto(property.val.as_str()).to_string(),
+                name: property.param("CN"),
+                status: property
+                    .param("PARTSTAT")
+                    .unwrap_or_else(|| "NEEDS-ACTION".to_string()),
+                role: property.param("ROLE"),
+            })
+            .collect()
+    }
+}
+
+pub(crate) trait PropertyExt {
+    fn param(&self, key: &str) -> Option<String>;
+}
+
+impl PropertyExt for icalendar::parser::Property<'_> {
+    fn param(&self, key: &str) -> Option<String> {
+        self.params
+            .iter()
+            .find(|param| param.key == key)
+            .and_then(|param| param.val.as_ref())
+            .map(|val| {
+                let val = val.as_str().trim_matches('"');
+                urlencoding::decode(val).map_or_else(|_| val.to_string(), |val| val.into_owned())
+            })
+    }
+}
+
+#[test]
+fn test_event_round_trip() {
+    let event = Event {
+        id: "lang-triage@example.org".to_string(),
+        summary: "Lang team triage, weekly".to_string(),
+        description: Some("Agenda:\n- triage".to_string()),
+        location: Some("https://meet.jit.si/ferris-rules".to_string()),
+        creator_email: "niko@example.org".to_string(),
+        creator_name: "Niko".to_string(),
+        start: EventTime::parse("2024-03-06T11:00:00", Some("America/New_York")).unwrap(),
+        end: EventTime::parse("2024-03-06T16:00:00Z", None).unwrap(),
+        recurrence: Recurrence::from_lines([
+            "RRULE:FREQ=WEEKLY;BYDAY=WE",
+            "EXDATE;TZID=America/New_York:20240313T110000",
+        ]),
+        teams: vec!["lang".to_string(), "types".to_string()],
+        sequence: 2,
+        url: Some("https://example.org/triage".to_string()),
+        attendees: vec![Attendee {
+            email: "tmandry@example.org".to_string(),
+            name: Some("Tyler".to_string()),
+            status: "ACCEPTED".to_string(),
+            role: Some("OPT-PARTICIPANT".to_string()),
+        }],
+        ..Event::default()
+    };
+
+    let ics = event_to_ics(&event).unwrap();
+    let events = events_from_ics(&ics).unwrap();
+    expect_test::expect![[r#"
+        [
+            Event {
+                id: "lang-triage@example.org",
+                summary: "Lang team triage, weekly",
+                description: Some(
+                    "Agenda:\n- triage",
+                ),
+                location: Some(
+                    "https://meet.jit.si/ferris-rules",
+                ),
+                url: Some(
+                    "https://example.org/triage",
+                ),
+                creator_email: "niko@example.org",
+                creator_name: "Niko",
+                start: Zoned(
+                    2024-03-06T11:00:00EST,
+                ),
+                end: Zoned(
+                    2024-03-06T16:00:00UTC,
+                ),
+                recurrence: Some(
+                    Recurrence {
+                        rule: Some(
+                            "FREQ=WEEKLY;BYDAY=WE",
+                        ),
+                        description: "Weekly on Wednesday",
+                        dates: [],
+                        exception_dates: [
+                            Zoned(
+                                2024-03-13T11:00:00EDT,
+                            ),
+                        ],
+                    },
+                ),
+                recurring_event_id: None,
+                original_start: None,
+                status: None,
+                teams: [
+                    "lang",
+                    "types",
+                ],
+                sequence: 2,
+                attendees: [
+                    Attendee {
+                        email: "tmandry@example.org",
+                        name: Some(
+                            "Tyler",
+                        ),
+                        status: "ACCEPTED",
+                        role: Some(
+                            "OPT-PARTICIPANT",
+                        ),
+                    },
+                ],
+                visibility: Public,
+                updated: None,
+                redacted: false,
+            },
+        ]
+    "#]]
+    .assert_debug_eq(&events);
+}
+
+#[test]
+fn test_replace_in_ics() {
+    // A series with a moved occurrence and a zone of its own, as another app wrote it
+    let ics = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:other\r\n\
+               BEGIN:VTIMEZONE\r\nTZID:Triage time\r\nBEGIN:STANDARD\r\n\
+               DTSTART:19700101T000000\r\nTZOFFSETFROM:-0500\r\nTZOFFSETTO:-0500\r\n\
+               END:STANDARD\r\nEND:VTIMEZONE\r\n\
+               BEGIN:VEVENT\r\nUID:triage\r\nDTSTAMP:20240301T000000Z\r\n\
+               DTSTART;TZID=Triage time:20240306T110000\r\n\
+               DTEND;TZID=Triage time:20240306T120000\r\n\
+               RRULE:FREQ=WEEKLY;BYDAY=WE\r\nSUMMARY:Triage\r\nEND:VEVENT\r\n\
+               BEGIN:VEVENT\r\nUID:triage\r\nDTSTAMP:20240301T000000Z\r\n\
+               RECURRENCE-ID;TZID=Triage time:20240313T110000\r\n\
+               DTSTART;TZID=Triage time:20240314T110000\r\n\
+               DTEND;TZID=Triage time:20240314T120000\r\nSUMMARY:Triage (moved)\r\n\
+               END:VEVENT\r\nEND:VCALENDAR\r\n";
+    let summaries = |ics: &str| {
+        events_from_ics(ics)
+            .unwrap()
+            .into_iter()
+            .map(|event| format!("{} {}", event.id, event.summary))
+            .collect::<Vec<_>>()
+    };
+    let events = events_from_ics(ics).unwrap();
+
+    // Changing the series keeps the moved occurrence, and the zone it is in
+    let master = Event {
+        summary: "Lang triage".to_string(),
+        ..events[0].clone()
+    };
+    let replaced = replace_in_ics(ics, "triage", Some(&master)).unwrap();
+    assert!(replaced.contains("TZID:Triage time"));
+    expect_test::expect![[r#"
+        [
+            "triage Lang triage",
+            "triage_20240313T160000Z Triage (moved)",
+        ]
+    "#]]
+    .assert_debug_eq(&summaries(&replaced));
+
+    // Changing an occurrence replaces only that one, or adds it if it wasn't changed yet
+    let cancelled = Event {
+        status: Some("CANCELLED".to_string()),
+        ..events[1].clone()
+    };
+    let replaced = replace_in_ics(&replaced, &cancelled.id, Some(&cancelled)).unwrap();
+    let next =
+        super::recurrence::occurrence_at(&master, master.start.shifted(chrono::Duration::weeks(2)));
+    let replaced = replace_in_ics(&replaced, &next.id, Some(&next)).unwrap();
+    let events = events_from_ics(&replaced).unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[1].status.as_deref(), Some("CANCELLED"));
+    assert_eq!(events[2].id, "triage_20240320T160000Z");
+
+    // Removing an occurrence leaves the rest, removing the series removes it all
+    let removed = replace_in_ics(&replaced, &next.id, None).unwrap();
+    assert_eq!(events_from_ics(&removed).unwrap().len(), 2);
+    let removed = replace_in_ics(&removed, "triage", None).unwrap();
+    assert!(events_from_ics(&removed).unwrap().is_empty());
+    assert!(removed.contains("TZID:Triage time"));
+}
+
+#[test]
+fn test_all_day_and_floating() {
+    let ics = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Test//Test//EN\r
+BEGIN:VEVENT\r
+UID:rustconf@example.org\r
+SUMMARY:RustConf\r
+DTSTART;VALUE=DATE:20240910\r
+DTEND;VALUE=DATE:20240914\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:holiday@example.org\r
+SUMMARY:Holiday\r
+DTSTART;VALUE=DATE:20241225\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup@example.org\r
+SUMMARY:Standup\r
+DTSTART:20240306T090000\r
+DTEND:20240306T091500\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+    let events = events_from_ics(ics).unwrap();
+    let summary: Vec<_> = events
+        .iter()
+        .map(|e| (e.kind(), e.start.to_string(), e.end.to_string()))
+        .collect();
+    expect_test::expect![[r#"
+        [
+            (
+                AllDay,
+                "2024-09-10",
+                "2024-09-14",
+            ),
+            (
+                AllDay,
+                "2024-12-25",
+                "2024-12-26",
+            ),
+            (
+                Floating,
+                "2024-03-06T09:00:00",
+                "2024-03-06T09:15:00",
+            ),
+        ]
+    "#]]
+    .assert_debug_eq(&summary);
+
+    // All-day events keep their dates when written back
+    let written = event_to_ics(&events[0]).unwrap();
+    assert!(written.contains("DTSTART;VALUE=DATE:20240910"), "{written}");
+    assert_eq!(
+        events_from_ics(&written).unwrap()[0].kind(),
+        crate::calendar::EventKind::AllDay
+    );
+}
+
+#[test]
+fn test_vtimezone() {
+    let ics = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Test//Test//EN\r
+BEGIN:VTIMEZONE\r
+TZID:Custom Eastern\r
+X-LIC-LOCATION:America/New_York\r
+END:VTIMEZONE\r
+BEGIN:VTIMEZONE\r
+TZID:Somewhere\r
+BEGIN:STANDARD\r
+DTSTART:19700101T000000\r
+TZOFFSETFROM:+0300\r
+TZOFFSETTO:+0300\r
+END:STANDARD\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+UID:one@example.org\r
+DTSTART;TZID=Custom Eastern:20240306T110000\r
+DTEND;TZID=\"/mozilla.org/20050126_1/America/New_York\":20240306T120000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:two@example.org\r
+DTSTART;TZID=Somewhere:20240306T110000\r
+DTEND;TZID=W. Europe Standard Time:20240306T100000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+    let events = events_from_ics(ics).unwrap();
+    let times: Vec<_> = events
+        .iter()
+        .map(|e| (e.start.to_string(), e.end.to_string()))
+        .collect();
+    expect_test::expect![[r#"
+        [
+            (
+                "2024-03-06T11:00:00-05:00",
+                "2024-03-06T12:00:00-05:00",
+            ),
+            (
+                "2024-03-06T11:00:00+03:00",
+                "2024-03-06T10:00:00+01:00",
+            ),
+        ]
+    "#]]
+    .assert_debug_eq(&times);
+}
+
+#[test]
+fn test_feed_to_ics() {
+    let series = Event {
+        id: "lang-triage@example.org".to_string(),
+        summary: "Lang team triage".to_string(),
+        creator_email: "niko@example.org".to_string(),
+        creator_name: "Niko".to_string(),
+        start: EventTime::parse("2024-03-06T11:00:00", Some("America/New_York")).unwrap(),
+        end: EventTime::parse("2024-03-06T12:00:00", Some("America/New_York")).unwrap(),
+        recurrence: Recurrence::from_lines(["RRULE:FREQ=WEEKLY;BYDAY=WE"]),
+        teams: vec!["lang".to_string()],
+        updated: "2024-03-01T09:30:00Z".parse().ok(),
+        ..Event::default()
+    };
+    let moved = Event {
+        id: instance_id(&series.id, &"2024-03-13T15:00:00Z".parse().unwrap()),
+        recurring_event_id: Some(series.id.clone()),
+        original_start: Some(
+            EventTime::parse("2024-03-13T11:00:00", Some("America/New_York")).unwrap(),
+        ),
+        start: EventTime::parse("2024-03-13T13:00:00", Some("America/New_York")).unwrap(),
+        end: EventTime::parse("2024-03-13T14:00:00", Some("America/New_York")).unwrap(),
+        recurrence: None,
+        ..series.clone()
+    };
+    let from = "2024-01-01T00:00:00Z".parse().unwrap();
+    let to = "2025-01-01T00:00:00Z".parse().unwrap();
+    let ics = feed_to_ics("Eventageous", &[series, moved], from, to);
+    expect_test::expect![[r#"
+        BEGIN:VCALENDAR
+        VERSION:2.0
+        PRODID:-//Eventageous//Eventageous//EN
+        X-WR-CALNAME:Eventageous
+        BEGIN:VTIMEZONE
+        TZID:America/New_York
+        BEGIN:STANDARD
+        DTSTART:20231231T190000
+        TZOFFSETFROM:-0500
+        TZOFFSETTO:-0500
+        TZNAME:EST
+        END:STANDARD
+        BEGIN:STANDARD
+        DTSTART:20241103T020000
+        TZOFFSETFROM:-0400
+        TZOFFSETTO:-0500
+        TZNAME:EST
+        END:STANDARD
+        BEGIN:DAYLIGHT
+        DTSTART:20240310T020000
+        TZOFFSETFROM:-0500
+        TZOFFSETTO:-0400
+        TZNAME:EDT
+        END:DAYLIGHT
+        END:VTIMEZONE
+        BEGIN:VEVENT
+        UID:lang-triage@example.org
+        DTSTAMP:20240301T093000Z
+        SUMMARY:Lang team triage
+        ORGANIZER;CN=Niko:mailto:niko@example.org
+        DTSTART;TZID=America/New_York:20240306T110000
+        DTEND;TZID=America/New_York:20240306T120000
+        CATEGORIES:lang
+        RRULE:FREQ=WEEKLY;BYDAY=WE
+        LAST-MODIFIED:20240301T093000Z
+        END:VEVENT
+        BEGIN:VEVENT
+        UID:lang-triage@example.org
+        DTSTAMP:20240301T093000Z
+        SUMMARY:Lang team triage
+        ORGANIZER;CN=Niko:mailto:niko@example.org
+        DTSTART;TZID=America/New_York:20240313T130000
+        DTEND;TZID=America/New_York:20240313T140000
+        CATEGORIES:lang
+        RECURRENCE-ID;TZID=America/New_York:20240313T110000
+        LAST-MODIFIED:20240301T093000Z
+        END:VEVENT
+        END:VCALENDAR
+    "#]]
+    .assert_eq(&ics.replace("\r\n", "\n"));
+
+    // The moved occurrence stays attached to its series
+    let events = events_from_ics(&ics).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].start.to_string(), "2024-03-13T13:00:00-04:00");
+}
@@ -0,0 +1,185 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::calendar::Event;
+
+use super::backend::CalendarBackend;
+use super::ical::{event_to_ics, events_from_ics, replace_in_ics};
+
+/// A directory of `.ics` files, for self-hosting and for running without any secrets.
+///
+/// Each file may contain any number of events. Events written through the backend
+/// get a file of their own, named after their UID, except for the changed occurrences
+/// of a series, which go into the file of the series.
+pub struct IcsDirectory {
+    path: PathBuf,
+}
+
+impl IcsDirectory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Parse every `.ics` file in the directory, returning the events along with the file
+    /// they came from.
+    async fn read_all(&self) -> anyhow::Result<Vec<(PathBuf, Event)>> {
+        let mut events = vec![];
+        let mut entries = tokio::fs::read_dir(&self.path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "ics") {
+                continue;
+            }
+
+            let text = tokio::fs::read_to_string(&path).await?;
+            match events_from_ics(&text) {
+                Ok(parsed) => events.extend(parsed.into_iter().map(|event| (path.clone(), event))),
+                Err(e) => tracing::error!("skipping unparseable file {}: {e}", path.display()),
+            }
+        }
+        Ok(events)
+    }
+
+    async fn find(&self, id: &str) -> anyhow::Result<Option<(PathBuf, Event)>> {
+        let events = self.read_all().await?;
+        Ok(events.into_iter().find(|(_, event)| event.id == id))
+    }
+
+    fn file_for(&self, id: &str) -> PathBuf {
+        let name: String = id
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' | '@' => c,
+                _ => '_',
+            })
+            .collect();
+        self.path.join(format!("{name}.ics"))
+    }
+
+    /// Replace (or add, or without an `event` remove) the event `id` in the file at
+    /// `path`, leaving the other events in it alone. A file left without events goes.
+    async fn rewrite(&self, path: &Path, id: &str, event: Option<&Event>) -> anyhow::Result<()> {
+        let text = tokio::fs::read_to_string(path).await?;
+        let text = replace_in_ics(&text, id, event)?;
+        match events_from_ics(&text)?.is_empty() {
+            true => tokio::fs::remove_file(path).await?,
+            false => tokio::fs::write(path, text).await?,
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CalendarBackend for IcsDirectory {
+    async fn list_events(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<Event>> {
+        let in_window = |event: &Event| {
+            let recurring = event.recurrence.is_some() || event.recurring_event_id.is_some();
+            to.is_none_or(|to| event.start.instant() < to)
+                && (recurring || from.is_none_or(|from| event.end.instant() >= from))
+        };
+        let mut events: Vec<Event> = self
+            .read_all()
+            .await?
+            .into_iter()
+            .map(|(_, event)| event)
+            .filter(in_window)
+            .collect();
+        events.sort_by_key(|event| event.start.instant());
+        Ok(events)
+    }
+
+    async fn get_event(&self, id: &str) -> anyhow::Result<Option<Event>> {
+        Ok(self.find(id).await?.map(|(_, event)| event))
+    }
+
+    async fn create_event(&self, mut event: Event) -> anyhow::Result<Event> {
+        if event.id.is_empty() {
+            event.id = format!("{}@eventageous", uuid::Uuid::new_v4());
+        }
+        if self.find(&event.id).await?.is_some() {
+            anyhow::bail!("an event with id `{}` already exists", event.id);
+        }
+        // A changed occurrence shares the UID of its series, so it goes into its file
+        if let Some(series_id) = &event.recurring_event_id {
+            let Some((path, _)) = self.find(series_id).await? else {
+                anyhow::bail!("no event with id `{series_id}`");
+            };
+            self.rewrite(&path, &event.id, Some(&event)).await?;
+            return Ok(event);
+        }
+        tokio::fs::write(self.file_for(&event.id), event_to_ics(&event)?).await?;
+        Ok(event)
+    }
+
+    async fn update_event(&self, id: &str, mut event: Event) -> anyhow::Result<Event> {
+        let Some((path, _)) = self.find(id).await? else {
+            anyhow::bail!("no event with id `{id}`");
+        };
+        event.id = id.to_string();
+        self.rewrite(&path, id, Some(&event)).await?;
+        Ok(event)
+    }
+
+    async fn delete_event(&self, id: &str) -> anyhow::Result<()> {
+        let Some((path, _)) = self.find(id).await? else {
+            anyhow::bail!("no event with id `{id}`");
+        };
+        self.rewrite(&path, id, None).await
+    }
+}
+
+#[tokio::test]
+async fn test_read_test_data() {
+    let calendar = IcsDirectory::new("test_data/calendar");
+    let ids: Vec<String> = calendar
+        .list_events(None, None)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|event| event.id)
+        .collect();
+    expect_test::expect![[r#"
+        [
+            "6v2ielbusc7s08p9ev6f40g4en@google.com",
+            "compiler-steering@example.org",
+        ]
+    "#]]
+    .assert_debug_eq(&ids);
+}
+
+#[tokio::test]
+async fn test_write_round_trip() {
+    let dir = std::env::temp_dir().join(format!("eventageous-{}", uuid::Uuid::new_v4()));
+    tokio::fs::create_dir(&dir).await.unwrap();
+    let calendar = IcsDirectory::new(&dir);
+
+    let event = Event {
+        id: String::new(),
+        summary: "Local event".to_string(),
+        description: None,
+        location: None,
+        creator_email: "test@example.org".to_string(),
+        creator_name: "Test".to_string(),
+        start: super::EventTime::parse("2024-03-13T16:00:00Z", None).unwrap(),
+        end: super::EventTime::parse("2024-03-13T17:00:00Z", None).unwrap(),
+        ..Event::default()
+    };
+    let created = calendar.create_event(event).await.unwrap();
+    assert!(calendar.create_event(created.clone()).await.is_err());
+
+    let mut changed = calendar.get_event(&created.id).await.unwrap().unwrap();
+    changed.summary = "Local event (moved)".to_string();
+    calendar.update_event(&created.id, changed).await.unwrap();
+    let fetched = calendar.get_event(&created.id).await.unwrap().unwrap();
+    assert_eq!(fetched.summary, "Local event (moved)");
+
+    calendar.delete_event(&created.id).await.unwrap();
+    assert!(calendar.list_events(None, None).await.unwrap().is_empty());
+    tokio::fs::remove_dir(&dir).await.unwrap();
+}
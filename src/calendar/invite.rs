@@ -1,19 +1,94 @@
 use mail_parser::{Message, MessageParser, MimeHeaders};
 use tracing::info;
 
-use super::google_calendar::{Creator, Event, EventDate};
+use crate::calendar::teams::{self, Routing, Team};
+use crate::calendar::{Attendee, Event, EventTime, Visibility};
 
+use super::ical::{
+    event_from_component, events_from_calendar, unfold, CalendarExt, ComponentExt, Zones,
+};
+use super::recurrence::instance_id;
+
+/// The iTIP (RFC 5546) messages found in an email.
 #[derive(Default, Debug)]
 pub struct CalendarEmail {
     event_requests: Vec<Event>,
+    published: Vec<Event>,
+    cancellations: Vec<EventRef>,
+    replies: Vec<Reply>,
+    counters: Vec<Counter>,
+    declined_counters: Vec<EventRef>,
+    /// What the plus-addresses the email was sent to ask for.
+    routing: Routing,
+    /// The `From` address, which is only as trustworthy as the checks of the mail
+    /// server that received the email (SPF, DKIM and DMARC).
+    sender: Option<String>,
+    /// Addresses that the organizers say send on their behalf (`SENT-BY`), in lowercase.
+    delegates: Vec<String>,
+}
+
+/// The event (or single occurrence of a series) that an iTIP message is about.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventRef {
+    pub uid: String,
+    /// The original start of the occurrence, if the message is about just one.
+    pub recurrence_id: Option<EventTime>,
+    /// The `SEQUENCE` of the event as the sender knows it.
+    pub sequence: u32,
+    /// The address of the `ORGANIZER` as the sender says it is.
+    pub organizer: Option<String>,
+}
+
+/// An attendee saying whether they are coming (`METHOD:REPLY`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reply {
+    pub event: EventRef,
+    pub attendees: Vec<Attendee>,
+}
+
+/// An attendee proposing another time for an event (`METHOD:COUNTER`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Counter {
+    pub event: EventRef,
+    pub attendee: String,
+    pub start: EventTime,
+    pub end: EventTime,
+    pub comment: Option<String>,
+}
+
+impl EventRef {
+    /// The id of the event or occurrence, as in [`Event::id`].
+    pub fn event_id(&self) -> String {
+        match &self.recurrence_id {
+            Some(original) => instance_id(&self.uid, &original.instant()),
+            None => self.uid.clone(),
+        }
+    }
+
+    fn from_component(component: &icalendar::parser::Component<'_>, zones: &Zones) -> Option<Self> {
+        Some(EventRef {
+            uid: component.property("UID")?,
+            recurrence_id: component.date_time("RECURRENCE-ID", zones),
+            sequence: component.sequence(),
+            organizer: component.organizer(),
+        })
+    }
 }
 
 impl CalendarEmail {
-    pub fn parse_email(input: &str) -> anyhow::Result<CalendarEmail> {
-        let mut output = CalendarEmail::default();
+    /// Parse the invites in an email. Recipients that are plus-addresses of
+    /// `calendar_address` route the invited events, see [`CalendarEmail::route`].
+    pub fn parse_email(input: &str, calendar_address: Option<&str>) -> anyhow::Result<Self> {
         let Some(message) = MessageParser::default().parse(input) else {
             anyhow::bail!("could not parse email")
         };
+        let mut output = CalendarEmail {
+            sender: message
+                .from()
+                .and_then(|address| address.first()?.address())
+                .map(str::to_lowercase),
+            ..CalendarEmail::default()
+        };
         for attachment in message.attachments() {
             let Some(content_type) = attachment.content_type() else {
                 continue;
@@ -22,12 +97,102 @@ impl CalendarEmail {
                 output.parse_calendar_invite(&message, attachment.contents())?;
             }
         }
+        if let Some(calendar_address) = calendar_address {
+            let tags = recipient_tags(&message, calendar_address);
+            output.route(&Routing::from_tags(tags.iter().map(String::as_str)));
+        }
         Ok(output)
     }
 
+    /// Put the invited events on the calendars of the teams in `routing`, and make them
+    /// private if it asks for that.
+    pub fn route(&mut self, routing: &Routing) {
+        self.routing.merge(routing);
+        for event in self.event_requests.iter_mut().chain(&mut self.published) {
+            event.teams.extend(routing.teams.iter().cloned());
+            event.teams = teams::teams_from_tags(event.teams.iter().map(String::as_str));
+            if routing.private {
+                event.visibility = Visibility::Private;
+            }
+        }
+    }
+
+    /// Take the teams that aren't `known` off the invited events, returning them. These
+    /// come from `CATEGORIES`, which organizers can fill with anything.
+    pub fn keep_known_teams(&mut self, known: &[Team]) -> Vec<String> {
+        let mut dropped = vec![];
+        for event in self.event_requests.iter_mut().chain(&mut self.published) {
+            let unknown = Routing {
+                teams: event.teams.clone(),
+                private: false,
+            }
+            .unknown_teams(known);
+            event.teams.retain(|team| !unknown.contains(team));
+            dropped.extend(unknown);
+        }
+        dropped.sort();
+        dropped.dedup();
+        dropped
+    }
+
+    /// Who sent the email, by its `From` address in lowercase.
+    pub fn sender(&self) -> Option<&str> {
+        self.sender.as_deref()
+    }
+
+    /// Who the organizers say sends the email on their behalf, such as the person
+    /// behind a shared calendar. Anyone can say so, so this is only worth anything for
+    /// a new event.
+    pub fn delegates(&self) -> &[String] {
+        &self.delegates
+    }
+
+    /// Where the email was routed, by all the plus-addresses seen so far.
+    pub fn routing(&self) -> &Routing {
+        &self.routing
+    }
+
+    /// Whether the email holds nothing for the calendar.
+    pub fn is_empty(&self) -> bool {
+        self.event_requests.is_empty()
+            && self.published.is_empty()
+            && self.cancellations.is_empty()
+            && self.replies.is_empty()
+            && self.counters.is_empty()
+            && self.declined_counters.is_empty()
+    }
+
+    /// The events that the email asks to add to the calendar.
+    pub fn event_requests(&self) -> &[Event] {
+        &self.event_requests
+    }
+
+    /// Events that are published for information; nobody is asked to attend them.
+    pub fn published(&self) -> &[Event] {
+        &self.published
+    }
+
+    /// Events (or occurrences) that the organizer called off.
+    pub fn cancellations(&self) -> &[EventRef] {
+        &self.cancellations
+    }
+
+    pub fn replies(&self) -> &[Reply] {
+        &self.replies
+    }
+
+    pub fn counters(&self) -> &[Counter] {
+        &self.counters
+    }
+
+    /// Events whose counter proposals the organizer turned down.
+    pub fn declined_counters(&self) -> &[EventRef] {
+        &self.declined_counters
+    }
+
     fn parse_calendar_invite(&mut self, message: &Message<'_>, ics: &[u8]) -> anyhow::Result<()> {
         let ics = String::from_utf8(ics.to_owned())?;
-        let ics = icalendar::parser::unfold(&ics);
+        let ics = unfold(&ics);
         let calendar = match icalendar::parser::read_calendar(&ics) {
             Ok(c) => c,
             Err(e) => {
@@ -39,112 +204,221 @@ impl CalendarEmail {
             anyhow::bail!("Calendar request did not contain METHOD");
         };
 
+        let zones = Zones::from_components(&calendar.components);
+        let vevents = calendar
+            .components
+            .iter()
+            .filter(|component| component.name == "VEVENT");
+        for delegate in vevents
+            .clone()
+            .filter_map(|vevent| vevent.organizer_sent_by())
+        {
+            let delegate = delegate.to_lowercase();
+            if !self.delegates.contains(&delegate) {
+                self.delegates.push(delegate);
+            }
+        }
         match &*method {
-            "REQUEST" => self.parse_calendar_request(message, calendar),
-
+            "REQUEST" => {
+                let events = self.parse_calendar_request(message, &calendar);
+                self.event_requests.extend(events);
+            }
+            "PUBLISH" => {
+                let events = self.parse_calendar_request(message, &calendar);
+                self.published.extend(events);
+            }
+            "CANCEL" => {
+                let cancellations =
+                    vevents.filter_map(|vevent| EventRef::from_component(vevent, &zones));
+                self.cancellations.extend(cancellations);
+            }
+            "REPLY" => {
+                for vevent in vevents {
+                    let Some(event) = EventRef::from_component(vevent, &zones) else {
+                        continue;
+                    };
+                    let attendees = vevent.attendees();
+                    self.replies.push(Reply { event, attendees });
+                }
+            }
+            "COUNTER" => {
+                for vevent in vevents {
+                    let (Some(event), Some(proposal)) = (
+                        EventRef::from_component(vevent, &zones),
+                        event_from_component(vevent, &zones),
+                    ) else {
+                        continue;
+                    };
+                    // The proposal is made by its (only) attendee, or else the sender
+                    let from = message
+                        .from()
+                        .and_then(|address| address.first()?.address());
+                    let attendee = vevent.attendees().into_iter().next();
+                    let Some(attendee) = attendee
+                        .map(|attendee| attendee.email)
+                        .or(from.map(str::to_string))
+                    else {
+                        continue;
+                    };
+                    self.counters.push(Counter {
+                        event,
+                        attendee,
+                        start: proposal.start,
+                        end: proposal.end,
+                        comment: vevent.text("COMMENT"),
+                    });
+                }
+            }
+            "DECLINECOUNTER" => {
+                let declined =
+                    vevents.filter_map(|vevent| EventRef::from_component(vevent, &zones));
+                self.declined_counters.extend(declined);
+            }
             _ => {
                 anyhow::bail!("Calendar request with unknown method: `{method}`");
             }
         }
+        Ok(())
     }
 
+    /// The events of a request or publication, to be added to the calendar.
     fn parse_calendar_request(
-        &mut self,
+        &self,
         message: &Message<'_>,
-        request: icalendar::parser::Calendar,
-    ) -> anyhow::Result<()> {
-        // This is a "request to add to the calendar". We will accept it.
+        request: &icalendar::parser::Calendar,
+    ) -> Vec<Event> {
         for component in &request.components {
-            match component.name.as_str() {
-                "VEVENT" => {
-                    let event = self.event_from_request(message, component)?;
-                    self.event_requests.push(event);
-                }
-                _ => {
-                    info!("unexpected calendar component type, ignoring: {component:?}");
-                }
+            if !["VEVENT", "VTIMEZONE"].contains(&component.name.as_str()) {
+                info!("unexpected calendar component type, ignoring: {component:?}");
             }
         }
 
-        Ok(())
+        let mut events = events_from_calendar(request);
+        for event in &mut events {
+            // Fall back to the sender for invites without an ORGANIZER
+            if event.creator_email.is_empty() {
+                if let Some(from) = message.from().and_then(|address| address.first()) {
+                    event.creator_email = from.address().unwrap_or_default().to_string();
+                    event.creator_name = from.name().unwrap_or_default().to_string();
+                }
+            }
+        }
+        events
     }
+}
 
-    fn event_from_request(
-        &mut self,
-        message: &Message<'_>,
-        event: &icalendar::parser::Component<'_>,
-    ) -> anyhow::Result<Event> {
-        let summary = event.property("SUMMARY").unwrap_or_default();
-        let description = event.property("DESCRIPTION");
-        let location = event.property("LOCATION");
-        let creator = message
-            .from()
-            .and_then(|address| {
-                let email = address.first()?;
-                Some(Creator {
-                    email: email.address().map(|s| s.to_string()),
-                    display_name: email.name().map(|s| s.to_string()),
-                })
-            })
-            .unwrap_or_default();
-        let start = event.property("DTSTART").map(|s| EventDate {
-            date_time: Some(s),
-            date: None,
-            time_zone: None,
-        });
-        let end = event.property("DTEND").map(|s| EventDate {
-            date_time: Some(s),
-            date: None,
-            time_zone: None,
-        });
-
-        Ok(Event {
-            summary: summary,
-            description: description,
-            location: location,
-            creator,
-            start,
-            end,
-            original_start_time: None,
-            recurring_event_id: None,
-        })
-    }
+/// The plus-address tags of every `To`, `Cc` and `Delivered-To` recipient at
+/// `calendar_address`. `Delivered-To` is added by the receiving MTA and catches the
+/// address of a `Bcc`.
+fn recipient_tags(message: &Message<'_>, calendar_address: &str) -> Vec<String> {
+    let listed = [message.to(), message.cc()]
+        .into_iter()
+        .flatten()
+        .flat_map(|recipients| recipients.iter())
+        .filter_map(|recipient| recipient.address());
+    let delivered_to = message
+        .headers()
+        .iter()
+        .filter(|header| header.name.as_str().eq_ignore_ascii_case("Delivered-To"))
+        .filter_map(|header| header.value.as_text())
+        .map(|address| address.trim().trim_start_matches('<').trim_end_matches('>'));
+    listed
+        .chain(delivered_to)
+        .filter_map(|address| teams::plus_tags(address, calendar_address))
+        .flatten()
+        .collect()
 }
 
 #[test]
-#[ignore = "FIXME"]
 fn test_parse_email() {
     let input = include_str!("../../test_data/invite.eml");
-    let calendar = CalendarEmail::parse_email(input).unwrap();
-    expect_test::expect![""].assert_debug_eq(&calendar);
-}
-
-trait CalendarExt {
-    fn property(&self, name: &str) -> Option<String>;
-}
-
-impl CalendarExt for icalendar::parser::Calendar<'_> {
-    fn property(&self, name: &str) -> Option<String> {
-        for property in &self.properties {
-            if property.name == name {
-                return Some(property.val.as_str().to_string());
-            }
+    let calendar = CalendarEmail::parse_email(input, None).unwrap();
+    expect_test::expect![[r#"
+        CalendarEmail {
+            event_requests: [
+                Event {
+                    id: "6v2ielbusc7s08p9ev6f40g4en@google.com",
+                    summary: "Lang team triage",
+                    description: Some(
+                        "-::~:~::~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~::~:~::-\nJoin with Google Meet: https://meet.google.com/pog-rcin-eot\n\nLearn more about Meet at: https://support.google.com/a/users/answer/9282720\n\nPlease do not edit this section.\n-::~:~::~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~:~::~:~::-",
+                    ),
+                    location: Some(
+                        "https://meet.jit.si/ferris-rules",
+                    ),
+                    url: None,
+                    creator_email: "b3dc920ccd55f1861b5b5e9f75c33b865c3761529ca3faa76d4a0a906782ed55@group.calendar.google.com",
+                    creator_name: "Americano Test Calendar",
+                    start: Zoned(
+                        2024-03-06T11:00:00EST,
+                    ),
+                    end: Zoned(
+                        2024-03-06T12:00:00EST,
+                    ),
+                    recurrence: Some(
+                        Recurrence {
+                            rule: Some(
+                                "FREQ=WEEKLY;BYDAY=WE",
+                            ),
+                            description: "Weekly on Wednesday",
+                            dates: [],
+                            exception_dates: [],
+                        },
+                    ),
+                    recurring_event_id: None,
+                    original_start: None,
+                    status: Some(
+                        "CONFIRMED",
+                    ),
+                    teams: [],
+                    sequence: 0,
+                    attendees: [
+                        Attendee {
+                            email: "niko@alum.mit.edu",
+                            name: Some(
+                                "niko@alum.mit.edu",
+                            ),
+                            status: "NEEDS-ACTION",
+                            role: Some(
+                                "REQ-PARTICIPANT",
+                            ),
+                        },
+                    ],
+                    visibility: Public,
+                    updated: None,
+                    redacted: false,
+                },
+            ],
+            published: [],
+            cancellations: [],
+            replies: [],
+            counters: [],
+            declined_counters: [],
+            routing: Routing {
+                teams: [],
+                private: false,
+            },
+            sender: Some(
+                "rust@nikomatsakis.com",
+            ),
+            delegates: [],
         }
-        None
-    }
-}
-
-trait ComponentExt {
-    fn property(&self, name: &str) -> Option<String>;
+    "#]]
+    .assert_debug_eq(&calendar);
 }
 
-impl ComponentExt for icalendar::parser::Component<'_> {
-    fn property(&self, name: &str) -> Option<String> {
-        for property in &self.properties {
-            if property.name == name {
-                return Some(property.val.as_str().to_string());
-            }
-        }
-        None
-    }
+#[test]
+fn test_recipient_teams() {
+    let input = include_str!("../../test_data/invite.eml").replacen(
+        "To: niko@alum.mit.edu",
+        "To: calendar+lang+private@example.org, niko@alum.mit.edu\nCc: calendar+Types@example.org\n\
+         Delivered-To: calendar+compiler@example.org",
+        1,
+    );
+    let calendar = CalendarEmail::parse_email(&input, Some("calendar@example.org")).unwrap();
+    assert_eq!(
+        calendar.event_requests[0].teams,
+        ["compiler", "lang", "types"]
+    );
+    assert_eq!(calendar.event_requests[0].visibility, Visibility::Private);
+    assert!(calendar.routing().private);
 }
@@ -0,0 +1,186 @@
+//! Selecting a page of events: the time window, sort order and cursor-based pagination
+//! behind the query parameters of `/api/events`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Months, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::calendar::{Event, Events};
+
+/// How many events a page has unless asked otherwise.
+pub const DEFAULT_LIMIT: usize = 250;
+/// The most events a page can have, the same as Google allows.
+pub const MAX_LIMIT: usize = 2500;
+
+/// Which events [`Calendar::events`](super::Calendar::events) returns.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventQuery {
+    /// Only events that end at or after this; defaults to now.
+    pub from: Option<DateTime<Utc>>,
+    /// Only events that start before this; defaults to a year after `from`.
+    pub to: Option<DateTime<Utc>>,
+    /// Page size, see [`DEFAULT_LIMIT`] and [`MAX_LIMIT`].
+    pub limit: Option<usize>,
+    /// Continue after the page that returned this as its `nextCursor`.
+    pub cursor: Option<Cursor>,
+    #[serde(default)]
+    pub sort: SortOrder,
+    /// Only events of one of these comma-separated teams.
+    pub team: Option<String>,
+}
+
+/// Events are sorted by start, then by id so that the order is total.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// The position of the last event of a page.
+///
+/// Pages continue from a position rather than an offset, so that events added or
+/// removed between requests don't make pages skip or repeat events.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cursor {
+    start: DateTime<Utc>,
+    id: String,
+}
+
+impl EventQuery {
+    /// The time window, with the defaults filled in.
+    pub fn window(&self) -> anyhow::Result<(DateTime<Utc>, DateTime<Utc>)> {
+        let from = self.from.unwrap_or_else(Utc::now);
+        let to = match self.to {
+            Some(to) => to,
+            None => from
+                .checked_add_months(Months::new(12))
+                .ok_or_else(|| anyhow::anyhow!("`from` is too far in the future"))?,
+        };
+        if to < from {
+            anyhow::bail!("`to` is before `from`");
+        }
+        Ok((from, to))
+    }
+
+    /// Whether `event` is tagged with one of the teams asked for, if any were.
+    pub fn matches_teams(&self, event: &Event) -> bool {
+        let Some(team) = &self.team else {
+            return true;
+        };
+        let teams = super::teams::teams_from_tags(team.split(','));
+        event.teams.iter().any(|team| teams.contains(team))
+    }
+
+    /// Sort `events` and cut out the page this query asks for.
+    pub fn paginate(&self, mut events: Vec<Event>) -> Events {
+        let key = |event: &Event| (event.start.instant(), event.id.clone());
+        events.sort_by_key(key);
+        if self.sort == SortOrder::Desc {
+            events.reverse();
+        }
+
+        if let Some(cursor) = &self.cursor {
+            let position = (cursor.start, cursor.id.clone());
+            events.retain(|event| match self.sort {
+                SortOrder::Asc => key(event) > position,
+                SortOrder::Desc => key(event) < position,
+            });
+        }
+
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let next_cursor = (events.len() > limit).then(|| {
+            let last = &events[limit - 1];
+            Cursor {
+                start: last.start.instant(),
+                id: last.id.clone(),
+            }
+        });
+        events.truncate(limit);
+        Events {
+            events,
+            next_cursor,
+        }
+    }
+}
+
+/// `<start as a Unix timestamp>_<event id>`; event ids may contain underscores, but
+/// timestamps don't.
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.start.timestamp(), self.id)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(cursor: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::anyhow!("invalid cursor `{cursor}`");
+        let (timestamp, id) = cursor.split_once('_').ok_or_else(invalid)?;
+        let start = DateTime::from_timestamp(timestamp.parse().map_err(|_| invalid())?, 0)
+            .ok_or_else(invalid)?;
+        Ok(Cursor {
+            start,
+            id: id.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = anyhow::Error;
+
+    fn try_from(cursor: String) -> anyhow::Result<Self> {
+        cursor.parse()
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        cursor.to_string()
+    }
+}
+
+#[test]
+fn test_paginate() {
+    let event = |id: &str, start: &str| Event {
+        id: id.to_string(),
+        start: super::EventTime::parse(start, None).unwrap(),
+        end: super::EventTime::parse(start, None).unwrap(),
+        ..Event::default()
+    };
+    let events = vec![
+        event("c", "2024-03-14T10:00:00Z"),
+        event("a", "2024-03-13T10:00:00Z"),
+        event("b", "2024-03-13T10:00:00Z"),
+        event("d", "2024-03-15T10:00:00Z"),
+    ];
+    let ids = |page: &Events| page.events.iter().map(|e| e.id.clone()).collect::<Vec<_>>();
+
+    let mut query = EventQuery {
+        limit: Some(3),
+        ..EventQuery::default()
+    };
+    let first = query.paginate(events.clone());
+    assert_eq!(ids(&first), ["a", "b", "c"]);
+    let cursor = first.next_cursor.unwrap();
+    assert_eq!(cursor.to_string(), "1710410400_c");
+
+    query.cursor = Some(cursor.to_string().parse().unwrap());
+    let second = query.paginate(events.clone());
+    assert_eq!(ids(&second), ["d"]);
+    assert_eq!(second.next_cursor, None);
+
+    let descending = EventQuery {
+        limit: Some(2),
+        sort: SortOrder::Desc,
+        cursor: Some("1710410400_c".parse().unwrap()),
+        ..EventQuery::default()
+    };
+    assert_eq!(ids(&descending.paginate(events)), ["b", "a"]);
+}
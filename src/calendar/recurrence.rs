@@ -0,0 +1,812 @@
+//! Recurring events (RFC 5545 `RRULE`, `RDATE` and `EXDATE`).
+//!
+//! Backends hand us recurring series as a single "master" event carrying a
+//! [`Recurrence`], plus one event per modified or cancelled occurrence (with
+//! `recurring_event_id` and `original_start_datetime` set). [`expand`] turns
+//! those into the concrete occurrences within a time window.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::calendar::{date_time, Event, EventTime};
+
+/// Upper bound on the number of periods we walk through for a single rule, so that a
+/// rule which never matches can't spin forever.
+const MAX_PERIODS: u32 = 10_000;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Recurrence {
+    /// The `RRULE` value, e.g. `FREQ=WEEKLY;BYDAY=WE`.
+    pub rule: Option<String>,
+    /// The rule in words, e.g. "Weekly on Wednesday".
+    pub description: String,
+    /// Extra occurrences (`RDATE`).
+    pub dates: Vec<EventTime>,
+    /// Occurrences that were removed (`EXDATE`).
+    pub exception_dates: Vec<EventTime>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A parsed `RRULE`. Only the parts that matter for day-granularity rules are
+/// supported; anything else (e.g. `FREQ=HOURLY`, `BYSETPOS`) is rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecurrenceRule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<EventTime>,
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+    by_month: Vec<u32>,
+}
+
+impl Recurrence {
+    /// Build a recurrence from iCalendar content lines such as `RRULE:FREQ=WEEKLY` or
+    /// `EXDATE;TZID=America/New_York:20240313T110000`, as found in Google's `recurrence`
+    /// field.
+    pub fn from_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        Self::from_lines_in(lines, date_time::zone)
+    }
+
+    /// Like [`Recurrence::from_lines`], with TZIDs looked up through `zone`, e.g. in the
+    /// `VTIMEZONE`s of the calendar the lines came from.
+    pub fn from_lines_in<'a>(
+        lines: impl IntoIterator<Item = &'a str>,
+        zone: impl Fn(&str) -> Option<Tz>,
+    ) -> Option<Self> {
+        let mut recurrence = Recurrence::default();
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let mut params = name.split(';');
+            let name = params.next().unwrap_or_default();
+            let tzid = params
+                .filter_map(|param| param.strip_prefix("TZID="))
+                .find_map(&zone);
+            match name {
+                "RRULE" => recurrence.rule = Some(value.to_string()),
+                "RDATE" => recurrence.dates.extend(date_list(value, tzid)),
+                "EXDATE" => recurrence.exception_dates.extend(date_list(value, tzid)),
+                _ => {}
+            }
+        }
+        if recurrence.rule.is_none() && recurrence.dates.is_empty() {
+            return None;
+        }
+        recurrence.describe();
+        Some(recurrence)
+    }
+
+    /// Fill in `description` from the rule and extra dates.
+    pub fn describe(&mut self) {
+        self.description = match self.rule.as_deref().map(RecurrenceRule::from_str) {
+            Some(Ok(rule)) => rule.describe(),
+            Some(Err(_)) => "Recurring".to_string(),
+            None => "On specific dates".to_string(),
+        };
+    }
+}
+
+fn date_list(value: &str, zone: Option<Tz>) -> impl Iterator<Item = EventTime> + '_ {
+    value
+        .split(',')
+        .filter_map(move |date| super::ical::event_time(date.trim(), zone))
+}
+
+impl FromStr for RecurrenceRule {
+    type Err = anyhow::Error;
+
+    fn from_str(rule: &str) -> anyhow::Result<Self> {
+        let mut frequency = None;
+        let mut parsed = RecurrenceRule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: vec![],
+            by_month_day: vec![],
+            by_month: vec![],
+        };
+
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let Some((key, value)) = part.split_once('=') else {
+                anyhow::bail!("malformed rule part `{part}`");
+            };
+            match key {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => anyhow::bail!("unsupported frequency `{value}`"),
+                    })
+                }
+                "INTERVAL" => parsed.interval = value.parse()?,
+                "COUNT" => parsed.count = Some(value.parse()?),
+                "UNTIL" => {
+                    parsed.until = Some(
+                        super::ical::event_time(value, None)
+                            .ok_or_else(|| anyhow::anyhow!("malformed UNTIL `{value}`"))?,
+                    )
+                }
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let split = day.len().saturating_sub(2);
+                        let (ordinal, weekday) = day.split_at(split);
+                        let ordinal = match ordinal {
+                            "" => None,
+                            ordinal => Some(ordinal.trim_start_matches('+').parse()?),
+                        };
+                        parsed.by_day.push((ordinal, weekday_from_str(weekday)?));
+                    }
+                }
+                "BYMONTHDAY" => {
+                    for day in value.split(',') {
+                        parsed.by_month_day.push(day.parse()?);
+                    }
+                }
+                "BYMONTH" => {
+                    for month in value.split(',') {
+                        parsed.by_month.push(month.parse()?);
+                    }
+                }
+                // We only support the default week start of Monday, which matters for
+                // weekly rules with an interval and several days; close enough.
+                "WKST" => {}
+                _ => anyhow::bail!("unsupported rule part `{key}`"),
+            }
+        }
+
+        match frequency {
+            Some(frequency) => Ok(RecurrenceRule {
+                frequency,
+                ..parsed
+            }),
+            None => anyhow::bail!("rule `{rule}` has no FREQ"),
+        }
+    }
+}
+
+fn weekday_from_str(day: &str) -> anyhow::Result<Weekday> {
+    Ok(match day {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => anyhow::bail!("unknown weekday `{day}`"),
+    })
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+fn ordinal_name(ordinal: i32) -> String {
+    match ordinal {
+        1 => "first".to_string(),
+        2 => "second".to_string(),
+        3 => "third".to_string(),
+        4 => "fourth".to_string(),
+        5 => "fifth".to_string(),
+        -1 => "last".to_string(),
+        -2 => "second to last".to_string(),
+        n if n < 0 => format!("{} to last", ordinal_name(-n)),
+        n => {
+            let suffix = match (n % 10, n % 100) {
+                (1, 11) | (2, 12) | (3, 13) => "th",
+                (1, _) => "st",
+                (2, _) => "nd",
+                (3, _) => "rd",
+                _ => "th",
+            };
+            format!("{n}{suffix}")
+        }
+    }
+}
+
+fn month_name(month: u32) -> String {
+    NaiveDate::from_ymd_opt(2000, month, 1)
+        .map(|date| date.format("%B").to_string())
+        .unwrap_or_else(|| month.to_string())
+}
+
+fn join_words(words: Vec<String>) -> String {
+    match words.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {last}", rest.join(", ")),
+    }
+}
+
+impl RecurrenceRule {
+    /// Describe the rule in words, e.g. "Every 2 weeks on Monday and Thursday, 10 times".
+    pub fn describe(&self) -> String {
+        let (one, many) = match self.frequency {
+            Frequency::Daily => ("Daily", "days"),
+            Frequency::Weekly => ("Weekly", "weeks"),
+            Frequency::Monthly => ("Monthly", "months"),
+            Frequency::Yearly => ("Yearly", "years"),
+        };
+        let mut description = if self.interval == 1 {
+            one.to_string()
+        } else {
+            format!("Every {} {many}", self.interval)
+        };
+
+        let days: Vec<String> = self
+            .by_day
+            .iter()
+            .map(|(ordinal, day)| match ordinal {
+                Some(ordinal) => format!("the {} {}", ordinal_name(*ordinal), weekday_name(*day)),
+                None => weekday_name(*day).to_string(),
+            })
+            .collect();
+        if !days.is_empty() {
+            write!(description, " on {}", join_words(days)).unwrap();
+        }
+        if !self.by_month_day.is_empty() {
+            let days = self
+                .by_month_day
+                .iter()
+                .map(|&day| match day {
+                    -1 => "the last day".to_string(),
+                    day => format!("day {day}"),
+                })
+                .collect();
+            write!(description, " on {}", join_words(days)).unwrap();
+        }
+        if !self.by_month.is_empty() {
+            let months = self
+                .by_month
+                .iter()
+                .map(|month| month_name(*month))
+                .collect();
+            write!(description, " in {}", join_words(months)).unwrap();
+        }
+        if let Some(count) = self.count {
+            write!(description, ", {count} times").unwrap();
+        }
+        if let Some(until) = self.until {
+            let until = until.local().date();
+            write!(description, ", until {}", until.format("%B %-d, %Y")).unwrap();
+        }
+        description
+    }
+
+    /// The start times of all occurrences starting at `dtstart`, in order, stopping at
+    /// the first one after `limit`. Occurrences keep the wall-clock time of `dtstart`
+    /// in its zone, also across DST changes.
+    fn occurrences(&self, dtstart: &EventTime, limit: DateTime<Utc>) -> Vec<EventTime> {
+        let local_start = dtstart.local();
+        let mut occurrences = vec![];
+        let mut count = 0;
+
+        for period in 0..MAX_PERIODS {
+            let Some(candidates) = self.period(local_start.date(), period) else {
+                return occurrences;
+            };
+            for date in candidates {
+                let local = date.and_time(local_start.time());
+                if local < local_start {
+                    continue;
+                }
+                let occurrence = dtstart.with_local(local);
+                if occurrence.instant() > limit || self.is_after_until(&occurrence) {
+                    return occurrences;
+                }
+                occurrences.push(occurrence);
+                count += 1;
+                if self.count.is_some_and(|max| count >= max) {
+                    return occurrences;
+                }
+            }
+        }
+        tracing::warn!(
+            "stopped expanding `{}` from {dtstart} after {MAX_PERIODS} periods",
+            self.describe()
+        );
+        occurrences
+    }
+
+    /// UNTIL is an instant when it is in UTC, and otherwise a local time or date.
+    fn is_after_until(&self, occurrence: &EventTime) -> bool {
+        match self.until {
+            None => false,
+            Some(EventTime::Date(until)) => occurrence.local().date() > until,
+            Some(until @ EventTime::Zoned(_)) => occurrence.instant() > until.instant(),
+            Some(EventTime::Floating(until)) => occurrence.local() > until,
+        }
+    }
+
+    /// The matching days in the `n`th period (day, week, month or year) after `start`.
+    fn period(&self, start: NaiveDate, n: u32) -> Option<Vec<NaiveDate>> {
+        let step = n.checked_mul(self.interval)?;
+        let mut days = match self.frequency {
+            Frequency::Daily => {
+                let day = start.checked_add_signed(Duration::days(step.into()))?;
+                vec![day]
+                    .into_iter()
+                    .filter(|day| self.matches_day(day))
+                    .collect()
+            }
+            Frequency::Weekly => {
+                let monday = start.week(Weekday::Mon).first_day();
+                let monday = monday.checked_add_signed(Duration::weeks(step.into()))?;
+                let weekdays: Vec<Weekday> = match self.by_day.is_empty() {
+                    true => vec![start.weekday()],
+                    false => self.by_day.iter().map(|(_, day)| *day).collect(),
+                };
+                (0..7)
+                    .map(|offset| monday + Duration::days(offset))
+                    .filter(|day| weekdays.contains(&day.weekday()))
+                    .filter(|day| self.by_month.is_empty() || self.by_month.contains(&day.month()))
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let first = start.with_day(1)?.checked_add_months(Months::new(step))?;
+                if !self.by_month.is_empty() && !self.by_month.contains(&first.month()) {
+                    return Some(vec![]);
+                }
+                self.days_in_month(first, start.day())
+            }
+            Frequency::Yearly => {
+                let year = start.year() + i32::try_from(step).ok()?;
+                let first = NaiveDate::from_ymd_opt(year, 1, 1)?;
+                let months = match (self.by_month.is_empty(), self.by_month_day.is_empty()) {
+                    (false, _) => self.by_month.clone(),
+                    // BYDAY alone picks days of the whole year, e.g. `20MO` is its 20th
+                    // Monday
+                    (true, true) if !self.by_day.is_empty() => {
+                        return Some(self.matching_days(first, first + Months::new(12), 0));
+                    }
+                    // BYMONTHDAY alone picks that day of every month
+                    (true, false) => (1..=12).collect(),
+                    (true, true) => vec![start.month()],
+                };
+                let mut days = vec![];
+                for month in months {
+                    if let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) {
+                        days.extend(self.days_in_month(first, start.day()));
+                    }
+                }
+                days
+            }
+        };
+        days.sort();
+        days.dedup();
+        Some(days)
+    }
+
+    fn matches_day(&self, day: &NaiveDate) -> bool {
+        (self.by_month.is_empty() || self.by_month.contains(&day.month()))
+            && (self.by_month_day.is_empty() || self.by_month_day.contains(&(day.day() as i32)))
+            && (self.by_day.is_empty() || self.by_day.iter().any(|(_, d)| *d == day.weekday()))
+    }
+
+    /// Days of the month starting at `first` that match BYMONTHDAY and BYDAY, defaulting
+    /// to the same day of the month as the series start.
+    fn days_in_month(&self, first: NaiveDate, default_day: u32) -> Vec<NaiveDate> {
+        self.matching_days(first, first + Months::new(1), default_day)
+    }
+
+    /// Days from `first` until `end` that match BYMONTHDAY and BYDAY, or are
+    /// `default_day` of the month. Ordinals in BYDAY count within the span, and
+    /// BYMONTHDAY is only used for spans of a month.
+    fn matching_days(&self, first: NaiveDate, end: NaiveDate, default_day: u32) -> Vec<NaiveDate> {
+        let all: Vec<NaiveDate> = first.iter_days().take_while(|day| *day < end).collect();
+        let length = all.len() as i32;
+
+        let by_month_day = |day: &NaiveDate| {
+            self.by_month_day.iter().any(|&wanted| {
+                let wanted = if wanted < 0 {
+                    length + wanted + 1
+                } else {
+                    wanted
+                };
+                wanted == day.day() as i32
+            })
+        };
+        let by_day = |day: &NaiveDate| {
+            self.by_day.iter().any(|(ordinal, weekday)| {
+                if day.weekday() != *weekday {
+                    return false;
+                }
+                let Some(ordinal) = ordinal else {
+                    return true;
+                };
+                let index = (*day - first).num_days() as i32;
+                let nth = index / 7 + 1;
+                let nth_from_end = -((length - index - 1) / 7 + 1);
+                *ordinal == nth || *ordinal == nth_from_end
+            })
+        };
+
+        all.into_iter()
+            .filter(
+                |day| match (self.by_month_day.is_empty(), self.by_day.is_empty()) {
+                    (true, true) => day.day() == default_day,
+                    (false, true) => by_month_day(day),
+                    (true, false) => by_day(day),
+                    (false, false) => by_month_day(day) && by_day(day),
+                },
+            )
+            .collect()
+    }
+}
+
+/// `time` expressed the way `like` is: in its zone if both are timed, and otherwise at
+/// the same wall-clock time.
+fn like(like: &EventTime, time: &EventTime) -> EventTime {
+    match (like, time) {
+        (EventTime::Zoned(like), EventTime::Zoned(time)) => {
+            EventTime::Zoned(time.with_timezone(&like.timezone()))
+        }
+        (like, time) => like.with_local(time.local()),
+    }
+}
+
+/// Identifier of one occurrence of a series, following Google's convention of
+/// `<series id>_<original start in UTC>`.
+pub fn instance_id(series_id: &str, original_start: &DateTime<Utc>) -> String {
+    format!("{series_id}_{}", super::ical::format_utc(original_start))
+}
+
+/// The series and original start that an [`instance_id`] is made of, if `id` looks
+/// like one.
+pub fn parse_instance_id(id: &str) -> Option<(&str, DateTime<Utc>)> {
+    let (series_id, start) = id.rsplit_once('_')?;
+    let start = NaiveDateTime::parse_from_str(start, "%Y%m%dT%H%M%SZ").ok()?;
+    Some((series_id, start.and_utc()))
+}
+
+/// Expand recurring series into their occurrences between `from` and `to`.
+///
+/// `events` may contain single events, series masters and per-occurrence overrides.
+/// Overrides replace the occurrence they were split from; cancelled ones remove it.
+/// Cancelled events are left out, and so is every occurrence of a cancelled series.
+pub fn expand(events: Vec<Event>, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Event> {
+    let overlaps = |event: &Event| event.start.instant() < to && event.end.instant() >= from;
+
+    // Overrides, keyed by the series and occurrence they replace
+    let mut overrides: HashMap<(String, DateTime<Utc>), Event> = HashMap::new();
+    let mut masters = vec![];
+    let mut output = vec![];
+    for event in events {
+        match (&event.recurring_event_id, event.original_start) {
+            (Some(series), Some(original)) => {
+                overrides.insert((series.clone(), original.instant()), event);
+            }
+            _ if event.recurrence.is_some() => masters.push(event),
+            _ => {
+                if !event.is_cancelled() && overlaps(&event) {
+                    output.push(event);
+                }
+            }
+        }
+    }
+
+    for master in masters {
+        if master.is_cancelled() {
+            overrides.retain(|(series, _), _| *series != master.id);
+            continue;
+        }
+        for occurrence in occurrences(&master, to) {
+            let original = occurrence.start.instant();
+            let occurrence = match overrides.remove(&(master.id.clone(), original)) {
+                Some(mut replaced) => {
+                    if replaced.recurrence.is_none() {
+                        replaced.recurrence = master.recurrence.clone();
+                    }
+                    replaced
+                }
+                None => occurrence,
+            };
+            if !occurrence.is_cancelled() && overlaps(&occurrence) {
+                output.push(occurrence);
+            }
+        }
+    }
+
+    // Overrides for occurrences we didn't generate (e.g. the master is outside the
+    // window, or a backend that already expands) are shown as they are
+    output.extend(
+        overrides
+            .into_values()
+            .filter(|event| !event.is_cancelled() && overlaps(event)),
+    );
+
+    output.sort_by_key(|event| (event.start.instant(), event.id.clone()));
+    output
+}
+
+/// The occurrences of a series master that start no later than `limit`.
+fn occurrences(master: &Event, limit: DateTime<Utc>) -> Vec<Event> {
+    let Some(recurrence) = &master.recurrence else {
+        return vec![];
+    };
+    let mut starts = match recurrence.rule.as_deref().map(RecurrenceRule::from_str) {
+        Some(Ok(rule)) => rule.occurrences(&master.start, limit),
+        Some(Err(e)) => {
+            tracing::info!("not expanding `{}`: {e}", master.id);
+            vec![master.start]
+        }
+        None => vec![master.start],
+    };
+    starts.extend(
+        recurrence
+            .dates
+            .iter()
+            .map(|date| like(&master.start, date)),
+    );
+    let excluded: Vec<DateTime<Utc>> = recurrence
+        .exception_dates
+        .iter()
+        .map(|date| like(&master.start, date).instant())
+        .collect();
+    starts.retain(|start| !excluded.contains(&start.instant()));
+    starts.sort_by_key(EventTime::instant);
+    starts.dedup();
+
+    starts
+        .into_iter()
+        .map(|start| occurrence_at(master, start))
+        .collect()
+}
+
+/// The occurrence of a series master that originally starts at `start`, as it would be
+/// if it wasn't changed on its own.
+pub fn occurrence_at(master: &Event, start: EventTime) -> Event {
+    let start = like(&master.start, &start);
+    let duration = master.end.instant() - master.start.instant();
+    Event {
+        id: instance_id(&master.id, &start.instant()),
+        recurring_event_id: Some(master.id.clone()),
+        original_start: Some(start),
+        start,
+        end: like(&master.end, &start.shifted(duration)),
+        ..master.clone()
+    }
+}
+
+#[cfg(test)]
+fn weekly_triage() -> Event {
+    Event {
+        id: "triage".to_string(),
+        summary: "Lang team triage".to_string(),
+        start: new_york("2024-03-06T11:00:00"),
+        end: new_york("2024-03-06T12:00:00"),
+        recurrence: Recurrence::from_lines(["RRULE:FREQ=WEEKLY;BYDAY=WE"]),
+        ..Event::default()
+    }
+}
+
+#[cfg(test)]
+fn new_york(local: &str) -> EventTime {
+    EventTime::parse(local, Some("America/New_York")).unwrap()
+}
+
+#[cfg(test)]
+fn starts(events: &[Event]) -> Vec<String> {
+    events.iter().map(|e| e.start.to_string()).collect()
+}
+
+#[test]
+fn test_describe() {
+    let describe = |rule: &str| RecurrenceRule::from_str(rule).unwrap().describe();
+    expect_test::expect![[r#"
+        [
+            "Weekly on Wednesday",
+            "Every 2 weeks on Monday and Thursday, 10 times",
+            "Monthly on the second Tuesday",
+            "Monthly on the last day, until March 13, 2025",
+            "Yearly in March and November",
+        ]
+    "#]]
+    .assert_debug_eq(&[
+        describe("FREQ=WEEKLY;BYDAY=WE"),
+        describe("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=10"),
+        describe("FREQ=MONTHLY;BYDAY=2TU"),
+        describe("FREQ=MONTHLY;BYMONTHDAY=-1;UNTIL=20250313T160000Z"),
+        describe("FREQ=YEARLY;BYMONTH=3,11"),
+    ]);
+}
+
+#[test]
+fn test_expand_weekly_with_exceptions() {
+    let mut master = weekly_triage();
+    master
+        .recurrence
+        .as_mut()
+        .unwrap()
+        .exception_dates
+        .push(new_york("2024-03-13T11:00:00"));
+
+    // The occurrence on the 20th moved an hour later, the one on the 27th was cancelled
+    let moved = Event {
+        id: "triage_20240320T150000Z".to_string(),
+        recurring_event_id: Some("triage".to_string()),
+        original_start: Some(new_york("2024-03-20T11:00:00")),
+        start: new_york("2024-03-20T12:00:00"),
+        end: new_york("2024-03-20T13:00:00"),
+        ..weekly_triage()
+    };
+    let cancelled = Event {
+        id: "triage_20240327T150000Z".to_string(),
+        recurring_event_id: Some("triage".to_string()),
+        original_start: Some(new_york("2024-03-27T11:00:00")),
+        status: Some("CANCELLED".to_string()),
+        ..weekly_triage()
+    };
+
+    let from = "2024-03-01T00:00:00Z".parse().unwrap();
+    let to = "2024-04-10T00:00:00Z".parse().unwrap();
+    let events = expand(vec![master, moved, cancelled], from, to);
+    expect_test::expect![[r#"
+        [
+            "2024-03-06T11:00:00-05:00",
+            "2024-03-20T12:00:00-04:00",
+            "2024-04-03T11:00:00-04:00",
+        ]
+    "#]]
+    .assert_debug_eq(&starts(&events));
+    assert_eq!(events[0].id, "triage_20240306T160000Z");
+    assert_eq!(
+        parse_instance_id(&events[0].id),
+        Some(("triage", events[0].start.instant()))
+    );
+    assert_eq!(parse_instance_id("triage"), None);
+    assert_eq!(events[1].recurrence, weekly_triage().recurrence);
+}
+
+#[test]
+fn test_expand_rules() {
+    let expand_rule = |rule: &str, start: &str| {
+        let start = EventTime::parse(start, None).unwrap();
+        let master = Event {
+            start,
+            end: start,
+            recurrence: Recurrence::from_lines([rule]),
+            ..weekly_triage()
+        };
+        let from = "2024-01-01T00:00:00Z".parse().unwrap();
+        let to = "2025-01-01T00:00:00Z".parse().unwrap();
+        expand(vec![master], from, to)
+            .into_iter()
+            .map(|e| e.start.to_string())
+            .take(4)
+            .collect::<Vec<_>>()
+    };
+
+    expect_test::expect![[r#"
+        [
+            [
+                "2024-01-09T15:00:00Z",
+                "2024-02-13T15:00:00Z",
+                "2024-03-12T15:00:00Z",
+                "2024-04-09T15:00:00Z",
+            ],
+            [
+                "2024-01-31T09:00:00Z",
+                "2024-02-29T09:00:00Z",
+                "2024-03-31T09:00:00Z",
+            ],
+            [
+                "2024-01-01T09:00:00Z",
+                "2024-01-04T09:00:00Z",
+                "2024-01-15T09:00:00Z",
+                "2024-01-18T09:00:00Z",
+            ],
+            [
+                "2024-03-03T02:00:00Z",
+                "2024-11-03T02:00:00Z",
+            ],
+        ]
+    "#]]
+    .assert_debug_eq(&[
+        expand_rule("RRULE:FREQ=MONTHLY;BYDAY=2TU", "2024-01-09T15:00:00Z"),
+        expand_rule(
+            "RRULE:FREQ=MONTHLY;BYMONTHDAY=-1;UNTIL=20240401T000000Z",
+            "2024-01-31T09:00:00Z",
+        ),
+        expand_rule(
+            "RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=10",
+            "2024-01-01T09:00:00Z",
+        ),
+        expand_rule(
+            "RRULE:FREQ=YEARLY;BYMONTH=3,11;BYDAY=1SU",
+            "2024-03-03T02:00:00Z",
+        ),
+    ]);
+}
+
+#[test]
+fn test_expand_yearly_by_day() {
+    let expand_rule = |rule: &str, start: &str| {
+        let start = EventTime::parse(start, None).unwrap();
+        let master = Event {
+            start,
+            end: start,
+            recurrence: Recurrence::from_lines([rule]),
+            ..weekly_triage()
+        };
+        let from = "2024-01-01T00:00:00Z".parse().unwrap();
+        let to = "2027-01-01T00:00:00Z".parse().unwrap();
+        let describe = master.recurrence.as_ref().unwrap().description.clone();
+        let starts = expand(vec![master], from, to)
+            .into_iter()
+            .map(|e| e.start.to_string())
+            .take(3)
+            .collect::<Vec<_>>();
+        (describe, starts)
+    };
+
+    // Without BYMONTH, days and their ordinals are counted in the whole year
+    expect_test::expect![[r#"
+        [
+            (
+                "Yearly on the 20th Monday",
+                [
+                    "2024-05-13T09:00:00Z",
+                    "2025-05-19T09:00:00Z",
+                    "2026-05-18T09:00:00Z",
+                ],
+            ),
+            (
+                "Yearly on the last Friday",
+                [
+                    "2024-12-27T09:00:00Z",
+                    "2025-12-26T09:00:00Z",
+                    "2026-12-25T09:00:00Z",
+                ],
+            ),
+            (
+                "Yearly on Monday",
+                [
+                    "2024-01-01T09:00:00Z",
+                    "2024-01-08T09:00:00Z",
+                    "2024-01-15T09:00:00Z",
+                ],
+            ),
+            (
+                "Yearly on day 15",
+                [
+                    "2024-01-15T09:00:00Z",
+                    "2024-02-15T09:00:00Z",
+                    "2024-03-15T09:00:00Z",
+                ],
+            ),
+        ]
+    "#]]
+    .assert_debug_eq(&[
+        expand_rule("RRULE:FREQ=YEARLY;BYDAY=20MO", "2024-05-13T09:00:00Z"),
+        expand_rule("RRULE:FREQ=YEARLY;BYDAY=-1FR", "2024-12-27T09:00:00Z"),
+        expand_rule("RRULE:FREQ=YEARLY;BYDAY=MO", "2024-01-01T09:00:00Z"),
+        expand_rule("RRULE:FREQ=YEARLY;BYMONTHDAY=15", "2024-01-15T09:00:00Z"),
+    ]);
+}
@@ -0,0 +1,146 @@
+//! Teams and the tags that put events on their calendars.
+//!
+//! An event is tagged with teams in one of three ways: by inviting a plus-address of
+//! the calendar (`calendar+lang+compiler@example.org`), through iCalendar `CATEGORIES`,
+//! or through a field of the backend (the shared `teams` extended property in Google
+//! Calendar).
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Team {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// Tags that change how an event is handled, rather than naming a team.
+pub const RESERVED_TAGS: &[&str] = &["private"];
+
+/// The canonical form of a team name: lowercase, with spaces as dashes. Returns `None`
+/// for tags with characters that can't appear in an email address.
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase().replace(' ', "-");
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.';
+    (!tag.is_empty() && tag.chars().all(valid)).then_some(tag)
+}
+
+/// The tags of a plus-address of `mailbox`, e.g. `["lang", "private"]` for
+/// `calendar+lang+private@example.org` when the mailbox is `calendar@example.org`.
+///
+/// Returns `None` for addresses of other mailboxes, and no tags for the mailbox itself.
+/// Tags that can't be team names are left out, and logged.
+pub fn plus_tags(address: &str, mailbox: &str) -> Option<Vec<String>> {
+    let (local, domain) = address.trim().rsplit_once('@')?;
+    let (mailbox_local, mailbox_domain) = mailbox.trim().rsplit_once('@')?;
+    if !domain.eq_ignore_ascii_case(mailbox_domain) {
+        return None;
+    }
+    let mut parts = local.split('+');
+    if !parts.next()?.eq_ignore_ascii_case(mailbox_local) {
+        return None;
+    }
+    let mut tags = vec![];
+    for part in parts {
+        match normalize(part) {
+            Some(tag) => tags.push(tag),
+            None => tracing::info!("ignoring tag `{part}` of {address}, which isn't a team name"),
+        }
+    }
+    Some(tags)
+}
+
+/// What the tags of plus-addresses ask for: which teams' calendars an event goes on, and
+/// whether it is private.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Routing {
+    pub teams: Vec<String>,
+    pub private: bool,
+}
+
+impl Routing {
+    pub fn from_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Self {
+        let tags: Vec<String> = tags.into_iter().filter_map(normalize).collect();
+        Routing {
+            teams: teams_from_tags(tags.iter().map(String::as_str)),
+            private: tags.iter().any(|tag| tag == "private"),
+        }
+    }
+
+    /// Add what `other` asks for.
+    pub fn merge(&mut self, other: &Routing) {
+        self.teams.extend(other.teams.iter().cloned());
+        self.teams = teams_from_tags(self.teams.iter().map(String::as_str));
+        self.private |= other.private;
+    }
+
+    /// The teams that aren't among `known`.
+    pub fn unknown_teams(&self, known: &[Team]) -> Vec<String> {
+        self.teams
+            .iter()
+            .filter(|team| {
+                !known
+                    .iter()
+                    .any(|known| normalize(&known.name).as_ref() == Some(*team))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// The teams among `tags`: normalized, without reserved tags, sorted and deduplicated.
+pub fn teams_from_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut teams: Vec<String> = tags
+        .into_iter()
+        .filter_map(normalize)
+        .filter(|tag| !RESERVED_TAGS.contains(&tag.as_str()))
+        .collect();
+    teams.sort();
+    teams.dedup();
+    teams
+}
+
+#[test]
+fn test_plus_tags() {
+    let mailbox = "calendar@example.org";
+    expect_test::expect![[r#"
+        [
+            Some(
+                [
+                    "lang",
+                    "compiler",
+                ],
+            ),
+            Some(
+                [
+                    "lang",
+                    "private",
+                ],
+            ),
+            Some(
+                [],
+            ),
+            None,
+            None,
+        ]
+    "#]]
+    .assert_debug_eq(&[
+        plus_tags("calendar+lang+compiler@example.org", mailbox),
+        plus_tags("Calendar+Lang+private@EXAMPLE.org", mailbox),
+        plus_tags("calendar@example.org", mailbox),
+        plus_tags("calendar+lang@example.com", mailbox),
+        plus_tags("niko+lang@example.org", mailbox),
+    ]);
+    assert_eq!(
+        teams_from_tags(["Lang", "private", "Compiler Team", "lang", "a/b"]),
+        ["compiler-team", "lang"]
+    );
+
+    let routing = Routing::from_tags(["Lang", "private", "types"]);
+    assert_eq!(routing.teams, ["lang", "types"]);
+    assert!(routing.private);
+    let known = [Team {
+        name: "lang".to_string(),
+        description: None,
+    }];
+    assert_eq!(routing.unknown_teams(&known), ["types"]);
+}
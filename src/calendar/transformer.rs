@@ -1,78 +1,303 @@
+use crate::calendar::teams::teams_from_tags;
+use crate::calendar::Attendee;
 use crate::calendar::Event;
-use crate::calendar::Events;
+use crate::calendar::EventKind;
+use crate::calendar::EventTime;
+use crate::calendar::Recurrence;
+use crate::calendar::Visibility;
 
 use super::google_calendar;
 
-pub fn google_to_americano(g_events: google_calendar::Events) -> Events {
-    let mut v: Vec<Event> = Vec::with_capacity(g_events.items.capacity());
-    for g_event in g_events.items.iter() {
-        // Some ad hoc validation
-        if g_event.start.is_none()
-            || g_event.start.as_ref().unwrap().date_time.is_none()
-            || g_event.end.is_none()
-            || g_event.end.as_ref().unwrap().date_time.is_none()
-            || g_event.creator.email.is_none()
-            || g_event.creator.display_name.is_none()
-        {
-            continue;
+/// What happened to each Google event that didn't convert cleanly.
+#[derive(Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    pub entries: Vec<Validation>,
+}
+
+/// The problems found with a single Google event.
+#[derive(Debug, PartialEq)]
+pub struct Validation {
+    pub event_id: String,
+    /// Whether the event was left out, rather than kept with a fallback value.
+    pub dropped: bool,
+    pub problems: Vec<String>,
+}
+
+impl ValidationReport {
+    fn record(&mut self, event_id: &str, dropped: bool, problems: Vec<String>) {
+        if problems.is_empty() {
+            return;
         }
+        self.entries.push(Validation {
+            event_id: event_id.to_string(),
+            dropped,
+            problems,
+        });
+    }
+
+    /// Log every entry, so that odd events in the calendar can be tracked down.
+    pub fn log(&self) {
+        for entry in &self.entries {
+            let problems = entry.problems.join("; ");
+            if entry.dropped {
+                tracing::warn!("dropped event `{}`: {problems}", entry.event_id);
+            } else {
+                tracing::info!(
+                    "imported event `{}` with fallbacks: {problems}",
+                    entry.event_id
+                );
+            }
+        }
+    }
+}
+
+pub fn google_to_americano(g_events: &[google_calendar::Event]) -> (Vec<Event>, ValidationReport) {
+    let mut report = ValidationReport::default();
+    let events = g_events
+        .iter()
+        .filter_map(|g_event| google_event_to_americano(g_event, &mut report))
+        .collect();
+
+    (events, report)
+}
 
-        // TODO
-        // do some useful transformations, like resolve recurrance dates
-
-        let start_datetime = g_event
-            .start
-            .as_ref()
-            .unwrap()
-            .date_time
-            .as_ref()
-            .unwrap()
-            .clone();
-        let end_datetime = g_event
-            .end
-            .as_ref()
-            .unwrap()
-            .date_time
-            .as_ref()
-            .unwrap()
-            .clone();
-
-        let start_timezone = g_event
-            .start
-            .as_ref()
-            .unwrap()
-            .time_zone
-            .as_ref()
-            .unwrap()
-            .clone();
-        let end_timezone = g_event
-            .end
-            .as_ref()
-            .unwrap()
-            .time_zone
-            .as_ref()
-            .unwrap()
-            .clone();
-
-        let creator_email = g_event.creator.email.as_ref().unwrap().clone();
-        let creator_name = g_event.creator.display_name.as_ref().unwrap().clone();
-
-        let recurrance = g_event.recurring_event_id.is_some();
-
-        let event = Event {
-            summary: g_event.summary.clone(),
-            description: g_event.description.clone(),
-            location: g_event.location.clone(),
-            creator_email: creator_email,
-            creator_name: creator_name,
-            start_datetime: start_datetime,
-            start_timezone: start_timezone,
-            end_datetime: end_datetime,
-            end_timezone: end_timezone,
-            recurrence: recurrance,
+/// Convert a Google event, noting in `report` anything that had to be filled in or
+/// that made the event unusable.
+pub fn google_event_to_americano(
+    g_event: &google_calendar::Event,
+    report: &mut ValidationReport,
+) -> Option<Event> {
+    let mut problems = vec![];
+    let event = convert(g_event, &mut problems);
+    report.record(&g_event.id, event.is_none(), problems);
+    event
+}
+
+fn convert(g_event: &google_calendar::Event, problems: &mut Vec<String>) -> Option<Event> {
+    let status = g_event.status.as_ref().map(|status| status.to_uppercase());
+    let original_start = g_event
+        .original_start_time
+        .as_ref()
+        .and_then(|original| event_date(original, "originalStartTime", problems));
+
+    // A cancelled occurrence of a series only carries enough to identify the occurrence
+    if status.as_deref() == Some("CANCELLED") {
+        let Some(original) = original_start else {
+            problems.push("cancelled event has no original start".to_string());
+            return None;
         };
-        v.push(event);
+        return Some(Event {
+            id: g_event.id.clone(),
+            start: original,
+            end: original,
+            recurring_event_id: g_event.recurring_event_id.clone(),
+            original_start: Some(original),
+            status,
+            ..Event::default()
+        });
     }
 
-    Events { events: v }
+    let start = match &g_event.start {
+        Some(start) => event_date(start, "start", problems),
+        None => {
+            problems.push("start is missing".to_string());
+            None
+        }
+    };
+    let end = match &g_event.end {
+        Some(end) => event_date(end, "end", problems),
+        None => {
+            problems.push("end is missing".to_string());
+            None
+        }
+    };
+    let (start, end) = (start?, end?);
+    if (start.kind() == EventKind::AllDay) != (end.kind() == EventKind::AllDay) {
+        problems.push("start and end mix a date with a date-time".to_string());
+        return None;
+    }
+
+    let creator_email = g_event.creator.email.clone().unwrap_or_else(|| {
+        problems.push("creator has no email".to_string());
+        String::new()
+    });
+    let creator_name = g_event.creator.display_name.clone().unwrap_or_else(|| {
+        problems.push("creator has no display name, using the email".to_string());
+        creator_email.clone()
+    });
+
+    let recurrence = g_event
+        .recurrence
+        .as_ref()
+        .and_then(|lines| Recurrence::from_lines(lines.iter().map(String::as_str)));
+    let teams = g_event
+        .extended_properties
+        .as_ref()
+        .and_then(|properties| properties.shared.get("teams"))
+        .map(|teams| teams_from_tags(teams.split(',')))
+        .unwrap_or_default();
+
+    Some(Event {
+        id: g_event.id.clone(),
+        summary: g_event.summary.clone(),
+        description: g_event.description.clone(),
+        location: g_event.location.clone(),
+        url: None,
+        creator_email,
+        creator_name,
+        start,
+        end,
+        recurrence,
+        recurring_event_id: g_event.recurring_event_id.clone(),
+        original_start,
+        status,
+        teams,
+        sequence: g_event.sequence.unwrap_or_default(),
+        attendees: g_event.attendees.iter().filter_map(attendee).collect(),
+        visibility: match g_event.visibility.as_deref() {
+            Some("private" | "confidential") => Visibility::Private,
+            _ => Visibility::Public,
+        },
+        updated: None,
+        redacted: false,
+    })
+}
+
+/// A Google attendee in iCalendar terms; attendees without an email are left out.
+fn attendee(g_attendee: &google_calendar::Attendee) -> Option<Attendee> {
+    let status = match g_attendee.response_status.as_deref() {
+        Some("accepted") => "ACCEPTED",
+        Some("declined") => "DECLINED",
+        Some("tentative") => "TENTATIVE",
+        _ => "NEEDS-ACTION",
+    };
+    let role = match (g_attendee.organizer, g_attendee.optional) {
+        (true, _) => "CHAIR",
+        (false, true) => "OPT-PARTICIPANT",
+        (false, false) => "REQ-PARTICIPANT",
+    };
+    Some(Attendee {
+        email: g_attendee.email.clone()?,
+        name: g_attendee.display_name.clone(),
+        status: status.to_string(),
+        role: Some(role.to_string()),
+    })
+}
+
+/// A Google start or end as an [`EventTime`].
+///
+/// Google always gives timed events with an offset, and only sometimes names the zone.
+fn event_date(
+    date: &google_calendar::EventDate,
+    field: &str,
+    problems: &mut Vec<String>,
+) -> Option<EventTime> {
+    let parsed = match (&date.date_time, &date.date) {
+        (Some(date_time), _) => {
+            if date.time_zone.is_none() {
+                problems.push(format!("{field} has no time zone, showing it in UTC"));
+            }
+            EventTime::parse(date_time, date.time_zone.as_deref())
+        }
+        (None, Some(date)) => EventTime::parse(date, None),
+        (None, None) => {
+            problems.push(format!("{field} has neither a date nor a date-time"));
+            return None;
+        }
+    };
+    parsed
+        .map_err(|e| problems.push(format!("{field} is invalid: {e}")))
+        .ok()
+}
+
+#[test]
+fn test_validation_report() {
+    let g_events: google_calendar::Events = serde_json::from_str(
+        r#"{
+            "items": [
+                {
+                    "id": "offsite",
+                    "summary": "All hands offsite",
+                    "creator": { "email": "jane@example.org" },
+                    "start": { "date": "2024-03-13" },
+                    "end": { "date": "2024-03-15" }
+                },
+                {
+                    "id": "triage",
+                    "summary": "Triage",
+                    "creator": { "email": "niko@example.org", "displayName": "Niko" },
+                    "start": { "dateTime": "2024-03-13T11:00:00-04:00", "timeZone": "America/New_York" },
+                    "end": { "dateTime": "2024-03-13T12:00:00-04:00", "timeZone": "America/New_York" },
+                    "extendedProperties": { "shared": { "teams": "lang, Types" } }
+                },
+                {
+                    "id": "broken",
+                    "summary": "Broken",
+                    "start": { "date": "2024-03-13" },
+                    "end": {}
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (events, report) = google_to_americano(&g_events.items);
+    let summary: Vec<_> = events
+        .iter()
+        .map(|e| {
+            (
+                &e.id,
+                e.kind(),
+                e.start.to_string(),
+                e.end.to_string(),
+                e.is_multi_day(),
+                &e.teams,
+            )
+        })
+        .collect();
+    expect_test::expect![[r#"
+        [
+            (
+                "offsite",
+                AllDay,
+                "2024-03-13",
+                "2024-03-15",
+                true,
+                [],
+            ),
+            (
+                "triage",
+                Timed,
+                "2024-03-13T11:00:00-04:00",
+                "2024-03-13T12:00:00-04:00",
+                false,
+                [
+                    "lang",
+                    "types",
+                ],
+            ),
+        ]
+    "#]]
+    .assert_debug_eq(&summary);
+    expect_test::expect![[r#"
+        ValidationReport {
+            entries: [
+                Validation {
+                    event_id: "offsite",
+                    dropped: false,
+                    problems: [
+                        "creator has no display name, using the email",
+                    ],
+                },
+                Validation {
+                    event_id: "broken",
+                    dropped: true,
+                    problems: [
+                        "end has neither a date nor a date-time",
+                    ],
+                },
+            ],
+        }
+    "#]]
+    .assert_debug_eq(&report);
 }
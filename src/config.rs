@@ -1,11 +1,179 @@
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
+const CONFIG_FILE: &str = "americano.toml";
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Configuration {
+    #[serde(default)]
     pub google_api_key: String,
+    #[serde(default)]
     pub google_calendar_id: String,
+    #[serde(default)]
+    pub backend: BackendConfig,
+    #[serde(default)]
+    pub store: StoreConfig,
+    /// The address that invites are sent to, e.g. `calendar@example.org`. Inviting a
+    /// plus-address of it, like `calendar+lang@example.org`, tags the event with teams.
+    #[serde(default)]
+    pub calendar_address: Option<String>,
+    #[serde(default)]
+    pub roster: RosterConfig,
+    /// Receive invites over SMTP; off unless there is an `[smtp]` table.
+    pub smtp: Option<SmtpConfig>,
+    /// Poll a mailbox for invites; off unless there is a `[mailbox]` table.
+    pub mailbox: Option<MailboxConfig>,
+    /// How to send mail, such as replies to invites; without an `[outbound]` table we
+    /// don't send any.
+    pub outbound: Option<OutboundConfig>,
+    /// Where the app is served, e.g. `https://calendar.example.org`, for the links in
+    /// the mail we send. Requesting invites without logging in needs it.
+    #[serde(default)]
+    pub public_url: Option<String>,
+    /// GitHub logins of the people who may see the delivery log and such.
+    #[serde(default)]
+    pub admins: Vec<String>,
+}
+
+/// Where the team roster comes from, see [`crate::roster`].
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RosterConfig {
+    /// A directory in the layout of the rust-lang/team repository; without one there
+    /// are no team members.
+    pub path: Option<PathBuf>,
+    /// Seconds between reloads of the directory.
+    pub refresh_interval_seconds: u64,
+}
+
+impl Default for RosterConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            refresh_interval_seconds: 60 * 60,
+        }
+    }
+}
+
+/// Where the local copy of the calendar lives and how often it is refreshed.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct StoreConfig {
+    /// Path of the SQLite database.
+    pub path: PathBuf,
+    /// Seconds between syncs with the backend.
+    pub sync_interval_seconds: u64,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("eventageous.db"),
+            sync_interval_seconds: 5 * 60,
+        }
+    }
+}
+
+/// The SMTP server that invites to `calendar_address` are delivered to.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct SmtpConfig {
+    pub listen: SocketAddr,
+    /// The name the server greets clients with.
+    pub hostname: String,
+    /// Larger messages are refused.
+    pub max_message_bytes: usize,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 2525)),
+            hostname: "localhost".to_string(),
+            max_message_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// A mailbox that receives the mail for `calendar_address`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MailboxConfig {
+    #[serde(flatten)]
+    pub source: MailboxSource,
+    /// Seconds between polls.
+    #[serde(default = "default_poll_interval_seconds")]
+    pub poll_interval_seconds: u64,
+}
+
+fn default_poll_interval_seconds() -> u64 {
+    60
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MailboxSource {
+    /// A local Maildir, e.g. one that the MTA delivers into.
+    Maildir { path: PathBuf },
+    /// A folder on an IMAP server.
+    Imap {
+        host: String,
+        /// Defaults to 993, or 143 without TLS.
+        port: Option<u16>,
+        /// Connect without TLS, e.g. to a server on the same machine.
+        #[serde(default)]
+        plaintext: bool,
+        username: String,
+        password: String,
+        /// Defaults to `INBOX`.
+        mailbox: Option<String>,
+        /// Where messages that can't be taken in go; defaults to `Quarantine`.
+        quarantine: Option<String>,
+    },
+}
+
+/// How mail from `calendar_address` goes out.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OutboundConfig {
+    /// Hand mail to an SMTP relay, using STARTTLS unless `plaintext` is set.
+    Smtp {
+        host: String,
+        /// Defaults to 587, or 25 without TLS.
+        port: Option<u16>,
+        #[serde(default)]
+        plaintext: bool,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    /// Pipe each message to a local `sendmail` program.
+    Sendmail {
+        /// Defaults to `/usr/sbin/sendmail`.
+        command: Option<PathBuf>,
+    },
+    /// Write each message to a `.eml` file in a directory instead of sending it, for
+    /// testing.
+    Outbox { path: PathBuf },
+}
+
+/// Which calendar software holds the events.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BackendConfig {
+    /// A Google calendar, accessed with `google_api_key` and `google_calendar_id`.
+    #[default]
+    Google,
+    /// A calendar collection on a CalDAV server such as Nextcloud or Radicale.
+    #[serde(rename = "caldav")]
+    CalDav {
+        /// URL of the calendar collection, e.g. `https://dav.example.org/user/calendar/`.
+        url: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    /// A local directory of `.ics` files.
+    IcsDirectory { path: PathBuf },
 }
 
 impl Configuration {
@@ -13,6 +181,15 @@ impl Configuration {
         Self {
             google_api_key,
             google_calendar_id,
+            backend: BackendConfig::default(),
+            store: StoreConfig::default(),
+            calendar_address: None,
+            roster: RosterConfig::default(),
+            smtp: None,
+            mailbox: None,
+            outbound: None,
+            public_url: None,
+            admins: vec![],
         }
     }
 
@@ -20,15 +197,16 @@ impl Configuration {
         let google_api_key = std::env::var("GOOGLE_API_KEY")?;
         let google_calendar_id = std::env::var("GOOGLE_CALENDAR_ID")?;
 
-        Ok(Self {
-            google_api_key,
-            google_calendar_id,
-        })
+        Ok(Self::new(google_api_key, google_calendar_id))
     }
 
     pub fn load() -> anyhow::Result<Self> {
-        let path = PathBuf::from("americano.toml");
-        Self::from_toml_in_file(&path)
+        Self::from_toml_in_file(Path::new(CONFIG_FILE))
+    }
+
+    /// Whether there is an `americano.toml` for [`Configuration::load`] to read.
+    pub fn exists() -> bool {
+        Path::new(CONFIG_FILE).exists()
     }
 
     pub fn from_toml_in_file(path: &Path) -> anyhow::Result<Self> {
@@ -40,3 +218,94 @@ impl Configuration {
         Ok(toml::from_str(text)?)
     }
 }
+
+#[test]
+fn test_backend_defaults_to_google() {
+    let config = Configuration::from_toml_str(
+        r#"
+        google_api_key = "key"
+        google_calendar_id = "calendar"
+        "#,
+    )
+    .unwrap();
+    assert!(matches!(config.backend, BackendConfig::Google));
+    // So invites can't be taken in with it
+    let backend = crate::calendar::backend::from_config(&std::sync::Arc::new(config)).unwrap();
+    assert!(backend.is_read_only());
+}
+
+#[test]
+fn test_caldav_backend() {
+    let config = Configuration::from_toml_str(
+        r#"
+        google_api_key = ""
+        google_calendar_id = ""
+
+        [backend]
+        kind = "caldav"
+        url = "http://localhost:5232/user/calendar/"
+        username = "user"
+        "#,
+    )
+    .unwrap();
+    expect_test::expect![[r#"
+        CalDav {
+            url: "http://localhost:5232/user/calendar/",
+            username: Some(
+                "user",
+            ),
+            password: None,
+        }
+    "#]]
+    .assert_debug_eq(&config.backend);
+}
+
+#[test]
+fn test_ics_directory_backend() {
+    let config = Configuration::from_toml_str(
+        r#"
+        [backend]
+        kind = "ics_directory"
+        path = "test_data/calendar"
+        "#,
+    )
+    .unwrap();
+    expect_test::expect![[r#"
+        IcsDirectory {
+            path: "test_data/calendar",
+        }
+    "#]]
+    .assert_debug_eq(&config.backend);
+}
+
+#[test]
+fn test_imap_mailbox() {
+    let config = Configuration::from_toml_str(
+        r#"
+        [mailbox]
+        kind = "imap"
+        host = "imap.example.org"
+        username = "calendar"
+        password = "secret"
+        poll_interval_seconds = 30
+        "#,
+    )
+    .unwrap();
+    expect_test::expect![[r#"
+        Some(
+            MailboxConfig {
+                source: Imap {
+                    host: "imap.example.org",
+                    port: None,
+                    plaintext: false,
+                    username: "calendar",
+                    password: "secret",
+                    mailbox: None,
+                    quarantine: None,
+                },
+                poll_interval_seconds: 30,
+            },
+        )
+    "#]]
+    .assert_debug_eq(&config.mailbox);
+}
@@ -0,0 +1,372 @@
+//! ICS feeds that calendar apps can subscribe to: the whole calendar, the events of a
+//! team, or what someone subscribed to (at a URL with a secret token, as there is no
+//! logging in from a calendar app).
+//!
+//! Single events can be downloaded too, to add them to a calendar app once.
+//!
+//! Feeds only change when events do, and carry an `ETag` and `Last-Modified` so that
+//! apps polling them get a `304 Not Modified` most of the time.
+
+use std::sync::Arc;
+
+use axum::extract::Path;
+use axum::http::header::{
+    CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tower_sessions::Session;
+
+use crate::calendar::{ical, teams, Calendar, Event, Viewer, Visibility};
+use crate::roster::Roster;
+use crate::store::Subscription;
+use crate::user_session;
+
+/// How far back feeds go.
+const FEED_PAST: Duration = Duration::days(90);
+/// How far ahead feeds go.
+const FEED_FUTURE: Duration = Duration::days(365);
+
+type Error = (StatusCode, String);
+
+fn internal(e: anyhow::Error) -> Error {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// `/calendar.ics`: every public event.
+pub async fn calendar_feed(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let anonymous = Viewer::default();
+    feed(&calendar, "Eventageous", &headers, &anonymous, |event| {
+        event.visibility == Visibility::Public
+    })
+    .await
+}
+
+/// `/ics/<team>.ics`: the public events of a team.
+pub async fn team_feed(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Path(file): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let not_found = || (StatusCode::NOT_FOUND, format!("no feed `{file}`"));
+    let team = file
+        .strip_suffix(".ics")
+        .and_then(teams::normalize)
+        .ok_or_else(not_found)?;
+    let known = calendar.teams().await.map_err(internal)?;
+    if !known.iter().any(|known| known.name == team) {
+        return Err(not_found());
+    }
+    feed(
+        &calendar,
+        &format!("Eventageous: {team}"),
+        &headers,
+        &Viewer::default(),
+        |event| event.visibility == Visibility::Public && event.teams.contains(&team),
+    )
+    .await
+}
+
+/// `/ics/user/<token>.ics`: the events someone subscribed to, directly or through a
+/// team, including the private ones they may see.
+pub async fn user_feed(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Path(file): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let not_found = || (StatusCode::NOT_FOUND, format!("no feed `{file}`"));
+    let token = file.strip_suffix(".ics").ok_or_else(not_found)?;
+    let store = calendar.store();
+    let email = store
+        .feed_token_email(token)
+        .map_err(internal)?
+        .ok_or_else(not_found)?;
+    let subscriptions = store.subscriptions(&email).map_err(internal)?;
+    let viewer = Viewer {
+        teams: roster.current().teams_of_email(&email),
+        email: Some(email),
+    };
+
+    feed(
+        &calendar,
+        "Eventageous: subscribed",
+        &headers,
+        &viewer,
+        |event| {
+            let series = event.recurring_event_id.as_ref().unwrap_or(&event.id);
+            let subscribed = subscriptions.iter().any(|subscription| match subscription {
+                Subscription::Event(id) => id == series,
+                Subscription::Team(team) => event.teams.contains(team),
+            });
+            subscribed && event.visible_to(&viewer)
+        },
+    )
+    .await
+}
+
+/// `/ics/event/<id>.ics`: a single event, if the logged in user may see it. An
+/// occurrence of a series comes as an event of its own, since apps have no use for an
+/// occurrence without the rest of its series.
+pub async fn event_download(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Path(file): Path<String>,
+    session: Session,
+) -> Result<Response, Error> {
+    let not_found = || (StatusCode::NOT_FOUND, format!("no event `{file}`"));
+    let id = file.strip_suffix(".ics").ok_or_else(not_found)?;
+    let event = calendar
+        .event(id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(not_found)?;
+    let viewer = user_session::get_viewer(&session, &roster).await;
+    if !event.visible_to(&viewer) {
+        return Err(not_found());
+    }
+    let mut event = event.for_viewer(&viewer);
+    if event.recurring_event_id.take().is_some() {
+        event.original_start = None;
+        event.recurrence = None;
+    }
+    let from = event.start.instant();
+    let to = match event.recurrence {
+        Some(_) => from.max(Utc::now()) + FEED_FUTURE,
+        None => event.end.instant(),
+    };
+
+    let body = ical::download_to_ics(&event, from, to);
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        "text/calendar; charset=utf-8"
+            .parse()
+            .expect("valid header"),
+    );
+    headers.insert(
+        CONTENT_DISPOSITION,
+        "attachment; filename=\"event.ics\""
+            .parse()
+            .expect("valid header"),
+    );
+    Ok((headers, body).into_response())
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedUrlResponse {
+    url: String,
+}
+
+/// The URL of the logged in user's feed.
+pub async fn feed_url(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    session: Session,
+) -> Result<Json<FeedUrlResponse>, Error> {
+    let email = logged_in_email(&session).await?;
+    let token = calendar.store().feed_token(&email).map_err(internal)?;
+    Ok(Json(FeedUrlResponse {
+        url: format!("/ics/user/{token}.ics"),
+    }))
+}
+
+/// Give the logged in user's feed a new URL, say because the old one leaked.
+pub async fn reset_feed_url(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    session: Session,
+) -> Result<Json<FeedUrlResponse>, Error> {
+    let email = logged_in_email(&session).await?;
+    let token = calendar
+        .store()
+        .reset_feed_token(&email)
+        .map_err(internal)?;
+    Ok(Json(FeedUrlResponse {
+        url: format!("/ics/user/{token}.ics"),
+    }))
+}
+
+async fn logged_in_email(session: &Session) -> Result<String, Error> {
+    user_session::get_user_email(session).await.ok_or((
+        StatusCode::UNAUTHORIZED,
+        "log in with an email address to get a feed".to_string(),
+    ))
+}
+
+/// Serve the events for which `include` holds, as `viewer` may see them, unless the
+/// client has them already.
+async fn feed(
+    calendar: &Calendar,
+    name: &str,
+    headers: &HeaderMap,
+    viewer: &Viewer,
+    include: impl Fn(&Event) -> bool,
+) -> Result<Response, Error> {
+    // Move the window a day at a time, so that the feed doesn't change with every request
+    let today = Utc::now()
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight exists")
+        .and_utc();
+    let (from, to) = (today - FEED_PAST, today + FEED_FUTURE);
+    let mut events = calendar.series(from, to).await.map_err(internal)?;
+    events.retain(|event| include(event));
+    // Anyone may read the public feeds, so they don't say who is coming
+    let events: Vec<Event> = events
+        .iter()
+        .map(|event| event.for_viewer(viewer))
+        .collect();
+
+    let body = ical::feed_to_ics(name, &events, from, to);
+    let etag = format!("\"{:x}\"", Sha256::digest(&body));
+    let last_deletion = calendar.store().last_deletion().map_err(internal)?;
+    let last_modified = events
+        .iter()
+        .filter_map(|event| event.updated)
+        .chain(last_deletion)
+        .max();
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(ETAG, etag.parse().expect("hex is a valid header"));
+    if let Some(last_modified) = last_modified {
+        let date = last_modified
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        response_headers.insert(
+            LAST_MODIFIED,
+            date.parse().expect("dates are valid headers"),
+        );
+    }
+    if not_modified(headers, &etag, last_modified) {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+    response_headers.insert(
+        CONTENT_TYPE,
+        "text/calendar; charset=utf-8"
+            .parse()
+            .expect("valid header"),
+    );
+    Ok((response_headers, body).into_response())
+}
+
+/// Whether the conditional headers of a request say the client has the current feed.
+/// As in RFC 9110, `If-Modified-Since` only counts without an `If-None-Match`.
+fn not_modified(headers: &HeaderMap, etag: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(if_none_match) = header(IF_NONE_MATCH) {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag || tag == "*");
+    }
+    let since =
+        header(IF_MODIFIED_SINCE).and_then(|since| DateTime::parse_from_rfc2822(since).ok());
+    match (since, last_modified) {
+        (Some(since), Some(last_modified)) => last_modified.timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+#[test]
+fn test_not_modified() {
+    let headers = |pairs: &[(&'static str, &str)]| {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    };
+    let modified = "2024-03-06T16:00:00.5Z".parse::<DateTime<Utc>>().ok();
+
+    assert!(!not_modified(&headers(&[]), "\"a\"", modified));
+    assert!(not_modified(
+        &headers(&[("if-none-match", "\"b\", W/\"a\"")]),
+        "\"a\"",
+        modified
+    ));
+    assert!(!not_modified(
+        &headers(&[("if-none-match", "\"b\"")]),
+        "\"a\"",
+        modified
+    ));
+    let since = "Wed, 06 Mar 2024 16:00:00 GMT";
+    assert!(not_modified(
+        &headers(&[("if-modified-since", since)]),
+        "\"a\"",
+        modified
+    ));
+    let before = "Wed, 06 Mar 2024 15:59:59 GMT";
+    assert!(!not_modified(
+        &headers(&[("if-modified-since", before)]),
+        "\"a\"",
+        modified
+    ));
+    // A changed ETag wins over an old enough date
+    let both = [("if-none-match", "\"b\""), ("if-modified-since", since)];
+    assert!(!not_modified(&headers(&both), "\"a\"", modified));
+}
+
+#[tokio::test]
+async fn test_public_feeds_have_no_attendees() {
+    use crate::calendar::{backend, Attendee, EventTime};
+    use crate::config::{BackendConfig, Configuration};
+    use crate::store::Store;
+
+    let mut config = Configuration::new(String::new(), String::new());
+    config.backend = BackendConfig::IcsDirectory {
+        path: std::env::temp_dir(),
+    };
+    let store = Arc::new(Store::in_memory().unwrap());
+    let backend = backend::from_config(&Arc::new(config)).unwrap();
+    let calendar = Arc::new(Calendar::new(backend, store.clone()));
+    let start = Utc::now() + Duration::days(1);
+    store
+        .upsert_event(&Event {
+            id: "triage@example.org".to_string(),
+            summary: "Triage".to_string(),
+            start: EventTime::parse(&start.to_rfc3339(), None).unwrap(),
+            end: EventTime::parse(&(start + Duration::hours(1)).to_rfc3339(), None).unwrap(),
+            attendees: vec![Attendee {
+                email: "jane@example.org".to_string(),
+                name: None,
+                status: "ACCEPTED".to_string(),
+                role: None,
+            }],
+            ..Event::default()
+        })
+        .unwrap();
+
+    let body = |response: Response| async {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    };
+    let public = calendar_feed(Extension(calendar.clone()), HeaderMap::new())
+        .await
+        .unwrap();
+    let public = body(public).await;
+    assert!(public.contains("SUMMARY:Triage"));
+    assert!(!public.contains("ATTENDEE"));
+
+    let jane = Viewer {
+        email: Some("jane@example.org".to_string()),
+        teams: vec![],
+    };
+    let subscribed = feed(
+        &calendar,
+        "Eventageous: subscribed",
+        &HeaderMap::new(),
+        &jane,
+        |_| true,
+    )
+    .await
+    .unwrap();
+    assert!(body(subscribed).await.contains("ATTENDEE"));
+}
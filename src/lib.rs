@@ -1,32 +1,156 @@
 use auth::Auth;
-use axum::{extract::State, routing::get, Extension, Json, Router};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Html;
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
 
-use calendar::Events;
+use calendar::draft::{self, EventDraft};
+use calendar::{Attendance, Attendee, Event, EventQuery, Events, Rsvp, Team, Viewer};
 use config::Configuration;
+use mail::invitations::Invitations;
+use mail::outbound::Transport;
+use mail::poller::Poller;
+use mail::queue::Queue;
+use mail::requests::{Confirmation, InviteRequests};
+use mail::smtp::SmtpServer;
+use mail::Inbox;
 use oauth_config::OAuthConfig;
-use serde::Serialize;
+use roster::Roster;
+use serde::{Deserialize, Serialize};
 use shuttle_secrets::SecretStore;
 use std::sync::Arc;
+use store::{Delivery, DeliveryStatus, Subscription};
 use time::Duration;
-use tower_http::services::ServeDir;
+use tower_http::services::{ServeDir, ServeFile};
 use tower_sessions::{Expiry, MemoryStore, Session, SessionManagerLayer};
 
 use crate::calendar::Calendar;
 
 mod auth;
-mod calendar;
-mod config;
+pub mod calendar;
+pub mod config;
+mod feeds;
+pub mod mail;
 mod oauth_config;
+pub mod roster;
+pub mod store;
 mod user_session;
 
 // For testing, should be defined on a cookie or something
 const SESSION_LENGTH_SECONDS: i64 = 60 * 2;
+/// How often the outbound mail queue is checked for mail that is due.
+const QUEUE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
 
 pub async fn eventageous(secret_store: SecretStore) -> shuttle_axum::ShuttleAxum {
-    // Configure the backend
-    let google_api_key = secret_store.get("GOOGLE_API_KEY").unwrap();
-    let google_calendar_id = secret_store.get("GOOGLE_CALENDAR_ID").unwrap();
-    let config = Arc::new(Configuration::new(google_api_key, google_calendar_id));
+    // Configure the backend, preferring an `americano.toml` if there is one
+    let config = if Configuration::exists() {
+        Configuration::load()?
+    } else {
+        let google_api_key = secret_store.get("GOOGLE_API_KEY").unwrap();
+        let google_calendar_id = secret_store.get("GOOGLE_CALENDAR_ID").unwrap();
+        Configuration::new(google_api_key, google_calendar_id)
+    };
+    let config = Arc::new(config);
+    let calendar = Arc::new(Calendar::try_from(&config)?);
+
+    // Keep the local store in sync with the backend. The first sync happens before we
+    // start serving; if the backend is down we carry on with whatever the store has.
+    if let Err(e) = calendar.sync().await {
+        tracing::error!("initial calendar sync failed: {e:?}");
+    }
+    let sync_interval = std::time::Duration::from_secs(config.store.sync_interval_seconds);
+    tokio::spawn(calendar::sync_periodically(calendar.clone(), sync_interval));
+
+    // Load the team roster, and reload it now and then to pick up changes
+    let roster = Arc::new(Roster::new(
+        config.roster.path.clone(),
+        calendar.store().clone(),
+    ));
+    if let Err(e) = roster.refresh() {
+        tracing::error!("loading the team roster failed: {e:?}");
+    }
+    let refresh_interval = std::time::Duration::from_secs(config.roster.refresh_interval_seconds);
+    tokio::spawn(roster::refresh_periodically(
+        roster.clone(),
+        refresh_interval,
+    ));
+
+    // Outgoing mail is queued in the store, and sent from there in the background
+    let transport: Option<Arc<dyn Transport>> = match &config.outbound {
+        Some(outbound) => {
+            let queue = Arc::new(Queue::new(
+                calendar.store().clone(),
+                mail::outbound::transport_from_config(outbound)?,
+            ));
+            tokio::spawn(mail::queue::deliver_periodically(
+                queue.clone(),
+                QUEUE_INTERVAL,
+            ));
+            Some(queue)
+        }
+        None => None,
+    };
+    // Team members and subscribers get invites of their own from the calendar address
+    let invitations = match (&transport, &config.calendar_address) {
+        (Some(transport), Some(calendar_address)) => Some(Arc::new(Invitations::new(
+            calendar.store().clone(),
+            roster.clone(),
+            transport.clone(),
+            calendar_address.clone(),
+            config.public_url.clone(),
+        ))),
+        (Some(_), None) => {
+            return Err(anyhow::anyhow!("sending mail needs a `calendar_address`").into());
+        }
+        (None, _) => None,
+    };
+    // People without an account confirm their address before they get invites, which
+    // takes a link to us
+    let invite_requests = match (&invitations, &config.public_url) {
+        (Some(invitations), Some(public_url)) => Some(Arc::new(InviteRequests::new(
+            calendar.store().clone(),
+            invitations.clone(),
+            transport.clone().expect("invitations need a transport"),
+            config.calendar_address.clone().unwrap_or_default(),
+            public_url.clone(),
+        ))),
+        _ => None,
+    };
+
+    // Take in invites over SMTP next to the web app, or from a mailbox, if configured
+    if config.smtp.is_some() || config.mailbox.is_some() {
+        let Some(calendar_address) = config.calendar_address.clone() else {
+            return Err(anyhow::anyhow!("taking in mail needs a `calendar_address`").into());
+        };
+        // Otherwise every invite would be refused, and senders would retry until they bounce
+        if calendar.is_read_only() {
+            return Err(anyhow::anyhow!(
+                "taking in mail needs a `[backend]` that can store events, such as CalDAV"
+            )
+            .into());
+        }
+        let inbox = Arc::new(Inbox::new(
+            calendar.clone(),
+            calendar_address,
+            transport,
+            invitations.clone(),
+        ));
+        if let Some(smtp) = &config.smtp {
+            let listener = tokio::net::TcpListener::bind(smtp.listen).await?;
+            tracing::info!("receiving mail on {}", smtp.listen);
+            tokio::spawn(Arc::new(SmtpServer::new(inbox.clone(), smtp)).serve(listener));
+        }
+        if let Some(mailbox) = &config.mailbox {
+            let poller = Arc::new(Poller::new(
+                mail::poller::mailbox_from_config(mailbox),
+                inbox,
+                calendar.store().clone(),
+            ));
+            let poll_interval = std::time::Duration::from_secs(mailbox.poll_interval_seconds);
+            tokio::spawn(mail::poller::poll_periodically(poller, poll_interval));
+        }
+    }
 
     // Configure OAuth
     let oauth2_client_id = secret_store.get("GITHUB_CLIENT_ID").unwrap();
@@ -51,10 +175,46 @@ pub async fn eventageous(secret_store: SecretStore) -> shuttle_axum::ShuttleAxum
     // Configure the routes
     let session_store = MemoryStore::default();
     let router = Router::new()
-        .nest_service("/", ServeDir::new("dist"))
-        .route("/api/events", get(handler))
+        // Pages of the app, such as `/events/<id>`, are routed by the app itself
+        .nest_service(
+            "/",
+            ServeDir::new("dist").fallback(ServeFile::new("dist/index.html")),
+        )
+        .route("/api/events", get(handler).post(create_event_handler))
+        .route("/api/teams", get(teams_handler))
+        .route(
+            "/api/feed",
+            get(feeds::feed_url).post(feeds::reset_feed_url),
+        )
+        .route("/calendar.ics", get(feeds::calendar_feed))
+        .route("/ics/:team", get(feeds::team_feed))
+        .route("/ics/user/:token", get(feeds::user_feed))
+        .route("/ics/event/:id", get(feeds::event_download))
+        .route(
+            "/api/events/:id",
+            get(event_handler)
+                .patch(update_event_handler)
+                .delete(delete_event_handler),
+        )
+        .route("/api/events/:id/rsvp", post(rsvp_handler))
+        .route("/api/events/:id/subscribe", post(subscribe_handler))
+        .route("/api/events/:id/unsubscribe", post(unsubscribe_handler))
+        .route(
+            "/api/events/:id/request-invite",
+            post(request_invite_handler),
+        )
+        .route("/api/invites/:token/confirm", get(confirm_invite_handler))
+        .route(
+            "/api/unsubscribe/:token",
+            get(unsubscribe_page_handler).post(unsubscribe_link_handler),
+        )
+        .route("/api/admin/deliveries", get(deliveries_handler))
         .nest("/auth", auth_router)
         .with_state(config)
+        .layer(Extension(calendar))
+        .layer(Extension(roster))
+        .layer(Extension(invitations))
+        .layer(Extension(invite_requests))
         .layer(
             SessionManagerLayer::new(session_store)
                 .with_secure(true)
@@ -71,9 +231,15 @@ struct Response {
     data: Events,
     authed: bool, // don't do this for realz, just for testing
     email: String,
+    teams: Vec<String>,
 }
 
-async fn handler(State(config): State<Arc<Configuration>>, session: Session) -> Json<Response> {
+async fn handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Query(query): Query<EventQuery>,
+    session: Session,
+) -> Result<Json<Response>, (StatusCode, String)> {
     tracing::info!("handler: session: {:?}", session.id());
 
     if session.is_empty().await {
@@ -81,19 +247,503 @@ async fn handler(State(config): State<Arc<Configuration>>, session: Session) ->
         tracing::info!("handler: cycled id, does it exist now?: {:?}", session.id());
     }
 
-    let calendar = Calendar::from(&config);
-    let events = calendar.events().await.unwrap();
+    if let Err(e) = query.window() {
+        return Err((StatusCode::BAD_REQUEST, e.to_string()));
+    }
+    let mut events = calendar
+        .events(&query)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     tracing::info!("Got data from Calenar API!");
+    // Private events show up as busy to everyone who isn't in on them
+    events.redact_for(&user_session::get_viewer(&session, &roster).await);
 
     // Shoving this data in this response for now, should handle properly
     let logged_in = user_session::logged_in(&session).await;
     let email = user_session::get_user_email_from_session(&session).await;
+    let teams = user_session::get_user_teams(&session, &roster).await;
     tracing::info!("Logged in {} /email {}", logged_in, email);
     let response = Response {
         data: events,
         authed: logged_in,
-        email: email,
+        email,
+        teams,
+    };
+
+    Ok(Json(response))
+}
+
+#[derive(Debug, Serialize)]
+struct TeamsResponse {
+    teams: Vec<Team>,
+}
+
+async fn teams_handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+) -> Result<Json<TeamsResponse>, (StatusCode, String)> {
+    let teams = calendar
+        .teams()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(TeamsResponse { teams }))
+}
+
+#[derive(Debug, Serialize)]
+struct EventResponse {
+    event: Event,
+    /// Counted for everyone, while who is coming is only shown to logged in users.
+    attendance: Attendance,
+}
+
+impl EventResponse {
+    fn new(event: &Event, viewer: &Viewer) -> Self {
+        let event = event.for_viewer(viewer);
+        let attendance = match event.redacted {
+            true => Attendance::default(),
+            false => Attendance::of(&event.attendees),
+        };
+        Self { event, attendance }
+    }
+}
+
+async fn event_handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Path(id): Path<String>,
+    session: Session,
+) -> Result<Json<EventResponse>, (StatusCode, String)> {
+    let event = calendar
+        .event(&id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("no event `{id}`")))?;
+    let viewer = user_session::get_viewer(&session, &roster).await;
+    Ok(Json(EventResponse::new(&event, &viewer)))
+}
+
+/// Create an event organized by the logged in user, and invite its teams.
+async fn create_event_handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Extension(invitations): Extension<Option<Arc<Invitations>>>,
+    session: Session,
+    Json(draft): Json<EventDraft>,
+) -> Result<(StatusCode, Json<Event>), (StatusCode, String)> {
+    let Some(email) = user_session::get_user_email(&session).await else {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "log in with an email address to create events".to_string(),
+        ));
+    };
+    if draft.summary.is_none() || draft.start.is_none() || draft.end.is_none() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "an event needs a summary, start and end".to_string(),
+        ));
+    }
+    let login = user_session::get_user_login(&session).await;
+    let name = login
+        .as_deref()
+        .and_then(|login| Some(roster.current().person(login)?.name.clone()))
+        .or(login)
+        .unwrap_or_else(|| email.clone());
+    let mut event = Event {
+        creator_email: email,
+        creator_name: name,
+        ..Event::default()
+    };
+    apply_draft(&calendar, draft, &mut event).await?;
+
+    let event = calendar
+        .create_event(event)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    send_invitations(invitations.as_deref(), &event).await;
+    Ok((StatusCode::CREATED, Json(event)))
+}
+
+/// Change what the request sets of an event, and send everyone the update.
+async fn update_event_handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Extension(invitations): Extension<Option<Arc<Invitations>>>,
+    Path(id): Path<String>,
+    session: Session,
+    Json(draft): Json<EventDraft>,
+) -> Result<Json<Event>, (StatusCode, String)> {
+    let event = editable_event(&calendar, &roster, &id, &session).await?;
+    let mut updated = event.clone();
+    apply_draft(&calendar, draft, &mut updated).await?;
+    updated.sequence = event.sequence + 1;
+
+    let updated = calendar
+        .update_event(&id, updated)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    send_invitations(invitations.as_deref(), &updated).await;
+    Ok(Json(updated))
+}
+
+/// Remove an event, telling everyone who was invited that it's off.
+async fn delete_event_handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Extension(invitations): Extension<Option<Arc<Invitations>>>,
+    Path(id): Path<String>,
+    session: Session,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let event = editable_event(&calendar, &roster, &id, &session).await?;
+    // Deleting the event forgets who has an invite, but nobody is told it's off unless
+    // the delete worked
+    let invited = match invitations.as_deref() {
+        Some(invitations) => invitations
+            .invited(&id)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+        None => vec![],
     };
+    calendar
+        .delete_event(&id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let Some(invitations) = invitations.as_deref() {
+        let cancelled = Event {
+            status: Some("CANCELLED".to_string()),
+            sequence: event.sequence + 1,
+            ..event
+        };
+        let sent = invitations.cancel_deleted(&cancelled, &invited).await;
+        tracing::info!("sent {sent} cancellations for {id}");
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The event `id`, if the logged in user may change it, see [`draft::may_edit`].
+async fn editable_event(
+    calendar: &Calendar,
+    roster: &Roster,
+    id: &str,
+    session: &Session,
+) -> Result<Event, (StatusCode, String)> {
+    let email = user_session::get_user_email(session).await;
+    let login = user_session::get_user_login(session).await;
+    if email.is_none() && login.is_none() {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "log in to change events".to_string(),
+        ));
+    }
+    let event = calendar
+        .event(id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("no event `{id}`")))?;
+    if !draft::may_edit(
+        &event,
+        email.as_deref(),
+        login.as_deref(),
+        &roster.current(),
+    ) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "only the creator of an event and the leads of its teams can change it".to_string(),
+        ));
+    }
+    Ok(event)
+}
+
+async fn apply_draft(
+    calendar: &Calendar,
+    draft: EventDraft,
+    event: &mut Event,
+) -> Result<(), (StatusCode, String)> {
+    let known = calendar
+        .teams()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    draft
+        .apply(event, &known)
+        .map_err(|problems| (StatusCode::UNPROCESSABLE_ENTITY, problems.join("; ")))
+}
 
-    Json(response)
+/// Bring the invites to `event` up to date, if we send mail. The event is changed
+/// either way, so failures are only logged.
+async fn send_invitations(invitations: Option<&Invitations>, event: &Event) {
+    let Some(invitations) = invitations else {
+        return;
+    };
+    match invitations.send(event).await {
+        Ok(sent) => tracing::info!("sent {sent} invitations for {}", event.id),
+        Err(e) => tracing::error!("could not send invitations for {}: {e:?}", event.id),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RsvpRequest {
+    status: Rsvp,
+}
+
+/// Let the logged in user say whether they are coming.
+async fn rsvp_handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Path(id): Path<String>,
+    session: Session,
+    Json(request): Json<RsvpRequest>,
+) -> Result<Json<EventResponse>, (StatusCode, String)> {
+    let viewer = user_session::get_viewer(&session, &roster).await;
+    let Some(email) = viewer.email.clone() else {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "log in with an email address to say whether you're coming".to_string(),
+        ));
+    };
+    let not_found = || (StatusCode::NOT_FOUND, format!("no event `{id}`"));
+    // Private events the user can't see might as well not exist
+    let event = calendar
+        .event(&id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .filter(|event| event.visible_to(&viewer))
+        .ok_or_else(not_found)?;
+    let attendee = Attendee {
+        email,
+        name: None,
+        status: request.status.status().to_string(),
+        role: None,
+    };
+    let event = calendar
+        .rsvp(&event.id, &attendee)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(not_found)?;
+    Ok(Json(EventResponse::new(&event, &viewer)))
+}
+
+#[derive(Debug, Serialize)]
+struct SubscriptionResponse {
+    subscribed: bool,
+    /// Whether we sent an invite (or, when unsubscribing, a cancellation) just now.
+    invited: bool,
+}
+
+async fn subscribe_handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(invitations): Extension<Option<Arc<Invitations>>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Path(id): Path<String>,
+    session: Session,
+) -> Result<Json<SubscriptionResponse>, (StatusCode, String)> {
+    let viewer = user_session::get_viewer(&session, &roster).await;
+    set_subscription(&calendar, invitations.as_deref(), &id, &viewer, true).await
+}
+
+async fn unsubscribe_handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(invitations): Extension<Option<Arc<Invitations>>>,
+    Extension(roster): Extension<Arc<Roster>>,
+    Path(id): Path<String>,
+    session: Session,
+) -> Result<Json<SubscriptionResponse>, (StatusCode, String)> {
+    let viewer = user_session::get_viewer(&session, &roster).await;
+    set_subscription(&calendar, invitations.as_deref(), &id, &viewer, false).await
+}
+
+/// (Un)subscribe the logged in user to an event, and send them an invite for it (or
+/// tell them it's off) if we can send mail.
+async fn set_subscription(
+    calendar: &Calendar,
+    invitations: Option<&Invitations>,
+    id: &str,
+    viewer: &Viewer,
+    subscribed: bool,
+) -> Result<Json<SubscriptionResponse>, (StatusCode, String)> {
+    let Some(email) = viewer.email.clone() else {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "log in with an email address to subscribe".to_string(),
+        ));
+    };
+    // Private events the user can't see might as well not exist
+    let event = calendar
+        .event(id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .filter(|event| event.visible_to(viewer))
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("no event `{id}`")))?;
+
+    let store = calendar.store();
+    let subscription = Subscription::Event(event.id.clone());
+    match subscribed {
+        true => store.subscribe(&email, &subscription),
+        false => store.unsubscribe(&email, &subscription),
+    }
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let invited = match invitations {
+        Some(invitations) => invitations
+            .send_to(&event, &email)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+        None => false,
+    };
+    Ok(Json(SubscriptionResponse {
+        subscribed,
+        invited,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct InviteRequestBody {
+    email: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InviteRequestResponse {
+    /// When the link we mailed stops working.
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Mail a link to confirm an invite to a public event, for people without an account.
+async fn request_invite_handler(
+    Extension(calendar): Extension<Arc<Calendar>>,
+    Extension(invite_requests): Extension<Option<Arc<InviteRequests>>>,
+    Path(id): Path<String>,
+    Json(body): Json<InviteRequestBody>,
+) -> Result<(StatusCode, Json<InviteRequestResponse>), (StatusCode, String)> {
+    let Some(invite_requests) = invite_requests else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "this calendar doesn't send invites".to_string(),
+        ));
+    };
+    if body.email.trim().parse::<lettre::Address>().is_err() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("`{}` is not an email address", body.email),
+        ));
+    }
+    let event = calendar
+        .event(&id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .filter(|event| event.visibility == calendar::Visibility::Public)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("no event `{id}`")))?;
+    let request = invite_requests
+        .request(&event, &body.email, chrono::Utc::now())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                "too many invites requested for this address, try again later".to_string(),
+            )
+        })?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(InviteRequestResponse {
+            expires_at: request.expires_at,
+        }),
+    ))
+}
+
+/// Where the link in the confirmation mail goes.
+async fn confirm_invite_handler(
+    Extension(invite_requests): Extension<Option<Arc<InviteRequests>>>,
+    Path(token): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, "this link doesn't work".to_string());
+    let invite_requests = invite_requests.ok_or_else(not_found)?;
+    let confirmation = invite_requests
+        .confirm(&token, chrono::Utc::now())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    match confirmation {
+        Confirmation::Invited(event) | Confirmation::AlreadyConfirmed(event) => Ok(format!(
+            "Thanks! The invite to {} is on its way to your inbox.",
+            event.summary
+        )),
+        Confirmation::Expired => Err((
+            StatusCode::GONE,
+            "this link has expired, please request the invite again".to_string(),
+        )),
+        Confirmation::Unknown => Err(not_found()),
+    }
+}
+
+/// Where the unsubscribe links in our mail go. Following one only asks whether to
+/// unsubscribe, as mail scanners follow links too.
+async fn unsubscribe_page_handler(
+    Extension(invite_requests): Extension<Option<Arc<InviteRequests>>>,
+    Path(token): Path<String>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, "this link doesn't work".to_string());
+    let invite_requests = invite_requests.ok_or_else(not_found)?;
+    let event = invite_requests
+        .unsubscribe_event(&token)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(not_found)?;
+    Ok(Html(format!(
+        "<!DOCTYPE html>\n<title>Unsubscribe</title>\n\
+         <form method=\"post\">\n\
+         <p>Stop getting mail about {}?</p>\n\
+         <button type=\"submit\">Unsubscribe</button>\n\
+         </form>\n",
+        escape_html(&event.summary)
+    )))
+}
+
+/// Unsubscribe, from the page above or one-click (RFC 8058) from a mail client.
+async fn unsubscribe_link_handler(
+    Extension(invite_requests): Extension<Option<Arc<InviteRequests>>>,
+    Path(token): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, "this link doesn't work".to_string());
+    let invite_requests = invite_requests.ok_or_else(not_found)?;
+    let event = invite_requests
+        .unsubscribe(&token)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(not_found)?;
+    Ok(format!(
+        "You won't get any more mail about {}.",
+        event.summary
+    ))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Debug, Serialize)]
+struct DeliveriesResponse {
+    deliveries: Vec<Delivery>,
+}
+
+/// Outgoing mail that didn't make it, for admins.
+async fn deliveries_handler(
+    State(config): State<Arc<Configuration>>,
+    Extension(calendar): Extension<Arc<Calendar>>,
+    session: Session,
+) -> Result<Json<DeliveriesResponse>, (StatusCode, String)> {
+    if !user_session::is_admin(&session, &config).await {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "only admins can see this".to_string(),
+        ));
+    }
+    let store = calendar.store();
+    let mut deliveries = store
+        .deliveries(DeliveryStatus::Failed)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    deliveries.extend(
+        store
+            .deliveries(DeliveryStatus::Bounced)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+    );
+    deliveries.sort_by_key(|delivery| std::cmp::Reverse(delivery.updated_at));
+    Ok(Json(DeliveriesResponse { deliveries }))
 }
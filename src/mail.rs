@@ -0,0 +1,741 @@
+//! Receiving invites by email, and accepting them.
+//!
+//! Organizers schedule events by inviting the calendar address, or a plus-address of
+//! it to put the event on the calendars of teams and possibly keep it private
+//! (`calendar+lang+private@example.org`). Mail for teams we don't know is refused, so
+//! that a typo doesn't quietly leave an event off a team's calendar.
+//!
+//! However a message arrives, it ends up in the [`Inbox`], which turns its invites into
+//! events, and applies cancellations, replies and counter proposals to the events we
+//! have. If we can send mail, it also replies to organizers that we accept their invites
+//! and forwards the events to the members of their teams.
+
+use std::sync::Arc;
+
+use mail_parser::MessageParser;
+
+use crate::calendar::invite::{CalendarEmail, EventRef};
+use crate::calendar::teams::{self, Routing};
+use crate::calendar::{ical, recurrence, Attendee, Calendar, Event};
+use crate::store::CounterProposal;
+
+use invitations::Invitations;
+use outbound::{OutgoingMessage, Transport};
+
+pub mod bounce;
+pub mod imap;
+pub mod invitations;
+pub mod maildir;
+pub mod outbound;
+pub mod poller;
+pub mod queue;
+pub mod requests;
+pub mod smtp;
+
+/// Where incoming mail for the calendar address is delivered.
+pub struct Inbox {
+    calendar: Arc<Calendar>,
+    calendar_address: String,
+    /// How replies to organizers are sent; without one, invites are accepted silently.
+    transport: Option<Arc<dyn Transport>>,
+    /// Forwards events to team members, if we can send mail.
+    invitations: Option<Arc<Invitations>>,
+}
+
+impl Inbox {
+    pub fn new(
+        calendar: Arc<Calendar>,
+        calendar_address: String,
+        transport: Option<Arc<dyn Transport>>,
+        invitations: Option<Arc<Invitations>>,
+    ) -> Self {
+        Self {
+            calendar,
+            calendar_address,
+            transport,
+            invitations,
+        }
+    }
+
+    /// Whether mail to `recipient` is for us: the calendar address or a plus-address of
+    /// it.
+    pub fn accepts(&self, recipient: &str) -> bool {
+        teams::plus_tags(recipient, &self.calendar_address).is_some()
+    }
+
+    /// The teams that mail to `recipient` would put an event on but that we don't know.
+    pub async fn unknown_teams(&self, recipient: &str) -> anyhow::Result<Vec<String>> {
+        let tags = teams::plus_tags(recipient, &self.calendar_address).unwrap_or_default();
+        let routing = Routing::from_tags(tags.iter().map(String::as_str));
+        Ok(routing.unknown_teams(&self.calendar.known_teams().await?))
+    }
+
+    /// If `message` is a bounce of mail we sent, mark the deliveries it is about as
+    /// bounced and return how many there were. Returns `None` for any other message.
+    pub fn record_bounce(&self, message: &str) -> anyhow::Result<Option<usize>> {
+        let Some(bounce) = bounce::parse_bounce(message) else {
+            return Ok(None);
+        };
+        let Some(message_id) = &bounce.message_id else {
+            tracing::warn!("ignoring a bounce that doesn't say which message bounced");
+            return Ok(Some(0));
+        };
+        let mut bounced = 0;
+        for failure in &bounce.failures {
+            let store = self.calendar.store();
+            if store.bounce_delivery(message_id, &failure.recipient, &failure.reason())? {
+                tracing::warn!("{message_id} to {} bounced", failure.recipient);
+                bounced += 1;
+            }
+        }
+        Ok(Some(bounced))
+    }
+
+    /// Parse a raw message, failing if it isn't something we can handle. Plus-addresses
+    /// among the `envelope_recipients` (if we know them) route it like those in its
+    /// headers.
+    pub async fn parse(
+        &self,
+        message: &str,
+        envelope_recipients: &[String],
+    ) -> anyhow::Result<CalendarEmail> {
+        let mut email = CalendarEmail::parse_email(message, Some(&self.calendar_address))?;
+        let tags: Vec<String> = envelope_recipients
+            .iter()
+            .filter_map(|recipient| teams::plus_tags(recipient, &self.calendar_address))
+            .flatten()
+            .collect();
+        email.route(&Routing::from_tags(tags.iter().map(String::as_str)));
+
+        // Only the roster's teams count, or one invite could make up a team for the next
+        let known = self.calendar.known_teams().await?;
+        let unknown = email.routing().unknown_teams(&known);
+        if !unknown.is_empty() {
+            anyhow::bail!(
+                "unknown team(s) in the recipient address: {}",
+                unknown.join(", ")
+            );
+        }
+        let dropped = email.keep_known_teams(&known);
+        if !dropped.is_empty() {
+            tracing::info!(
+                "ignoring categories that aren't teams: {}",
+                dropped.join(", ")
+            );
+        }
+        if email.is_empty() {
+            anyhow::bail!("no calendar invite in message");
+        }
+        Ok(email)
+    }
+
+    /// Apply everything in `email` to the calendar, returning the number of changes.
+    ///
+    /// Messages about an older `SEQUENCE` of an event than the one we have are ignored,
+    /// so delivering a message twice, or out of order, does no harm. Only the organizer
+    /// of an event (or whoever sent us the invite for it) may change or cancel it, and
+    /// attendees only answer for themselves; anything else in the email is ignored.
+    pub async fn deliver(&self, email: &CalendarEmail) -> anyhow::Result<usize> {
+        let sender = email.sender();
+        let mut changes = 0;
+        for event in email.event_requests() {
+            if !self.organizer_may_change(&event.id, Some(&event.creator_email), sender)? {
+                continue;
+            }
+            let new = self.stored_event(&event.id)?.is_none();
+            let mut event = event.clone();
+            let ours = self.accept(&mut event);
+            // Only reply once to each version of the invite
+            let replied = match (&ours, self.calendar.event(&event.id).await?) {
+                (Some(ours), Some(stored)) => {
+                    stored.sequence == event.sequence && stored.attendees.contains(ours)
+                }
+                _ => false,
+            };
+            if let Some(event) = self.calendar.import_event(event).await? {
+                tracing::info!("added `{}` ({}) from an invite", event.summary, event.id);
+                changes += 1;
+                if new {
+                    self.remember_senders(&event.id, email)?;
+                }
+                if let (Some(ours), false) = (ours, replied) {
+                    self.send_reply(&event, &ours).await;
+                }
+                self.forward(&event).await?;
+            }
+        }
+        // Published events are for information, and don't expect a reply
+        for event in email.published() {
+            if !self.organizer_may_change(&event.id, Some(&event.creator_email), sender)? {
+                continue;
+            }
+            let new = self.stored_event(&event.id)?.is_none();
+            if let Some(event) = self.calendar.import_event(event.clone()).await? {
+                tracing::info!("added `{}` ({}) from an invite", event.summary, event.id);
+                changes += 1;
+                if new {
+                    self.remember_senders(&event.id, email)?;
+                }
+                self.forward(&event).await?;
+            }
+        }
+
+        for cancelled in email.cancellations() {
+            let id = cancelled.event_id();
+            if !self.organizer_may_change(&id, cancelled.organizer.as_deref(), sender)? {
+                continue;
+            }
+            let (uid, recurrence_id) = (&cancelled.uid, cancelled.recurrence_id);
+            match self
+                .calendar
+                .cancel_event(uid, recurrence_id, cancelled.sequence)
+                .await?
+            {
+                Some(event) => {
+                    tracing::info!("cancelled `{}` ({})", event.summary, event.id);
+                    changes += 1;
+                    self.forward(&event).await?;
+                }
+                None => tracing::info!("nothing to cancel for {}", cancelled.event_id()),
+            }
+        }
+
+        for reply in email.replies() {
+            let Some(event) = self.current_event(&reply.event).await? else {
+                continue;
+            };
+            let (attendees, others): (Vec<&Attendee>, _) = reply
+                .attendees
+                .iter()
+                .partition(|attendee| is_sender(&attendee.email, sender));
+            for other in others {
+                tracing::warn!(
+                    "ignoring a reply for {} to {} from {sender:?}",
+                    other.email,
+                    event.id
+                );
+            }
+            if attendees.is_empty() {
+                continue;
+            }
+            for attendee in attendees {
+                tracing::info!(
+                    "{} replied {} to {}",
+                    attendee.email,
+                    attendee.status,
+                    event.id
+                );
+                // Replies are kept with us rather than written to the backend, which would
+                // have to rewrite the event (and may not take writes at all)
+                self.calendar.rsvp(&event.id, attendee).await?;
+            }
+            changes += 1;
+        }
+
+        for counter in email.counters() {
+            let Some(event) = self.current_event(&counter.event).await? else {
+                continue;
+            };
+            if !is_sender(&counter.attendee, sender) {
+                tracing::warn!(
+                    "ignoring a proposal by {} for {} from {sender:?}",
+                    counter.attendee,
+                    event.id
+                );
+                continue;
+            }
+            // Until we can mail the organizer, the log is where they find out
+            tracing::info!(
+                "{} proposes moving `{}` ({}) to {} - {}",
+                counter.attendee,
+                event.summary,
+                event.id,
+                counter.start,
+                counter.end
+            );
+            let proposal = CounterProposal {
+                attendee: counter.attendee.clone(),
+                sequence: counter.event.sequence,
+                start: counter.start,
+                end: counter.end,
+                comment: counter.comment.clone(),
+                status: "PROPOSED".to_string(),
+            };
+            self.calendar
+                .store()
+                .add_counter_proposal(&self.stored_id(&event)?, &proposal)?;
+            changes += 1;
+        }
+
+        for declined in email.declined_counters() {
+            let id = declined.event_id();
+            if !self.organizer_may_change(&id, declined.organizer.as_deref(), sender)? {
+                continue;
+            }
+            if let Some(event) = self.current_event(declined).await? {
+                tracing::info!("counter proposals for {} were declined", event.id);
+                let id = self.stored_id(&event)?;
+                self.calendar.store().decline_counter_proposals(&id)?;
+                changes += 1;
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Mark the calendar as going to `event`, returning its entry among the attendees. We
+    /// can only accept invites that list one of our addresses, and don't answer our own.
+    fn accept(&self, event: &mut Event) -> Option<Attendee> {
+        if self.accepts(&event.creator_email) {
+            return None;
+        }
+        let ours = event
+            .attendees
+            .iter()
+            .find(|attendee| self.accepts(&attendee.email))?;
+        let ours = Attendee {
+            status: "ACCEPTED".to_string(),
+            ..ours.clone()
+        };
+        event.set_attendance(&ours);
+        Some(ours)
+    }
+
+    /// Pass the news about `event` on to the members of its teams.
+    async fn forward(&self, event: &Event) -> anyhow::Result<()> {
+        if let Some(invitations) = &self.invitations {
+            invitations.send(event).await?;
+        }
+        Ok(())
+    }
+
+    /// Tell the organizer of `event` what `ours` says. Failures are only logged, as the
+    /// event is on the calendar either way.
+    async fn send_reply(&self, event: &Event, ours: &Attendee) {
+        let Some(transport) = &self.transport else {
+            return;
+        };
+        let message = ical::reply_to_ics(event, ours).and_then(|ics| {
+            OutgoingMessage::itip(
+                &ours.email,
+                &event.creator_email,
+                &format!("Accepted: {}", event.summary),
+                &format!("{} has accepted this invitation.", ours.email),
+                "REPLY",
+                &ics,
+                None,
+            )
+        });
+        let sent = match message {
+            Ok(message) => transport.send(&message).await,
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok(()) => tracing::info!("accepted {} on behalf of {}", event.id, ours.email),
+            Err(e) => tracing::error!("could not reply to the invite to {}: {e:?}", event.id),
+        }
+    }
+
+    /// The event a message is about, unless we don't know it or the message is about
+    /// an older version of it. Occurrences that weren't changed on their own are made
+    /// up from their series, see [`Calendar::event`].
+    async fn current_event(&self, about: &EventRef) -> anyhow::Result<Option<Event>> {
+        let event = match self.calendar.event(&about.event_id()).await? {
+            Some(event) => Some(event),
+            None => self.calendar.event(&about.uid).await?,
+        };
+        match event {
+            Some(event) if event.sequence > about.sequence => {
+                tracing::info!(
+                    "ignoring message about an old version of {} (sequence {} < {})",
+                    event.id,
+                    about.sequence,
+                    event.sequence
+                );
+                Ok(None)
+            }
+            Some(event) => Ok(Some(event)),
+            None => {
+                tracing::info!("ignoring message about unknown event {}", about.event_id());
+                Ok(None)
+            }
+        }
+    }
+
+    /// Whether a message from `sender`, which says `organizer` organizes the event (or
+    /// occurrence) `id`, may change it. Anyone may invite the calendar to a new event,
+    /// but only the organizer of an event we have may change it. They have to send the
+    /// change from their own address, or from one that the invite adding the event came
+    /// from (see [`Inbox::remember_senders`]), as shared calendars' invites do.
+    fn organizer_may_change(
+        &self,
+        id: &str,
+        organizer: Option<&str>,
+        sender: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let Some(stored) = self.stored_event(id)? else {
+            return Ok(true);
+        };
+        let is_organizer = |address: Option<&str>| {
+            address.is_some_and(|a| a.eq_ignore_ascii_case(&stored.creator_email))
+        };
+        // A changed occurrence may have come with the invite for its series
+        let mut senders = self.calendar.store().event_senders(&stored.id)?;
+        if let Some(series_id) = &stored.recurring_event_id {
+            senders.extend(self.calendar.store().event_senders(series_id)?);
+        }
+        let may_send = is_organizer(sender)
+            || sender.is_some_and(|sender| senders.iter().any(|known| known == sender));
+        if is_organizer(organizer) && may_send {
+            return Ok(true);
+        }
+        tracing::warn!(
+            "ignoring a change to {id} by {organizer:?} from {sender:?}, which {} organizes",
+            stored.creator_email
+        );
+        Ok(false)
+    }
+
+    /// The stored event (or occurrence) `id`, or for an occurrence that wasn't changed
+    /// on its own, its series.
+    fn stored_event(&self, id: &str) -> anyhow::Result<Option<Event>> {
+        let store = self.calendar.store();
+        match store.event(id)? {
+            Some(event) => Ok(Some(event)),
+            None => match recurrence::parse_instance_id(id) {
+                Some((series_id, _)) => store.event(series_id),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Remember who sent the invite that added the event `id`, and whom it names as
+    /// sending on the organizer's behalf, so that they can send changes to it. Invites
+    /// from shared calendars come from a person, not from the calendar organizing them.
+    fn remember_senders(&self, id: &str, email: &CalendarEmail) -> anyhow::Result<()> {
+        let store = self.calendar.store();
+        for sender in email
+            .sender()
+            .into_iter()
+            .chain(email.delegates().iter().map(String::as_str))
+        {
+            store.add_event_sender(id, sender)?;
+        }
+        Ok(())
+    }
+
+    /// The id that what we know about `event` is stored under: its own, or that of its
+    /// series for an occurrence that wasn't changed on its own.
+    fn stored_id(&self, event: &Event) -> anyhow::Result<String> {
+        match (
+            &event.recurring_event_id,
+            self.calendar.store().event(&event.id)?,
+        ) {
+            (Some(series_id), None) => Ok(series_id.clone()),
+            _ => Ok(event.id.clone()),
+        }
+    }
+}
+
+/// Whether `address` is the `sender` of a message.
+fn is_sender(address: &str, sender: Option<&str>) -> bool {
+    sender.is_some_and(|sender| sender.eq_ignore_ascii_case(address))
+}
+
+/// The `Message-ID` of a raw message, without the angle brackets.
+pub fn message_id(message: &str) -> Option<String> {
+    let message = MessageParser::default().parse(message)?;
+    message.message_id().map(str::to_string)
+}
+
+/// A directory for a test to write to, which goes away with everything in it when the
+/// test is done.
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn create() -> Self {
+        let path = std::env::temp_dir().join(format!("eventageous-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// An inbox for `calendar@example.org` that doesn't send mail, adding events to a
+/// calendar that knows `teams` and keeps its `.ics` files in `calendar/` of the
+/// returned directory.
+#[cfg(test)]
+pub fn test_inbox(teams: &[&str]) -> (TempDir, Arc<Calendar>, Inbox) {
+    use crate::calendar::{backend, Team};
+    use crate::config::{BackendConfig, Configuration};
+    use crate::store::Store;
+
+    let temp = TempDir::create();
+    let path = temp.path().join("calendar");
+    std::fs::create_dir_all(&path).unwrap();
+    let mut config = Configuration::new(String::new(), String::new());
+    config.backend = BackendConfig::IcsDirectory { path };
+    let store = Arc::new(Store::in_memory().unwrap());
+    for team in teams {
+        store
+            .upsert_team(&Team {
+                name: team.to_string(),
+                description: None,
+            })
+            .unwrap();
+    }
+    let backend = backend::from_config(&Arc::new(config)).unwrap();
+    let calendar = Arc::new(Calendar::new(backend, store));
+    let inbox = Inbox::new(
+        calendar.clone(),
+        "calendar@example.org".to_string(),
+        None,
+        None,
+    );
+    (temp, calendar, inbox)
+}
+
+#[tokio::test]
+async fn test_deliver_itip() {
+    use crate::calendar::EventQuery;
+
+    let (temp, calendar, inbox) = test_inbox(&[]);
+    let (store, inbox) = (calendar.store(), &inbox);
+    let calendar_dir = temp.path().join("calendar");
+
+    let deliver = |from: &str, method: &str, vevent: &str| {
+        let message = format!(
+            "From: {from}\r\nTo: calendar@example.org\r\nMIME-Version: 1.0\r\n\
+             Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n--b\r\n\
+             Content-Type: text/calendar; method={method}\r\n\r\n\
+             BEGIN:VCALENDAR\r\nVERSION:2.0\r\nMETHOD:{method}\r\nBEGIN:VEVENT\r\n\
+             UID:triage@example.org\r\nDTSTAMP:20240301T000000Z\r\n\
+             ORGANIZER:mailto:jane@example.org\r\n{vevent}END:VEVENT\r\n\
+             END:VCALENDAR\r\n--b--\r\n"
+        );
+        async move {
+            let email = inbox.parse(&message, &[]).await.unwrap();
+            inbox.deliver(&email).await.unwrap()
+        }
+    };
+    let starts = || async {
+        let query = EventQuery {
+            from: Some("2024-03-01T00:00:00Z".parse().unwrap()),
+            to: Some("2024-03-21T00:00:00Z".parse().unwrap()),
+            ..EventQuery::default()
+        };
+        let events = calendar.events(&query).await.unwrap().events;
+        events
+            .into_iter()
+            .map(|event| event.start.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let request = "SUMMARY:Triage\r\nDTSTART:20240306T160000Z\r\nDTEND:20240306T170000Z\r\n\
+                   RRULE:FREQ=WEEKLY\r\n";
+    assert_eq!(deliver("jane@example.org", "REQUEST", request).await, 1);
+    let cancel = "SEQUENCE:1\r\nRECURRENCE-ID:20240313T160000Z\r\n";
+    assert_eq!(deliver("jane@example.org", "CANCEL", cancel).await, 1);
+    assert_eq!(deliver("jane@example.org", "CANCEL", cancel).await, 1);
+    assert_eq!(
+        starts().await,
+        ["2024-03-06T16:00:00Z", "2024-03-20T16:00:00Z"]
+    );
+    // The cancelled occurrence shares the UID of the series, so it is stored with it
+    assert_eq!(std::fs::read_dir(&calendar_dir).unwrap().count(), 1);
+    // Only the organizer can change or cancel the event
+    let forged = "SEQUENCE:5\r\nSUMMARY:Not triage\r\nDTSTART:20240306T160000Z\r\n\
+                  DTEND:20240306T170000Z\r\n";
+    assert_eq!(deliver("mallory@example.org", "REQUEST", forged).await, 0);
+    assert_eq!(
+        deliver("mallory@example.org", "CANCEL", "SEQUENCE:5\r\n").await,
+        0
+    );
+    let stored = calendar.event("triage@example.org").await.unwrap().unwrap();
+    assert_eq!(stored.summary, "Triage");
+    assert_eq!(starts().await.len(), 2);
+    // A stale copy of the request doesn't bring the occurrence back
+    let stale = "RECURRENCE-ID:20240313T160000Z\r\nDTSTART:20240313T160000Z\r\n";
+    assert_eq!(deliver("jane@example.org", "REQUEST", stale).await, 0);
+    assert_eq!(starts().await.len(), 2);
+
+    let reply = "ATTENDEE;PARTSTAT=ACCEPTED;CN=Niko:mailto:niko@example.org\r\n";
+    assert_eq!(deliver("niko@example.org", "REPLY", reply).await, 1);
+    let attendees = || async {
+        let event = calendar.event("triage@example.org").await.unwrap().unwrap();
+        event.attendees
+    };
+    assert_eq!(attendees().await[0].email, "niko@example.org");
+    assert_eq!(attendees().await[0].status, "ACCEPTED");
+    // The backend is left alone
+    let file = std::fs::read_dir(&calendar_dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(!std::fs::read_to_string(file.path())
+        .unwrap()
+        .contains("niko@example.org"));
+    // Attendees only answer for themselves
+    let forged = "ATTENDEE;PARTSTAT=DECLINED:mailto:niko@example.org\r\n";
+    assert_eq!(deliver("mallory@example.org", "REPLY", forged).await, 0);
+    let forged = "ATTENDEE:mailto:niko@example.org\r\nDTSTART:20240308T160000Z\r\n\
+                  DTEND:20240308T170000Z\r\n";
+    assert_eq!(deliver("mallory@example.org", "COUNTER", forged).await, 0);
+    assert_eq!(attendees().await[0].status, "ACCEPTED");
+    // Replies about one occurrence are kept with the series, but only count for it
+    let reply = "RECURRENCE-ID:20240320T160000Z\r\n\
+                 ATTENDEE;PARTSTAT=DECLINED;CN=Niko:mailto:niko@example.org\r\n";
+    assert_eq!(deliver("niko@example.org", "REPLY", reply).await, 1);
+    let occurrence = calendar
+        .event("triage@example.org_20240320T160000Z")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(occurrence.attendees[0].status, "DECLINED");
+    assert_eq!(attendees().await[0].status, "ACCEPTED");
+
+    let counter = "ATTENDEE:mailto:niko@example.org\r\nDTSTART:20240307T160000Z\r\n\
+                   DTEND:20240307T170000Z\r\nCOMMENT:Thursdays are better\r\n";
+    assert_eq!(deliver("niko@example.org", "COUNTER", counter).await, 1);
+    assert_eq!(deliver("jane@example.org", "DECLINECOUNTER", "").await, 1);
+    let proposals = store.counter_proposals("triage@example.org").unwrap();
+    assert_eq!(proposals.len(), 1);
+    assert_eq!(
+        proposals[0].comment.as_deref(),
+        Some("Thursdays are better")
+    );
+    assert_eq!(proposals[0].status, "DECLINED");
+
+    assert_eq!(
+        deliver("jane@example.org", "CANCEL", "SEQUENCE:2\r\n").await,
+        1
+    );
+    assert!(starts().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_accept_invite() {
+    let (temp, calendar, mut inbox) = test_inbox(&[]);
+    let outbox = temp.path().join("outbox");
+    inbox.transport = Some(Arc::new(outbound::Outbox::new(&outbox)));
+
+    let message = "From: jane@example.org\r\nTo: calendar@example.org\r\n\
+                   MIME-Version: 1.0\r\nContent-Type: text/calendar; method=REQUEST\r\n\r\n\
+                   BEGIN:VCALENDAR\r\nVERSION:2.0\r\nMETHOD:REQUEST\r\nBEGIN:VEVENT\r\n\
+                   UID:triage@example.org\r\nDTSTAMP:20240301T000000Z\r\nSUMMARY:Triage\r\n\
+                   DTSTART:20240306T160000Z\r\nDTEND:20240306T170000Z\r\n\
+                   ORGANIZER;CN=Jane:mailto:jane@example.org\r\n\
+                   ATTENDEE;PARTSTAT=NEEDS-ACTION:mailto:calendar@example.org\r\n\
+                   END:VEVENT\r\nEND:VCALENDAR\r\n";
+    let email = inbox.parse(message, &[]).await.unwrap();
+    assert_eq!(inbox.deliver(&email).await.unwrap(), 1);
+    let sent = std::fs::read_dir(&outbox)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(sent.len(), 1);
+    let reply = std::fs::read_to_string(&sent[0]).unwrap();
+    assert!(reply.contains("To: jane@example.org"));
+    assert!(reply.contains("METHOD:REPLY"));
+    assert!(reply.contains("PARTSTAT=ACCEPTED"));
+    let event = calendar.event("triage@example.org").await.unwrap().unwrap();
+    assert_eq!(event.attendees[0].status, "ACCEPTED");
+
+    // The same invite again doesn't need another answer
+    let email = inbox.parse(message, &[]).await.unwrap();
+    inbox.deliver(&email).await.unwrap();
+    assert_eq!(std::fs::read_dir(&outbox).unwrap().count(), 1);
+}
+
+#[tokio::test]
+async fn test_categories_are_not_teams() {
+    let (_temp, calendar, inbox) = test_inbox(&["lang"]);
+
+    let message = "From: jane@example.org\r\nTo: calendar@example.org\r\n\
+                   MIME-Version: 1.0\r\nContent-Type: text/calendar; method=REQUEST\r\n\r\n\
+                   BEGIN:VCALENDAR\r\nVERSION:2.0\r\nMETHOD:REQUEST\r\nBEGIN:VEVENT\r\n\
+                   UID:triage@example.org\r\nDTSTAMP:20240301T000000Z\r\nSUMMARY:Triage\r\n\
+                   DTSTART:20240306T160000Z\r\nDTEND:20240306T170000Z\r\n\
+                   ORGANIZER;CN=Jane:mailto:jane@example.org\r\n\
+                   CATEGORIES:Lang,made-up\r\n\
+                   END:VEVENT\r\nEND:VCALENDAR\r\n";
+    let email = inbox.parse(message, &[]).await.unwrap();
+    assert_eq!(inbox.deliver(&email).await.unwrap(), 1);
+    let event = calendar.event("triage@example.org").await.unwrap().unwrap();
+    assert_eq!(event.teams, ["lang"]);
+
+    // A category doesn't make a team that mail can be routed to
+    assert_eq!(
+        inbox
+            .unknown_teams("calendar+made-up@example.org")
+            .await
+            .unwrap(),
+        ["made-up"]
+    );
+    assert!(inbox
+        .parse(message, &["calendar+made-up@example.org".to_string()])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_shared_calendar_invite() {
+    // Google sends the invites of a shared calendar from the person who made the
+    // event, with the calendar as its organizer
+    let (_temp, calendar, inbox) = test_inbox(&[]);
+    let inbox = &inbox;
+    let deliver = |message: String| async move {
+        let email = inbox.parse(&message, &[]).await.unwrap();
+        inbox.deliver(&email).await.unwrap()
+    };
+    let invite = include_str!("../test_data/invite.eml").replace('\n', "\r\n");
+    assert_eq!(deliver(invite.clone()).await, 1);
+
+    let update = |sequence: u32, summary: &str| {
+        invite
+            .replacen("SEQUENCE:0", &format!("SEQUENCE:{sequence}"), 1)
+            .replacen("SUMMARY:Lang team triage", &format!("SUMMARY:{summary}"), 1)
+    };
+    assert_eq!(deliver(update(1, "Lang team triage (moved)")).await, 1);
+    let forged = update(2, "Not triage").replacen(
+        "From: Nicholas Matsakis <rust@nikomatsakis.com>",
+        "From: Mallory <mallory@example.org>",
+        1,
+    );
+    assert_eq!(deliver(forged).await, 0);
+    let event = calendar
+        .event("6v2ielbusc7s08p9ev6f40g4en@google.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(event.summary, "Lang team triage (moved)");
+
+    // Someone the organizer says sends on their behalf may send changes too
+    let message = |from: &str, sequence: u32| {
+        format!(
+            "From: {from}\r\nTo: calendar@example.org\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/calendar; method=REQUEST\r\n\r\n\
+             BEGIN:VCALENDAR\r\nVERSION:2.0\r\nMETHOD:REQUEST\r\nBEGIN:VEVENT\r\n\
+             UID:triage@example.org\r\nDTSTAMP:20240301T000000Z\r\nSUMMARY:Triage\r\n\
+             DTSTART:20240306T160000Z\r\nDTEND:20240306T170000Z\r\nSEQUENCE:{sequence}\r\n\
+             ORGANIZER;SENT-BY=\"mailto:Assistant@example.org\":mailto:jane@example.org\r\n\
+             END:VEVENT\r\nEND:VCALENDAR\r\n"
+        )
+    };
+    assert_eq!(deliver(message("jane@example.org", 0)).await, 1);
+    assert_eq!(deliver(message("assistant@example.org", 1)).await, 1);
+    // Saying so later doesn't count, as anyone could
+    assert_eq!(deliver(message("mallory@example.org", 2)).await, 0);
+}
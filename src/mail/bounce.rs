@@ -0,0 +1,192 @@
+//! Delivery status notifications (RFC 3464), the bounces that tell us mail we sent
+//! didn't arrive.
+
+use mail_parser::{MessageParser, MimeHeaders};
+
+/// What a bounce says about one of our messages.
+#[derive(Debug, PartialEq)]
+pub struct Bounce {
+    /// The `Message-ID` of the message that bounced, if the report includes it.
+    pub message_id: Option<String>,
+    pub failures: Vec<Failure>,
+}
+
+/// A recipient that the message couldn't be delivered to.
+#[derive(Debug, PartialEq)]
+pub struct Failure {
+    pub recipient: String,
+    /// The enhanced status code, such as `5.1.1`.
+    pub status: Option<String>,
+    /// What the recipient's server said, if the report says.
+    pub diagnostic: Option<String>,
+}
+
+impl Failure {
+    /// A one-line description for the delivery log.
+    pub fn reason(&self) -> String {
+        match (&self.status, &self.diagnostic) {
+            (_, Some(diagnostic)) => format!("bounced: {diagnostic}"),
+            (Some(status), None) => format!("bounced with status {status}"),
+            (None, None) => "bounced".to_string(),
+        }
+    }
+}
+
+/// Parse `raw` if it is a delivery status notification, or return `None` if it's some
+/// other message. Delays and successful deliveries are left out of the failures.
+pub fn parse_bounce(raw: &str) -> Option<Bounce> {
+    let message = MessageParser::default().parse(raw)?;
+    let is_report = message.content_type().is_some_and(|content_type| {
+        content_type.ctype() == "multipart"
+            && content_type.subtype() == Some("report")
+            && content_type
+                .attribute("report-type")
+                .is_some_and(|report_type| report_type.eq_ignore_ascii_case("delivery-status"))
+    });
+    if !is_report {
+        return None;
+    }
+
+    let mut bounce = Bounce {
+        message_id: None,
+        failures: vec![],
+    };
+    for part in message.parts.iter() {
+        let Some(content_type) = part.content_type() else {
+            continue;
+        };
+        match (content_type.ctype(), content_type.subtype()) {
+            ("message", Some("delivery-status" | "global-delivery-status")) => {
+                let status = String::from_utf8_lossy(part.contents());
+                bounce.failures.extend(failures(&status));
+            }
+            // The original message, or just its headers
+            ("message", Some("rfc822" | "global")) => {
+                if let Some(original) = part.message() {
+                    bounce.message_id = original.message_id().map(str::to_string);
+                }
+            }
+            ("text", Some("rfc822-headers")) => {
+                let headers = MessageParser::default().parse(part.contents());
+                if let Some(id) = headers.as_ref().and_then(|headers| headers.message_id()) {
+                    bounce.message_id = Some(id.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(bounce)
+}
+
+/// The failed recipients in the body of a `message/delivery-status` part: a group of
+/// fields about the reporting MTA, then one group per recipient.
+fn failures(status: &str) -> Vec<Failure> {
+    let status = status.replace("\r\n", "\n");
+    status
+        .split("\n\n")
+        .filter_map(|group| {
+            let fields = fields(group);
+            let field = |name: &str| {
+                fields
+                    .iter()
+                    .find(|(field, _)| field.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value.clone())
+            };
+            if !field("Action")?.eq_ignore_ascii_case("failed") {
+                return None;
+            }
+            // `rfc822; niko@example.org`
+            let recipient = field("Final-Recipient").or_else(|| field("Original-Recipient"))?;
+            let recipient = recipient
+                .split_once(';')
+                .map_or(recipient.as_str(), |(_, address)| address)
+                .trim();
+            Some(Failure {
+                recipient: recipient.to_string(),
+                status: field("Status"),
+                diagnostic: field("Diagnostic-Code").map(|diagnostic| {
+                    diagnostic
+                        .split_once(';')
+                        .map_or(diagnostic.as_str(), |(_, text)| text)
+                        .trim()
+                        .to_string()
+                }),
+            })
+        })
+        .collect()
+}
+
+/// The `Name: value` fields of a group, with folded lines joined up.
+fn fields(group: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = vec![];
+    for line in group.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    fields
+}
+
+#[test]
+fn test_parse_bounce() {
+    let raw = "From: MAILER-DAEMON@mx.example.org\r\n\
+               To: calendar@example.org\r\n\
+               Subject: Undelivered Mail Returned to Sender\r\n\
+               MIME-Version: 1.0\r\n\
+               Content-Type: multipart/report; report-type=delivery-status; boundary=\"b\"\r\n\
+               \r\n\
+               --b\r\n\
+               Content-Type: text/plain\r\n\
+               \r\n\
+               Your message could not be delivered.\r\n\
+               --b\r\n\
+               Content-Type: message/delivery-status\r\n\
+               \r\n\
+               Reporting-MTA: dns; mx.example.org\r\n\
+               \r\n\
+               Final-Recipient: rfc822; nobody@example.org\r\n\
+               Action: failed\r\n\
+               Status: 5.1.1\r\n\
+               Diagnostic-Code: smtp; 550 5.1.1 <nobody@example.org>:\r\n\
+               \x20   Recipient address rejected\r\n\
+               \r\n\
+               Final-Recipient: rfc822; slow@example.org\r\n\
+               Action: delayed\r\n\
+               Status: 4.4.1\r\n\
+               \r\n\
+               --b\r\n\
+               Content-Type: text/rfc822-headers\r\n\
+               \r\n\
+               From: calendar@example.org\r\n\
+               Message-ID: <1234@example.org>\r\n\
+               \r\n\
+               --b--\r\n";
+    expect_test::expect![[r#"
+        Some(
+            Bounce {
+                message_id: Some(
+                    "1234@example.org",
+                ),
+                failures: [
+                    Failure {
+                        recipient: "nobody@example.org",
+                        status: Some(
+                            "5.1.1",
+                        ),
+                        diagnostic: Some(
+                            "550 5.1.1 <nobody@example.org>: Recipient address rejected",
+                        ),
+                    },
+                ],
+            },
+        )
+    "#]]
+    .assert_debug_eq(&parse_bounce(raw));
+
+    assert_eq!(parse_bounce("From: jane@example.org\r\n\r\nHi!\r\n"), None);
+}
@@ -0,0 +1,326 @@
+//! A mailbox on an IMAP (RFC 3501) server.
+//!
+//! Each poll logs in, fetches the unseen messages without marking them, and then logs in
+//! again to flag the processed ones as seen and move the quarantined ones.
+
+use std::borrow::Cow;
+
+use async_trait::async_trait;
+use imap_proto::types::{AttributeValue, MailboxDatum, Response, Status};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::poller::{with_error_header, IncomingMessage, Mailbox, Outcome};
+
+pub struct ImapMailbox {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub username: String,
+    pub password: String,
+    /// The folder to take messages from, usually `INBOX`.
+    pub mailbox: String,
+    /// The folder that messages we can't handle are moved to.
+    pub quarantine: String,
+}
+
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// A logged in connection, speaking just enough IMAP for the poller.
+struct Session<S> {
+    stream: BufReader<S>,
+    next_tag: u32,
+}
+
+impl ImapMailbox {
+    async fn connect(&self) -> anyhow::Result<Session<Box<dyn Connection>>> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let stream: Box<dyn Connection> = match self.tls {
+            true => {
+                let connector = tokio_native_tls::native_tls::TlsConnector::new()?;
+                let connector = tokio_native_tls::TlsConnector::from(connector);
+                Box::new(connector.connect(&self.host, tcp).await?)
+            }
+            false => Box::new(tcp),
+        };
+        let mut session = Session::new(stream).await?;
+        session
+            .command(&format!(
+                "LOGIN {} {}",
+                quote(&self.username)?,
+                quote(&self.password)?
+            ))
+            .await?;
+        session
+            .command(&format!("SELECT {}", quote(&self.mailbox)?))
+            .await?;
+        Ok(session)
+    }
+}
+
+#[async_trait]
+impl Mailbox for ImapMailbox {
+    async fn fetch(&self) -> anyhow::Result<Vec<IncomingMessage>> {
+        let mut session = self.connect().await?;
+        let messages = session.fetch_unseen().await?;
+        session.logout().await?;
+        Ok(messages)
+    }
+
+    async fn settle(&self, settled: &[(IncomingMessage, Outcome)]) -> anyhow::Result<()> {
+        if settled.is_empty() {
+            return Ok(());
+        }
+        let mut session = self.connect().await?;
+        session.settle(settled, &self.quarantine).await?;
+        session.logout().await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Session<S> {
+    /// Start a session on `stream`, reading the server's greeting.
+    async fn new(stream: S) -> anyhow::Result<Self> {
+        let mut session = Session {
+            stream: BufReader::new(stream),
+            next_tag: 1,
+        };
+        let greeting = session.read_response().await?;
+        match Response::from_bytes(&greeting) {
+            Ok((_, Response::Data { status, .. })) if status != Status::Bye => Ok(session),
+            _ => anyhow::bail!(
+                "unexpected greeting: {}",
+                String::from_utf8_lossy(&greeting)
+            ),
+        }
+    }
+
+    /// The unseen messages, by UID.
+    async fn fetch_unseen(&mut self) -> anyhow::Result<Vec<IncomingMessage>> {
+        let mut uids = vec![];
+        for response in self.command("UID SEARCH UNSEEN").await? {
+            if let Response::MailboxData(MailboxDatum::Search(found)) = response {
+                uids.extend(found);
+            }
+        }
+        uids.sort();
+
+        let mut messages = vec![];
+        for uid in uids {
+            // `BODY.PEEK` leaves the message unseen until it is settled
+            for response in self
+                .command(&format!("UID FETCH {uid} BODY.PEEK[]"))
+                .await?
+            {
+                let Response::Fetch(_, attributes) = response else {
+                    continue;
+                };
+                let body = attributes
+                    .into_iter()
+                    .find_map(|attribute| match attribute {
+                        AttributeValue::BodySection { data, .. } => data,
+                        _ => None,
+                    });
+                if let Some(body) = body {
+                    messages.push(IncomingMessage {
+                        key: uid.to_string(),
+                        raw: String::from_utf8_lossy(&body).into_owned(),
+                    });
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    async fn settle(
+        &mut self,
+        settled: &[(IncomingMessage, Outcome)],
+        quarantine: &str,
+    ) -> anyhow::Result<()> {
+        let mut expunge = false;
+        for (message, outcome) in settled {
+            let uid = &message.key;
+            match outcome {
+                Outcome::Processed => {
+                    self.command(&format!("UID STORE {uid} +FLAGS.SILENT (\\Seen)"))
+                        .await?;
+                }
+                Outcome::Quarantined(error) => {
+                    // Creating a folder that exists fails, which is fine
+                    let _ = self
+                        .command(&format!("CREATE {}", quote(quarantine)?))
+                        .await;
+                    let raw = with_error_header(&message.raw, error);
+                    self.append(quarantine, raw.as_bytes()).await?;
+                    self.command(&format!("UID STORE {uid} +FLAGS.SILENT (\\Seen \\Deleted)"))
+                        .await?;
+                    expunge = true;
+                }
+            }
+        }
+        if expunge {
+            self.command("EXPUNGE").await?;
+        }
+        Ok(())
+    }
+
+    async fn logout(&mut self) -> anyhow::Result<()> {
+        self.command("LOGOUT").await?;
+        Ok(())
+    }
+
+    /// Send a command and return the untagged responses to it, failing unless the
+    /// server completes it with `OK`.
+    async fn command(&mut self, command: &str) -> anyhow::Result<Vec<Response<'static>>> {
+        let tag = self.send(command).await?;
+        self.responses(&tag).await
+    }
+
+    /// Add `message` to `mailbox`, sending it as a literal once the server is ready.
+    async fn append(&mut self, mailbox: &str, message: &[u8]) -> anyhow::Result<()> {
+        let command = format!("APPEND {} (\\Seen) {{{}}}", quote(mailbox)?, message.len());
+        let tag = self.send(&command).await?;
+        let response = self.read_response().await?;
+        if !matches!(
+            Response::from_bytes(&response),
+            Ok((_, Response::Continue { .. }))
+        ) {
+            anyhow::bail!("APPEND refused: {}", String::from_utf8_lossy(&response));
+        }
+        self.stream.write_all(message).await?;
+        self.stream.write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+        self.responses(&tag).await?;
+        Ok(())
+    }
+
+    async fn send(&mut self, command: &str) -> anyhow::Result<String> {
+        let tag = format!("A{:04}", self.next_tag);
+        self.next_tag += 1;
+        self.stream
+            .write_all(format!("{tag} {command}\r\n").as_bytes())
+            .await?;
+        self.stream.flush().await?;
+        Ok(tag)
+    }
+
+    /// Read responses up to the one tagged `tag`.
+    async fn responses(&mut self, tag: &str) -> anyhow::Result<Vec<Response<'static>>> {
+        let mut responses = vec![];
+        loop {
+            let bytes = self.read_response().await?;
+            let response = match Response::from_bytes(&bytes) {
+                Ok((_, response)) => response.into_owned(),
+                Err(_) => {
+                    tracing::info!("ignoring IMAP response {}", String::from_utf8_lossy(&bytes));
+                    continue;
+                }
+            };
+            match response {
+                Response::Done {
+                    tag: done,
+                    status,
+                    information,
+                    ..
+                } if done.0 == tag => {
+                    if status != Status::Ok {
+                        let information = information.unwrap_or(Cow::Borrowed(""));
+                        anyhow::bail!("IMAP command failed: {status:?} {information}");
+                    }
+                    return Ok(responses);
+                }
+                response => responses.push(response),
+            }
+        }
+    }
+
+    /// Read one response, including any literals (`{42}` followed by 42 bytes) in it.
+    async fn read_response(&mut self) -> anyhow::Result<Vec<u8>> {
+        let mut response = vec![];
+        loop {
+            if self.stream.read_until(b'\n', &mut response).await? == 0 {
+                anyhow::bail!("IMAP server closed the connection");
+            }
+            let Some(length) = literal_length(&response) else {
+                return Ok(response);
+            };
+            let start = response.len();
+            response.resize(start + length, 0);
+            self.stream.read_exact(&mut response[start..]).await?;
+        }
+    }
+}
+
+/// The length of the literal announced at the end of `line`, as in `... {42}\r\n`.
+fn literal_length(line: &[u8]) -> Option<usize> {
+    let line = line
+        .strip_suffix(b"\r\n")
+        .or_else(|| line.strip_suffix(b"\n"))?;
+    let line = line.strip_suffix(b"}")?;
+    let open = line.iter().rposition(|&b| b == b'{')?;
+    std::str::from_utf8(&line[open + 1..]).ok()?.parse().ok()
+}
+
+/// An IMAP quoted string.
+fn quote(value: &str) -> anyhow::Result<String> {
+    if value.contains(['\r', '\n']) {
+        anyhow::bail!("line breaks can't be quoted");
+    }
+    Ok(format!(
+        "\"{}\"",
+        value.replace('\\', "\\\\").replace('"', "\\\"")
+    ))
+}
+
+#[tokio::test]
+async fn test_session() {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let script = [
+        ("", "* OK IMAP4rev1 ready\r\n"),
+        (
+            "A0001 UID SEARCH UNSEEN\r\n",
+            "* SEARCH 7\r\nA0001 OK done\r\n",
+        ),
+        (
+            "A0002 UID FETCH 7 BODY.PEEK[]\r\n",
+            "* 1 FETCH (UID 7 BODY[] {13}\r\nSubject: hi\r\n)\r\nA0002 OK done\r\n",
+        ),
+        ("A0003 CREATE \"Quarantine\"\r\n", "A0003 NO exists\r\n"),
+        (
+            "A0004 APPEND \"Quarantine\" (\\Seen) {45}\r\n",
+            "+ go ahead\r\n",
+        ),
+        (
+            "X-Eventageous-Error: no invite\r\nSubject: hi\r\n\r\n",
+            "A0004 OK appended\r\n",
+        ),
+        (
+            "A0005 UID STORE 7 +FLAGS.SILENT (\\Seen \\Deleted)\r\n",
+            "A0005 OK stored\r\n",
+        ),
+        ("A0006 EXPUNGE\r\n", "* 1 EXPUNGE\r\nA0006 OK expunged\r\n"),
+    ];
+    let server = tokio::spawn(async move {
+        let mut server = BufReader::new(server);
+        for (expected, reply) in script {
+            let mut received = vec![0; expected.len()];
+            server.read_exact(&mut received).await.unwrap();
+            assert_eq!(String::from_utf8_lossy(&received), expected);
+            server.write_all(reply.as_bytes()).await.unwrap();
+        }
+    });
+
+    let mut session = Session::new(client).await.unwrap();
+    let messages = session.fetch_unseen().await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].key, "7");
+    assert_eq!(messages[0].raw, "Subject: hi\r\n");
+
+    let settled = [(
+        messages[0].clone(),
+        Outcome::Quarantined("no invite".to_string()),
+    )];
+    session.settle(&settled, "Quarantine").await.unwrap();
+    server.await.unwrap();
+}
@@ -0,0 +1,378 @@
+//! Forwarding events to the members of their teams.
+//!
+//! Each member gets an invite of their own, with the calendar address as the
+//! organizer so that their replies come back to us. We remember which `SEQUENCE` of the
+//! event everyone was sent, so that only changes are sent again, and people who are no
+//! longer invited (or everyone, when the event is cancelled) are told it's off.
+//!
+//! Subscribers are invited as optional participants, and their invites come with a
+//! link to unsubscribe (if we know the `public_url` to link to).
+
+use std::sync::Arc;
+
+use crate::calendar::{ical, Attendee, Event, Visibility};
+use crate::roster::Roster;
+use crate::store::{Invitation, Store};
+
+use super::outbound::{OutgoingMessage, Transport};
+
+pub struct Invitations {
+    store: Arc<Store>,
+    roster: Arc<Roster>,
+    transport: Arc<dyn Transport>,
+    calendar_address: String,
+    public_url: Option<String>,
+}
+
+impl Invitations {
+    pub fn new(
+        store: Arc<Store>,
+        roster: Arc<Roster>,
+        transport: Arc<dyn Transport>,
+        calendar_address: String,
+        public_url: Option<String>,
+    ) -> Self {
+        Self {
+            store,
+            roster,
+            transport,
+            calendar_address,
+            public_url,
+        }
+    }
+
+    /// The link that unsubscribes `email` from the event `event_id`, if we know where
+    /// the app is.
+    pub fn unsubscribe_url(&self, email: &str, event_id: &str) -> anyhow::Result<Option<String>> {
+        let Some(public_url) = &self.public_url else {
+            return Ok(None);
+        };
+        let token = self.store.unsubscribe_token(email, event_id)?;
+        Ok(Some(format!(
+            "{}/api/unsubscribe/{token}",
+            public_url.trim_end_matches('/')
+        )))
+    }
+
+    /// Who should be invited to `event`: the members of its teams, and the people who
+    /// subscribed to it or its teams. Private events only go to team members.
+    pub fn recipients(&self, event: &Event) -> anyhow::Result<Vec<Attendee>> {
+        let roster = self.roster.current();
+        let mut recipients: Vec<Attendee> = vec![];
+        let mut add = |email: &str, name: Option<&str>, role: &str| {
+            let known = recipients
+                .iter()
+                .any(|recipient| recipient.email.eq_ignore_ascii_case(email));
+            if !known && !email.eq_ignore_ascii_case(&event.creator_email) {
+                recipients.push(Attendee {
+                    email: email.to_string(),
+                    name: name.map(str::to_string),
+                    status: "NEEDS-ACTION".to_string(),
+                    role: Some(role.to_string()),
+                });
+            }
+        };
+        for team in &event.teams {
+            for person in roster.members(team) {
+                if let Some(email) = &person.email {
+                    add(email, Some(&person.name), "REQ-PARTICIPANT");
+                }
+            }
+        }
+        if event.visibility == Visibility::Public {
+            for email in self.store.subscribers(event)? {
+                add(&email, None, "OPT-PARTICIPANT");
+            }
+        }
+        Ok(recipients)
+    }
+
+    /// Bring everyone up to date on `event`, returning how many messages were sent.
+    /// Failing to send to someone is logged, and retried with the next change.
+    pub async fn send(&self, event: &Event) -> anyhow::Result<usize> {
+        self.send_to_matching(event, |_| true).await
+    }
+
+    /// Bring just `email` up to date on `event`, say after they (un)subscribed. Returns
+    /// whether a message was sent.
+    pub async fn send_to(&self, event: &Event, email: &str) -> anyhow::Result<bool> {
+        let sent = self
+            .send_to_matching(event, |recipient| recipient.eq_ignore_ascii_case(email))
+            .await?;
+        Ok(sent > 0)
+    }
+
+    /// Who has an invite to the event `event_id` that is still on. Deleting an event
+    /// forgets whom it was sent to, so this has to be asked before.
+    pub fn invited(&self, event_id: &str) -> anyhow::Result<Vec<String>> {
+        let mut sent = self.store.invitations(event_id)?;
+        sent.retain(|invitation| invitation.method == "REQUEST");
+        Ok(sent
+            .into_iter()
+            .map(|invitation| invitation.email)
+            .collect())
+    }
+
+    /// Tell the people who were `invited` to `event` that it's off, once it's deleted.
+    /// Returns how many were told; failures are logged, as there is nothing to retry
+    /// them with.
+    pub async fn cancel_deleted(&self, event: &Event, invited: &[String]) -> usize {
+        let mut messages = 0;
+        for email in invited {
+            let recipient = Attendee {
+                email: email.clone(),
+                name: None,
+                status: "NEEDS-ACTION".to_string(),
+                role: None,
+            };
+            let result = async {
+                let message = self.message(event, &recipient, "CANCEL")?;
+                self.transport.send(&message).await
+            };
+            match result.await {
+                Ok(()) => {
+                    tracing::info!("sent CANCEL for {} to {email}", event.id);
+                    messages += 1;
+                }
+                Err(e) => {
+                    tracing::error!("could not send CANCEL for {} to {email}: {e:?}", event.id)
+                }
+            }
+        }
+        messages
+    }
+
+    async fn send_to_matching(
+        &self,
+        event: &Event,
+        matches: impl Fn(&str) -> bool,
+    ) -> anyhow::Result<usize> {
+        let mut sent = self.store.invitations(&event.id)?;
+        sent.retain(|invitation| matches(&invitation.email));
+        let mut recipients = match event.is_cancelled() {
+            true => vec![],
+            false => self.recipients(event)?,
+        };
+        recipients.retain(|recipient| matches(&recipient.email));
+        let last_sent = |email: &str| {
+            sent.iter()
+                .find(|invitation| invitation.email.eq_ignore_ascii_case(email))
+        };
+
+        let mut messages = 0;
+        for recipient in &recipients {
+            let current = last_sent(&recipient.email).is_some_and(|invitation| {
+                invitation.method == "REQUEST" && invitation.sequence >= event.sequence
+            });
+            if !current && self.send_one(event, recipient, "REQUEST").await {
+                messages += 1;
+            }
+        }
+        for invitation in &sent {
+            let invited = recipients
+                .iter()
+                .any(|recipient| recipient.email.eq_ignore_ascii_case(&invitation.email));
+            if invitation.method == "REQUEST" && !invited {
+                let recipient = Attendee {
+                    email: invitation.email.clone(),
+                    name: None,
+                    status: "NEEDS-ACTION".to_string(),
+                    role: None,
+                };
+                if self.send_one(event, &recipient, "CANCEL").await {
+                    messages += 1;
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Send `recipient` their copy of `event`, and remember it if that worked.
+    async fn send_one(&self, event: &Event, recipient: &Attendee, method: &str) -> bool {
+        let result = async {
+            let message = self.message(event, recipient, method)?;
+            self.transport.send(&message).await?;
+            self.store.record_invitation(
+                &event.id,
+                &Invitation {
+                    email: recipient.email.clone(),
+                    sequence: event.sequence,
+                    method: method.to_string(),
+                },
+            )
+        };
+        match result.await {
+            Ok(()) => {
+                tracing::info!("sent {method} for {} to {}", event.id, recipient.email);
+                true
+            }
+            Err(e) => {
+                tracing::error!(
+                    "could not send {method} for {} to {}: {e:?}",
+                    event.id,
+                    recipient.email
+                );
+                false
+            }
+        }
+    }
+
+    fn message(
+        &self,
+        event: &Event,
+        recipient: &Attendee,
+        method: &str,
+    ) -> anyhow::Result<OutgoingMessage> {
+        // The organizer's own attendee list is theirs to send; each member only sees
+        // themselves, invited by the calendar on the organizer's behalf
+        let forwarded = Event {
+            creator_email: self.calendar_address.clone(),
+            attendees: vec![recipient.clone()],
+            status: match method {
+                "CANCEL" => Some("CANCELLED".to_string()),
+                _ => event.status.clone(),
+            },
+            ..event.clone()
+        };
+        // Team members are invited for being on the team, which a link can't change
+        let unsubscribe = match recipient.role.as_deref() {
+            Some("OPT-PARTICIPANT") => self.unsubscribe_url(&recipient.email, &event.id)?,
+            _ => None,
+        };
+        let (subject, mut text) = match method {
+            "CANCEL" => (
+                format!("Cancelled: {}", event.summary),
+                format!("{} has been cancelled.", event.summary),
+            ),
+            _ => (
+                format!("Invitation: {}", event.summary),
+                format!("{} invited you to {}.", event.creator_name, event.summary),
+            ),
+        };
+        if let Some(unsubscribe) = &unsubscribe {
+            text.push_str(&format!(
+                "\n\nTo stop getting mail about this event, unsubscribe: {unsubscribe}"
+            ));
+        }
+        OutgoingMessage::itip(
+            &self.calendar_address,
+            &recipient.email,
+            &subject,
+            &text,
+            method,
+            &ical::itip_to_ics(&forwarded, method)?,
+            unsubscribe.as_deref(),
+        )
+    }
+}
+
+#[tokio::test]
+async fn test_send_invitations() {
+    use crate::calendar::EventTime;
+    use crate::store::Subscription;
+
+    let temp = std::env::temp_dir().join(format!("eventageous-{}", uuid::Uuid::new_v4()));
+    let store = Arc::new(Store::in_memory().unwrap());
+    let roster = Arc::new(Roster::new(Some("test_data/team".into()), store.clone()));
+    roster.refresh().unwrap();
+    let invitations = Invitations::new(
+        store.clone(),
+        roster,
+        Arc::new(super::outbound::Outbox::new(&temp)),
+        "calendar@example.org".to_string(),
+        None,
+    );
+    let mut event = Event {
+        id: "async-sync@example.org".to_string(),
+        summary: "Async sync".to_string(),
+        creator_email: "tmandry@example.org".to_string(),
+        creator_name: "Tyler Mandry".to_string(),
+        start: EventTime::parse("2024-03-07T17:00:00Z", None).unwrap(),
+        end: EventTime::parse("2024-03-07T18:00:00Z", None).unwrap(),
+        teams: vec!["wg-async".to_string()],
+        ..Event::default()
+    };
+    store.upsert_event(&event).unwrap();
+    let methods = || {
+        store
+            .invitations(&event.id)
+            .unwrap()
+            .into_iter()
+            .map(|invitation| format!("{} {}", invitation.method, invitation.email))
+            .collect::<Vec<_>>()
+    };
+
+    // The organizer is on the team, but doesn't need to be told
+    assert_eq!(invitations.send(&event).await.unwrap(), 1);
+    assert_eq!(methods(), ["REQUEST niko@alum.mit.edu"]);
+    assert_eq!(invitations.send(&event).await.unwrap(), 0);
+
+    store
+        .subscribe(
+            "jane@example.org",
+            &Subscription::Team("wg-async".to_string()),
+        )
+        .unwrap();
+    assert_eq!(invitations.send(&event).await.unwrap(), 1);
+    event.sequence = 1;
+    assert_eq!(invitations.send(&event).await.unwrap(), 2);
+
+    let amy = Subscription::Event(event.id.clone());
+    store.subscribe("amy@example.org", &amy).unwrap();
+    assert!(invitations
+        .send_to(&event, "amy@example.org")
+        .await
+        .unwrap());
+    assert!(!invitations
+        .send_to(&event, "amy@example.org")
+        .await
+        .unwrap());
+    store.unsubscribe("amy@example.org", &amy).unwrap();
+    assert!(invitations
+        .send_to(&event, "amy@example.org")
+        .await
+        .unwrap());
+
+    // Private events are only for the team
+    event.visibility = Visibility::Private;
+    assert_eq!(invitations.send(&event).await.unwrap(), 1);
+    assert_eq!(
+        methods(),
+        [
+            "CANCEL amy@example.org",
+            "CANCEL jane@example.org",
+            "REQUEST niko@alum.mit.edu"
+        ]
+    );
+
+    event.status = Some("CANCELLED".to_string());
+    event.sequence = 2;
+    assert_eq!(invitations.send(&event).await.unwrap(), 1);
+    assert_eq!(
+        methods(),
+        [
+            "CANCEL amy@example.org",
+            "CANCEL jane@example.org",
+            "CANCEL niko@alum.mit.edu"
+        ]
+    );
+
+    // Once an event is deleted, there is no telling who had an invite
+    let deleted = Event {
+        id: "lang-sync@example.org".to_string(),
+        sequence: 0,
+        status: None,
+        ..event
+    };
+    store.upsert_event(&deleted).unwrap();
+    assert_eq!(invitations.send(&deleted).await.unwrap(), 1);
+    let invited = invitations.invited(&deleted.id).unwrap();
+    assert_eq!(invited, ["niko@alum.mit.edu"]);
+    store.delete_event(&deleted.id).unwrap();
+    assert!(invitations.invited(&deleted.id).unwrap().is_empty());
+    assert_eq!(invitations.cancel_deleted(&deleted, &invited).await, 1);
+
+    let sent = std::fs::read_dir(&temp).unwrap().count();
+    assert_eq!(sent, 10);
+    std::fs::remove_dir_all(&temp).unwrap();
+}
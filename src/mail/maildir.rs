@@ -0,0 +1,74 @@
+//! A local [Maildir](https://cr.yp.to/proto/maildir.html), e.g. one that an MTA or
+//! `fetchmail` delivers the calendar's mail into.
+
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+
+use super::poller::{with_error_header, IncomingMessage, Mailbox, Outcome};
+
+/// New messages are read from `new/` and moved to `cur/` with the seen flag once taken
+/// in; quarantined ones go to the `.Quarantine` folder (as Maildir++ names subfolders).
+pub struct Maildir {
+    path: PathBuf,
+}
+
+impl Maildir {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn quarantine(&self) -> PathBuf {
+        self.path.join(".Quarantine")
+    }
+}
+
+#[async_trait]
+impl Mailbox for Maildir {
+    async fn fetch(&self) -> anyhow::Result<Vec<IncomingMessage>> {
+        let mut messages = vec![];
+        let mut entries = tokio::fs::read_dir(self.path.join("new")).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let key = entry.file_name().to_string_lossy().into_owned();
+            if key.starts_with('.') {
+                continue;
+            }
+            let raw = tokio::fs::read(entry.path()).await?;
+            messages.push(IncomingMessage {
+                key,
+                raw: String::from_utf8_lossy(&raw).into_owned(),
+            });
+        }
+        messages.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(messages)
+    }
+
+    async fn settle(&self, settled: &[(IncomingMessage, Outcome)]) -> anyhow::Result<()> {
+        for (message, outcome) in settled {
+            let path = self.path.join("new").join(&message.key);
+            match outcome {
+                Outcome::Processed => {
+                    let seen = self.path.join("cur").join(format!("{}:2,S", message.key));
+                    tokio::fs::rename(&path, seen).await?;
+                }
+                Outcome::Quarantined(error) => {
+                    let quarantine = self.quarantine();
+                    create_maildir(&quarantine).await?;
+                    // Write to `tmp/` first so that readers never see half a message
+                    let tmp = quarantine.join("tmp").join(&message.key);
+                    tokio::fs::write(&tmp, with_error_header(&message.raw, error)).await?;
+                    tokio::fs::rename(&tmp, quarantine.join("new").join(&message.key)).await?;
+                    tokio::fs::remove_file(&path).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn create_maildir(path: &Path) -> anyhow::Result<()> {
+    for dir in ["cur", "new", "tmp"] {
+        tokio::fs::create_dir_all(path.join(dir)).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,234 @@
+//! Sending mail from the calendar address, such as replies to the invites we accept.
+//!
+//! Messages are built as a whole and handed to a [`Transport`]: an SMTP relay, the local
+//! `sendmail` program, or for testing a directory that collects them as `.eml` files.
+//! The [`queue`](super::queue) sits in front of the transport to retry failures.
+
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use lettre::address::Envelope;
+use lettre::message::header::{ContentType, HeaderName, HeaderValue};
+use lettre::message::{MessageBuilder, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tokio::io::AsyncWriteExt;
+
+use crate::config::OutboundConfig;
+
+/// A message ready to be sent.
+#[derive(Clone, Debug)]
+pub struct OutgoingMessage {
+    /// Without angle brackets, so that bounces can be matched up with the message.
+    pub message_id: String,
+    pub from: String,
+    pub to: Vec<String>,
+    /// The whole message, headers and all.
+    pub raw: Vec<u8>,
+}
+
+/// A way of getting mail to its recipients.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<()>;
+}
+
+impl OutgoingMessage {
+    /// An iTIP message: some text for people, and the `ics` with the given `method` for
+    /// their calendar software. With an `unsubscribe` link, mail clients offer to stop
+    /// these messages.
+    pub fn itip(
+        from: &str,
+        to: &str,
+        subject: &str,
+        text: &str,
+        method: &str,
+        ics: &str,
+        unsubscribe: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let calendar =
+            ContentType::parse(&format!("text/calendar; method={method}; charset=utf-8"))?;
+        let (builder, message_id) = Self::builder(from, to, subject, unsubscribe)?;
+        let message = builder.multipart(
+            MultiPart::alternative()
+                .singlepart(SinglePart::plain(text.to_string()))
+                .singlepart(SinglePart::builder().header(calendar).body(ics.to_string())),
+        )?;
+        Ok(Self {
+            message_id,
+            from: from.to_string(),
+            to: vec![to.to_string()],
+            raw: message.formatted(),
+        })
+    }
+
+    /// A message with just some text.
+    pub fn text(
+        from: &str,
+        to: &str,
+        subject: &str,
+        text: &str,
+        unsubscribe: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let (builder, message_id) = Self::builder(from, to, subject, unsubscribe)?;
+        let message = builder.singlepart(SinglePart::plain(text.to_string()))?;
+        Ok(Self {
+            message_id,
+            from: from.to_string(),
+            to: vec![to.to_string()],
+            raw: message.formatted(),
+        })
+    }
+
+    /// The headers every message has, and its new `Message-ID`.
+    fn builder(
+        from: &str,
+        to: &str,
+        subject: &str,
+        unsubscribe: Option<&str>,
+    ) -> anyhow::Result<(MessageBuilder, String)> {
+        let domain = from
+            .rsplit_once('@')
+            .map_or("localhost", |(_, domain)| domain);
+        let message_id = format!("{}@{domain}", uuid::Uuid::new_v4());
+        let mut builder = Message::builder()
+            .from(from.parse()?)
+            .to(to.parse()?)
+            .subject(subject)
+            .message_id(Some(format!("<{message_id}>")));
+        if let Some(unsubscribe) = unsubscribe {
+            // RFC 8058: the link also takes a `POST`, so one click in the mail client does it
+            builder = builder
+                .raw_header(HeaderValue::new(
+                    HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                    format!("<{unsubscribe}>"),
+                ))
+                .raw_header(HeaderValue::new(
+                    HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                    "List-Unsubscribe=One-Click".to_string(),
+                ));
+        }
+        Ok((builder, message_id))
+    }
+}
+
+/// Hands mail to an SMTP server that relays it for us.
+pub struct SmtpRelay {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+#[async_trait]
+impl Transport for SmtpRelay {
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<()> {
+        let to = message
+            .to
+            .iter()
+            .map(|to| to.parse())
+            .collect::<Result<_, _>>()?;
+        let envelope = Envelope::new(Some(message.from.parse()?), to)?;
+        self.transport.send_raw(&envelope, &message.raw).await?;
+        Ok(())
+    }
+}
+
+/// Pipes mail to a `sendmail`-compatible program, which takes care of delivery.
+pub struct Sendmail {
+    command: PathBuf,
+}
+
+impl Sendmail {
+    pub fn new(command: impl Into<PathBuf>) -> Self {
+        Self {
+            command: command.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for Sendmail {
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<()> {
+        let mut child = tokio::process::Command::new(&self.command)
+            // Don't end the message at a line with a single dot
+            .arg("-i")
+            .arg("-f")
+            .arg(&message.from)
+            .arg("--")
+            .args(&message.to)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("could not run {}: {e}", self.command.display()))?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin.write_all(&message.raw).await?;
+        drop(stdin);
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} failed ({}): {}",
+                self.command.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Keeps mail in a directory instead of sending it, one `.eml` file per message.
+pub struct Outbox {
+    path: PathBuf,
+}
+
+impl Outbox {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl Transport for Outbox {
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.path).await?;
+        // Write under another name first so that readers never see half a message
+        let name = uuid::Uuid::new_v4();
+        let tmp = self.path.join(format!(".{name}.tmp"));
+        tokio::fs::write(&tmp, &message.raw).await?;
+        tokio::fs::rename(&tmp, self.path.join(format!("{name}.eml"))).await?;
+        Ok(())
+    }
+}
+
+/// Create the transport selected in the configuration.
+pub fn transport_from_config(config: &OutboundConfig) -> anyhow::Result<Arc<dyn Transport>> {
+    Ok(match config {
+        OutboundConfig::Smtp {
+            host,
+            port,
+            plaintext,
+            username,
+            password,
+        } => {
+            let mut builder = match plaintext {
+                true => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+                    .port(port.unwrap_or(25)),
+                false => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+                    .port(port.unwrap_or(587)),
+            };
+            if let (Some(username), Some(password)) = (username, password) {
+                builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+            }
+            Arc::new(SmtpRelay {
+                transport: builder.build(),
+            })
+        }
+        OutboundConfig::Sendmail { command } => Arc::new(Sendmail::new(
+            command
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("/usr/sbin/sendmail")),
+        )),
+        OutboundConfig::Outbox { path } => Arc::new(Outbox::new(path)),
+    })
+}
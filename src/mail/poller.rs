@@ -0,0 +1,245 @@
+//! Taking in invites by polling a mailbox, for deployments that can't receive mail
+//! themselves.
+//!
+//! Each poll fetches the waiting messages and hands them to the [`Inbox`]. Messages are
+//! remembered by `Message-ID` so that a message that shows up twice (say, delivered to
+//! two addresses) is only taken in once, and messages we can't make sense of are moved
+//! to a quarantine folder with the error in an `X-Eventageous-Error` header.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::config::{MailboxConfig, MailboxSource};
+use crate::store::Store;
+
+use super::imap::ImapMailbox;
+use super::maildir::Maildir;
+use super::{message_id, Inbox};
+
+/// A place where mail for the calendar address piles up.
+#[async_trait]
+pub trait Mailbox: Send + Sync {
+    /// The messages waiting to be taken in.
+    async fn fetch(&self) -> anyhow::Result<Vec<IncomingMessage>>;
+
+    /// Get messages out of the way once they are taken in: processed ones are marked as
+    /// such, quarantined ones are moved to the quarantine folder. Messages that aren't
+    /// mentioned stay where they are, to be fetched again by the next poll.
+    async fn settle(&self, settled: &[(IncomingMessage, Outcome)]) -> anyhow::Result<()>;
+}
+
+#[derive(Clone, Debug)]
+pub struct IncomingMessage {
+    /// Identifies the message within its mailbox, e.g. a file name or IMAP UID.
+    pub key: String,
+    pub raw: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Processed,
+    /// Quarantined with this error.
+    Quarantined(String),
+}
+
+/// What happened to the messages of one poll.
+#[derive(Debug, Default, PartialEq)]
+pub struct PollReport {
+    pub delivered: usize,
+    /// Messages that were taken in before.
+    pub duplicates: usize,
+    pub quarantined: usize,
+    /// Messages left for the next poll, because their events couldn't be stored.
+    pub deferred: usize,
+    /// Bounces of mail that we sent.
+    pub bounces: usize,
+}
+
+pub struct Poller {
+    mailbox: Box<dyn Mailbox>,
+    inbox: Arc<Inbox>,
+    store: Arc<Store>,
+}
+
+impl Poller {
+    pub fn new(mailbox: Box<dyn Mailbox>, inbox: Arc<Inbox>, store: Arc<Store>) -> Self {
+        Self {
+            mailbox,
+            inbox,
+            store,
+        }
+    }
+
+    /// Take in every waiting message.
+    pub async fn poll(&self) -> anyhow::Result<PollReport> {
+        let mut report = PollReport::default();
+        let mut settled = vec![];
+        for message in self.mailbox.fetch().await? {
+            let message_id = message_id(&message.raw);
+            if let Some(id) = &message_id {
+                if self.store.message_processed(id)? {
+                    report.duplicates += 1;
+                    settled.push((message, Outcome::Processed));
+                    continue;
+                }
+            }
+
+            let outcome = match self.inbox.record_bounce(&message.raw) {
+                Ok(Some(_)) => {
+                    report.bounces += 1;
+                    Outcome::Processed
+                }
+                Ok(None) => match self.inbox.parse(&message.raw, &[]).await {
+                    Ok(email) => match self.inbox.deliver(&email).await {
+                        Ok(_) => {
+                            report.delivered += 1;
+                            Outcome::Processed
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "could not add events of message {}: {e:?}",
+                                message.key
+                            );
+                            report.deferred += 1;
+                            continue;
+                        }
+                    },
+                    Err(e) => {
+                        tracing::info!("quarantining message {}: {e}", message.key);
+                        report.quarantined += 1;
+                        Outcome::Quarantined(e.to_string())
+                    }
+                },
+                Err(e) => {
+                    tracing::error!("could not record bounce {}: {e:?}", message.key);
+                    report.deferred += 1;
+                    continue;
+                }
+            };
+            if let Some(id) = &message_id {
+                let outcome = match &outcome {
+                    Outcome::Processed => "processed",
+                    Outcome::Quarantined(_) => "quarantined",
+                };
+                self.store.record_message(id, outcome)?;
+            }
+            settled.push((message, outcome));
+        }
+        self.mailbox.settle(&settled).await?;
+        Ok(report)
+    }
+}
+
+/// Create the mailbox selected in the configuration.
+pub fn mailbox_from_config(config: &MailboxConfig) -> Box<dyn Mailbox> {
+    match &config.source {
+        MailboxSource::Maildir { path } => Box::new(Maildir::new(path)),
+        MailboxSource::Imap {
+            host,
+            port,
+            plaintext,
+            username,
+            password,
+            mailbox,
+            quarantine,
+        } => Box::new(ImapMailbox {
+            host: host.clone(),
+            port: port.unwrap_or(if *plaintext { 143 } else { 993 }),
+            tls: !plaintext,
+            username: username.clone(),
+            password: password.clone(),
+            mailbox: mailbox.clone().unwrap_or_else(|| "INBOX".to_string()),
+            quarantine: quarantine
+                .clone()
+                .unwrap_or_else(|| "Quarantine".to_string()),
+        }),
+    }
+}
+
+/// Run [`Poller::poll`] every `interval`, logging what happened.
+pub async fn poll_periodically(poller: Arc<Poller>, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match poller.poll().await {
+            Ok(report) if report == PollReport::default() => {}
+            Ok(report) => tracing::info!("polled mailbox: {report:?}"),
+            Err(e) => tracing::error!("polling the mailbox failed: {e:?}"),
+        }
+    }
+}
+
+/// The raw message with an `X-Eventageous-Error` header in front, saying why it was
+/// quarantined.
+pub fn with_error_header(raw: &str, error: &str) -> String {
+    let error: String = error
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    format!("X-Eventageous-Error: {error}\r\n{raw}")
+}
+
+#[tokio::test]
+async fn test_poll_maildir() {
+    let (temp, calendar, inbox) = super::test_inbox(&["compiler"]);
+    let maildir = temp.path().join("maildir");
+    for dir in [&maildir.join("new"), &maildir.join("cur")] {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    for entry in std::fs::read_dir("test_data/maildir/new").unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, maildir.join("new").join(path.file_name().unwrap())).unwrap();
+    }
+    let store = calendar.store().clone();
+    let poller = Poller::new(Box::new(Maildir::new(&maildir)), Arc::new(inbox), store);
+
+    expect_test::expect![[r#"
+        PollReport {
+            delivered: 1,
+            duplicates: 1,
+            quarantined: 2,
+            deferred: 0,
+            bounces: 0,
+        }
+    "#]]
+    .assert_debug_eq(&poller.poll().await.unwrap());
+    assert_eq!(poller.poll().await.unwrap(), PollReport::default());
+
+    let event = calendar
+        .event("compiler-meeting@example.org")
+        .await
+        .unwrap();
+    assert_eq!(event.unwrap().teams, ["compiler"]);
+    let names = |dir: &str| {
+        let mut names: Vec<String> = std::fs::read_dir(maildir.join(dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+    assert!(names("new").is_empty());
+    assert_eq!(
+        names("cur"),
+        [
+            "1710000000.M1P1.mail.example.org:2,S",
+            "1710000001.M2P1.mail.example.org:2,S",
+        ]
+    );
+    let quarantined = names(".Quarantine/new");
+    assert_eq!(
+        quarantined,
+        [
+            "1710000002.M3P1.mail.example.org",
+            "1710000003.M4P1.mail.example.org",
+        ]
+    );
+    let raw =
+        |name: &str| std::fs::read_to_string(maildir.join(".Quarantine/new").join(name)).unwrap();
+    assert!(raw(&quarantined[0])
+        .starts_with("X-Eventageous-Error: Calendar request did not contain METHOD\r\nFrom:"));
+    assert!(raw(&quarantined[1]).starts_with(
+        "X-Eventageous-Error: unknown team(s) in the recipient address: compilre\r\n"
+    ));
+}
@@ -0,0 +1,209 @@
+//! The outbound mail queue.
+//!
+//! Mail isn't sent right away: the [`Queue`] stands in for the transport and keeps each
+//! message in the store, one delivery per recipient, and [`deliver_periodically`] hands
+//! them to the real transport. Failed attempts are retried with exponential backoff
+//! until [`MAX_ATTEMPTS`], so that a relay being down for a while doesn't lose any
+//! invites. Deliveries that fail for good, or that bounce later on (see
+//! [`super::bounce`]), are kept for admins to look at.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::store::{DeliveryStatus, Store};
+
+use super::outbound::{OutgoingMessage, Transport};
+
+/// How often a delivery is tried before giving up on it.
+pub const MAX_ATTEMPTS: u32 = 8;
+/// How long to wait before the first retry; each retry after that waits twice as long.
+const FIRST_RETRY: Duration = Duration::minutes(1);
+
+pub struct Queue {
+    store: Arc<Store>,
+    transport: Arc<dyn Transport>,
+}
+
+/// What became of the deliveries that were due.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub sent: usize,
+    pub retrying: usize,
+    pub failed: usize,
+}
+
+#[async_trait]
+impl Transport for Queue {
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<()> {
+        for recipient in &message.to {
+            self.store.enqueue_delivery(
+                &message.message_id,
+                &message.from,
+                recipient,
+                &message.raw,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Queue {
+    pub fn new(store: Arc<Store>, transport: Arc<dyn Transport>) -> Self {
+        Self { store, transport }
+    }
+
+    /// Try the deliveries that are due at `now`.
+    pub async fn flush(&self, now: DateTime<Utc>) -> anyhow::Result<Report> {
+        let mut report = Report::default();
+        for delivery in self.store.due_deliveries(now)? {
+            let message = OutgoingMessage {
+                message_id: delivery.message_id.clone(),
+                from: delivery.sender.clone(),
+                to: vec![delivery.recipient.clone()],
+                raw: delivery.raw.clone(),
+            };
+            let attempts = delivery.attempts + 1;
+            match self.transport.send(&message).await {
+                Ok(()) => {
+                    tracing::info!("sent {} to {}", delivery.message_id, delivery.recipient);
+                    self.store.record_delivery_attempt(
+                        delivery.id,
+                        DeliveryStatus::Sent,
+                        now,
+                        None,
+                    )?;
+                    report.sent += 1;
+                }
+                Err(e) if attempts >= MAX_ATTEMPTS || is_permanent(&e) => {
+                    tracing::error!(
+                        "giving up on sending {} to {} after {attempts} attempts: {e:?}",
+                        delivery.message_id,
+                        delivery.recipient
+                    );
+                    let error = e.to_string();
+                    self.store.record_delivery_attempt(
+                        delivery.id,
+                        DeliveryStatus::Failed,
+                        now,
+                        Some(&error),
+                    )?;
+                    report.failed += 1;
+                }
+                Err(e) => {
+                    let next_attempt_at = now + retry_delay(attempts);
+                    tracing::warn!(
+                        "could not send {} to {}, retrying at {next_attempt_at}: {e:?}",
+                        delivery.message_id,
+                        delivery.recipient
+                    );
+                    let error = e.to_string();
+                    self.store.record_delivery_attempt(
+                        delivery.id,
+                        DeliveryStatus::Queued,
+                        next_attempt_at,
+                        Some(&error),
+                    )?;
+                    report.retrying += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// How long to wait after the `attempts`th failed attempt.
+fn retry_delay(attempts: u32) -> Duration {
+    FIRST_RETRY * 2i32.pow(attempts.saturating_sub(1))
+}
+
+/// Whether retrying can't help, because the server refused the message for good.
+fn is_permanent(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<lettre::transport::smtp::Error>()
+        .is_some_and(|e| e.is_permanent())
+}
+
+/// Run [`Queue::flush`] every `interval`, logging failures.
+pub async fn deliver_periodically(queue: Arc<Queue>, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match queue.flush(Utc::now()).await {
+            Ok(report) if report != Report::default() => tracing::info!("mail queue: {report:?}"),
+            Ok(_) => {}
+            Err(e) => tracing::error!("sending queued mail failed: {e:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_retries() {
+    use std::sync::Mutex;
+
+    /// Fails while there are failures left, and remembers who it sent to.
+    struct Flaky {
+        failures: Mutex<u32>,
+        sent: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Transport for Flaky {
+        async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<()> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                anyhow::bail!("connection refused");
+            }
+            self.sent.lock().unwrap().extend(message.to.iter().cloned());
+            Ok(())
+        }
+    }
+
+    let store = Arc::new(Store::in_memory().unwrap());
+    let transport = Arc::new(Flaky {
+        failures: Mutex::new(2),
+        sent: Mutex::new(vec![]),
+    });
+    let queue = Queue::new(store.clone(), transport.clone());
+    let message = OutgoingMessage {
+        message_id: "1@example.org".to_string(),
+        from: "calendar@example.org".to_string(),
+        to: vec![
+            "niko@example.org".to_string(),
+            "jane@example.org".to_string(),
+        ],
+        raw: b"Subject: Hi\r\n\r\nHello\r\n".to_vec(),
+    };
+    queue.send(&message).await.unwrap();
+
+    let start = Utc::now();
+    let report = queue.flush(start).await.unwrap();
+    assert_eq!((report.sent, report.retrying), (0, 2));
+    // Nothing is due until the backoff is over
+    let report = queue.flush(start + Duration::seconds(30)).await.unwrap();
+    assert_eq!(report, Report::default());
+    let report = queue.flush(start + Duration::minutes(1)).await.unwrap();
+    assert_eq!((report.sent, report.retrying), (2, 0));
+    assert_eq!(
+        *transport.sent.lock().unwrap(),
+        ["niko@example.org", "jane@example.org"]
+    );
+    let sent = store.deliveries(DeliveryStatus::Sent).unwrap();
+    assert_eq!(sent.len(), 2);
+    assert!(sent.iter().all(|delivery| delivery.attempts == 2));
+
+    // A relay that stays down makes us give up eventually
+    *transport.failures.lock().unwrap() = u32::MAX;
+    queue.send(&message).await.unwrap();
+    let mut now = Utc::now();
+    for _ in 0..MAX_ATTEMPTS {
+        queue.flush(now).await.unwrap();
+        now += Duration::days(1);
+    }
+    let failed = store.deliveries(DeliveryStatus::Failed).unwrap();
+    assert_eq!(failed.len(), 2);
+    assert_eq!(failed[0].last_error.as_deref(), Some("connection refused"));
+    assert!(store.due_deliveries(now).unwrap().is_empty());
+}
@@ -0,0 +1,263 @@
+//! Requesting an invite to a public event without an account.
+//!
+//! Anyone can type in an address, so we first mail a link to it and only subscribe the
+//! address (which sends the invite, see [`super::invitations`]) once the link is
+//! followed. Links expire after [`REQUEST_EXPIRY`], and an address can't be sent more
+//! than [`MAX_REQUESTS`] of them per [`RATE_WINDOW`]. Every message has a link to
+//! unsubscribe, which also voids the links that weren't followed yet.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::calendar::{Event, Visibility};
+use crate::store::{InviteRequest, Store, Subscription};
+
+use super::invitations::Invitations;
+use super::outbound::{OutgoingMessage, Transport};
+
+/// How long a confirmation link works.
+pub const REQUEST_EXPIRY: Duration = Duration::hours(24);
+/// How many confirmation links an address is sent per [`RATE_WINDOW`].
+pub const MAX_REQUESTS: usize = 3;
+pub const RATE_WINDOW: Duration = Duration::hours(1);
+
+pub struct InviteRequests {
+    store: Arc<Store>,
+    invitations: Arc<Invitations>,
+    transport: Arc<dyn Transport>,
+    calendar_address: String,
+    public_url: String,
+}
+
+/// What following a confirmation link did.
+#[derive(Debug)]
+pub enum Confirmation {
+    /// Subscribed, and the invite is on its way.
+    Invited(Event),
+    /// The link was followed before.
+    AlreadyConfirmed(Event),
+    Expired,
+    /// There is no such link (any more), or its event is gone.
+    Unknown,
+}
+
+impl InviteRequests {
+    pub fn new(
+        store: Arc<Store>,
+        invitations: Arc<Invitations>,
+        transport: Arc<dyn Transport>,
+        calendar_address: String,
+        public_url: String,
+    ) -> Self {
+        Self {
+            store,
+            invitations,
+            transport,
+            calendar_address,
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Mail `email` a link to confirm that they want an invite to `event`. Returns
+    /// `None` if they asked too often lately.
+    pub async fn request(
+        &self,
+        event: &Event,
+        email: &str,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Option<InviteRequest>> {
+        if event.visibility != Visibility::Public {
+            anyhow::bail!("only public events can be requested");
+        }
+        let email = email.trim().to_lowercase();
+        if self
+            .store
+            .invite_requests_since(&email, now - RATE_WINDOW)?
+            >= MAX_REQUESTS
+        {
+            tracing::warn!("not sending {email} another invite request for now");
+            return Ok(None);
+        }
+        let request =
+            self.store
+                .add_invite_request(&event.id, &email, now, now + REQUEST_EXPIRY)?;
+
+        let confirm = format!("{}/api/invites/{}/confirm", self.public_url, request.token);
+        let unsubscribe = self
+            .invitations
+            .unsubscribe_url(&email, &event.id)?
+            .expect("we know the public URL");
+        let text = format!(
+            "Someone, hopefully you, asked for an invite to {} (starting {}).\n\n\
+             To get it, confirm within {} hours: {confirm}\n\n\
+             If it wasn't you, ignore this message. To stop getting mail about this \
+             event, unsubscribe: {unsubscribe}",
+            event.summary,
+            event.start,
+            REQUEST_EXPIRY.num_hours(),
+        );
+        let message = OutgoingMessage::text(
+            &self.calendar_address,
+            &email,
+            &format!("Confirm your invite to {}", event.summary),
+            &text,
+            Some(&unsubscribe),
+        )?;
+        self.transport.send(&message).await?;
+        Ok(Some(request))
+    }
+
+    /// Follow the confirmation link with `token`: subscribe its address to the event,
+    /// which sends the invite.
+    pub async fn confirm(&self, token: &str, now: DateTime<Utc>) -> anyhow::Result<Confirmation> {
+        let Some(request) = self.store.invite_request(token)? else {
+            return Ok(Confirmation::Unknown);
+        };
+        let Some(event) = self.store.event(&request.event_id)? else {
+            return Ok(Confirmation::Unknown);
+        };
+        if request.confirmed_at.is_some() {
+            return Ok(Confirmation::AlreadyConfirmed(event));
+        }
+        if request.expires_at < now {
+            return Ok(Confirmation::Expired);
+        }
+        self.store
+            .subscribe(&request.email, &Subscription::Event(event.id.clone()))?;
+        self.store.confirm_invite_request(token, now)?;
+        self.invitations.send_to(&event, &request.email).await?;
+        Ok(Confirmation::Invited(event))
+    }
+
+    /// The event that the unsubscribe link with `token` is about, if any, without
+    /// unsubscribing.
+    pub fn unsubscribe_event(&self, token: &str) -> anyhow::Result<Option<Event>> {
+        let Some((_, event_id)) = self.store.unsubscribe_token_target(token)? else {
+            return Ok(None);
+        };
+        self.store.event(&event_id)
+    }
+
+    /// Follow an unsubscribe link, returning the event it was about, if any. Whoever
+    /// had the invite is told it's off for them.
+    pub async fn unsubscribe(&self, token: &str) -> anyhow::Result<Option<Event>> {
+        let Some((email, event_id)) = self.store.unsubscribe_token_target(token)? else {
+            return Ok(None);
+        };
+        self.store
+            .unsubscribe(&email, &Subscription::Event(event_id.clone()))?;
+        self.store.drop_invite_requests(&email, &event_id)?;
+        let Some(event) = self.store.event(&event_id)? else {
+            return Ok(None);
+        };
+        self.invitations.send_to(&event, &email).await?;
+        Ok(Some(event))
+    }
+}
+
+#[tokio::test]
+async fn test_request_invite() {
+    use crate::calendar::EventTime;
+    use crate::roster::Roster;
+
+    let temp = std::env::temp_dir().join(format!("eventageous-{}", uuid::Uuid::new_v4()));
+    let store = Arc::new(Store::in_memory().unwrap());
+    let roster = Arc::new(Roster::new(None, store.clone()));
+    let transport: Arc<dyn Transport> = Arc::new(super::outbound::Outbox::new(&temp));
+    let invitations = Arc::new(Invitations::new(
+        store.clone(),
+        roster,
+        transport.clone(),
+        "calendar@example.org".to_string(),
+        Some("https://calendar.example.org".to_string()),
+    ));
+    let requests = InviteRequests::new(
+        store.clone(),
+        invitations,
+        transport,
+        "calendar@example.org".to_string(),
+        "https://calendar.example.org/".to_string(),
+    );
+    let event = Event {
+        id: "triage@example.org".to_string(),
+        summary: "Triage".to_string(),
+        creator_email: "niko@example.org".to_string(),
+        creator_name: "Niko".to_string(),
+        start: EventTime::parse("2024-03-07T17:00:00Z", None).unwrap(),
+        end: EventTime::parse("2024-03-07T18:00:00Z", None).unwrap(),
+        ..Event::default()
+    };
+    store.upsert_event(&event).unwrap();
+    let sent = || {
+        let mut sent: Vec<String> = std::fs::read_dir(&temp)
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        sent.sort_by_key(|message| message.contains("Confirm your invite"));
+        sent
+    };
+
+    let now: DateTime<Utc> = "2024-03-01T12:00:00Z".parse().unwrap();
+    let first = requests
+        .request(&event, "Jane@example.org", now)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(first.email, "jane@example.org");
+    for _ in 1..MAX_REQUESTS {
+        assert!(requests
+            .request(&event, "jane@example.org", now)
+            .await
+            .unwrap()
+            .is_some());
+    }
+    assert!(requests
+        .request(&event, "jane@example.org", now + Duration::minutes(30))
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(sent().len(), MAX_REQUESTS);
+    assert!(sent()[0].contains("List-Unsubscribe: <https://calendar.example.org/api/unsubscribe/"));
+
+    // Nothing happens until the address is confirmed, and not after the link expired
+    assert!(store.subscribers(&event).unwrap().is_empty());
+    let late = now + REQUEST_EXPIRY + Duration::minutes(1);
+    assert!(matches!(
+        requests.confirm(&first.token, late).await.unwrap(),
+        Confirmation::Expired
+    ));
+    assert!(matches!(
+        requests.confirm("nope", now).await.unwrap(),
+        Confirmation::Unknown
+    ));
+    let confirmed = requests.confirm(&first.token, now).await.unwrap();
+    assert!(matches!(confirmed, Confirmation::Invited(_)));
+    assert_eq!(store.subscribers(&event).unwrap(), ["jane@example.org"]);
+    let confirmed = requests.confirm(&first.token, now).await.unwrap();
+    assert!(matches!(confirmed, Confirmation::AlreadyConfirmed(_)));
+    let invite = sent().remove(0);
+    assert!(invite.contains("method=REQUEST"));
+    assert!(invite.contains("List-Unsubscribe"));
+
+    // Unsubscribing cancels the invite and voids the other links
+    let token = store
+        .unsubscribe_token("jane@example.org", &event.id)
+        .unwrap();
+    // Looking at the link doesn't unsubscribe
+    let linked = requests.unsubscribe_event(&token).unwrap().unwrap();
+    assert_eq!(linked.id, event.id);
+    assert_eq!(store.subscribers(&event).unwrap(), ["jane@example.org"]);
+    assert!(requests.unsubscribe(&token).await.unwrap().is_some());
+    assert!(store.subscribers(&event).unwrap().is_empty());
+    assert_eq!(
+        store.invitations(&event.id).unwrap()[0].method,
+        "CANCEL".to_string()
+    );
+    let pending = store
+        .invite_requests_since("jane@example.org", now - Duration::days(1))
+        .unwrap();
+    assert_eq!(pending, 1);
+
+    std::fs::remove_dir_all(&temp).unwrap();
+}
@@ -0,0 +1,327 @@
+//! A small SMTP (RFC 5321) server for receiving invites.
+//!
+//! It only takes mail for the calendar address and hands each message to the
+//! [`Inbox`]; it doesn't relay, authenticate or offer TLS, so it is meant to sit
+//! behind an MTA that does (or on a private network).
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::TcpListener;
+
+use crate::config::SmtpConfig;
+
+use super::Inbox;
+
+/// How long a client may stay silent before we hang up, as recommended by RFC 5321.
+const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// The longest command line we read, well over the 512 octets RFC 5321 allows.
+const MAX_LINE_BYTES: u64 = 4096;
+const MAX_RECIPIENTS: usize = 100;
+
+pub struct SmtpServer {
+    inbox: Arc<Inbox>,
+    hostname: String,
+    max_message_bytes: usize,
+}
+
+/// Where a session is in the mail transaction.
+#[derive(Default)]
+struct Transaction {
+    greeted: bool,
+    sender: Option<String>,
+    recipients: Vec<String>,
+}
+
+impl SmtpServer {
+    pub fn new(inbox: Arc<Inbox>, config: &SmtpConfig) -> Self {
+        Self {
+            inbox,
+            hostname: config.hostname.clone(),
+            max_message_bytes: config.max_message_bytes,
+        }
+    }
+
+    /// Accept connections on `listener` forever, one task per client.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    tracing::error!("SMTP accept failed: {e}");
+                    continue;
+                }
+            };
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.session(stream).await {
+                    tracing::info!("SMTP session with {peer} ended: {e}");
+                }
+            });
+        }
+    }
+
+    /// Talk SMTP with a single client until it quits or goes away.
+    pub async fn session<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+    ) -> anyhow::Result<()> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+        let mut transaction = Transaction::default();
+
+        reply(
+            &mut writer,
+            &format!("220 {} ESMTP Eventageous", self.hostname),
+        )
+        .await?;
+        loop {
+            let Some(line) = read_line(&mut reader).await? else {
+                return Ok(());
+            };
+            let (verb, argument) = match line.split_once(' ') {
+                Some((verb, argument)) => (verb.to_ascii_uppercase(), argument.trim()),
+                None => (line.to_ascii_uppercase(), ""),
+            };
+
+            let response = match verb.as_str() {
+                "EHLO" => {
+                    transaction = Transaction {
+                        greeted: true,
+                        ..Transaction::default()
+                    };
+                    format!(
+                        "250-{}\r\n250-SIZE {}\r\n250 8BITMIME",
+                        self.hostname, self.max_message_bytes
+                    )
+                }
+                "HELO" => {
+                    transaction = Transaction {
+                        greeted: true,
+                        ..Transaction::default()
+                    };
+                    format!("250 {}", self.hostname)
+                }
+                "MAIL" if !transaction.greeted => "503 5.5.1 Say EHLO first".to_string(),
+                "MAIL" => match path(argument, "FROM:") {
+                    Some(sender) => {
+                        transaction.sender = Some(sender);
+                        transaction.recipients.clear();
+                        "250 2.1.0 OK".to_string()
+                    }
+                    None => "501 5.5.4 Expected MAIL FROM:<address>".to_string(),
+                },
+                "RCPT" if transaction.sender.is_none() => "503 5.5.1 Need MAIL first".to_string(),
+                "RCPT" => match path(argument, "TO:") {
+                    Some(_) if transaction.recipients.len() >= MAX_RECIPIENTS => {
+                        "452 4.5.3 Too many recipients".to_string()
+                    }
+                    Some(recipient) if self.inbox.accepts(&recipient) => {
+                        // Refusing here has the sender's MTA bounce the mail with our reason
+                        match self.inbox.unknown_teams(&recipient).await {
+                            Ok(unknown) if unknown.is_empty() => {
+                                transaction.recipients.push(recipient);
+                                "250 2.1.5 OK".to_string()
+                            }
+                            Ok(unknown) => {
+                                format!("550 5.1.1 No such team here: {}", unknown.join(", "))
+                            }
+                            Err(e) => {
+                                tracing::error!("could not look up teams: {e:?}");
+                                "451 4.3.0 Try again later".to_string()
+                            }
+                        }
+                    }
+                    Some(_) => "550 5.1.1 No such mailbox here".to_string(),
+                    None => "501 5.5.4 Expected RCPT TO:<address>".to_string(),
+                },
+                "DATA" if transaction.recipients.is_empty() => {
+                    "503 5.5.1 Need RCPT first".to_string()
+                }
+                "DATA" => {
+                    reply(&mut writer, "354 End data with <CR><LF>.<CR><LF>").await?;
+                    let response = match read_data(&mut reader, self.max_message_bytes).await? {
+                        Some(message) => self.receive(&message, &transaction.recipients).await,
+                        None => "552 5.3.4 Message too big".to_string(),
+                    };
+                    transaction.sender = None;
+                    transaction.recipients.clear();
+                    response
+                }
+                "RSET" => {
+                    transaction.sender = None;
+                    transaction.recipients.clear();
+                    "250 2.0.0 OK".to_string()
+                }
+                "NOOP" => "250 2.0.0 OK".to_string(),
+                "VRFY" => "252 2.5.0 Send some mail and we'll see".to_string(),
+                "QUIT" => {
+                    reply(&mut writer, &format!("221 2.0.0 {} closing", self.hostname)).await?;
+                    return Ok(());
+                }
+                _ => "502 5.5.1 Command not implemented".to_string(),
+            };
+            reply(&mut writer, &response).await?;
+        }
+    }
+
+    /// Deliver a message to the inbox. Messages we can't make sense of are refused for
+    /// good, while failures to store events are temporary so that the sender retries.
+    async fn receive(&self, message: &str, recipients: &[String]) -> String {
+        match self.inbox.record_bounce(message) {
+            Ok(Some(bounced)) => return format!("250 2.0.0 Recorded {bounced} bounced deliveries"),
+            Ok(None) => {}
+            Err(e) => {
+                tracing::error!("could not record bounce: {e:?}");
+                return "451 4.3.0 Could not record the bounce, try again later".to_string();
+            }
+        }
+        let email = match self.inbox.parse(message, recipients).await {
+            Ok(email) => email,
+            Err(e) => {
+                tracing::info!("refused message: {e}");
+                return format!("554 5.6.0 {e}");
+            }
+        };
+        match self.inbox.deliver(&email).await {
+            Ok(changes) => format!("250 2.0.0 Made {changes} changes to the calendar"),
+            Err(e) => {
+                tracing::error!("could not add invited events: {e:?}");
+                "451 4.3.0 Could not add the events, try again later".to_string()
+            }
+        }
+    }
+}
+
+async fn reply<W: AsyncWrite + Unpin>(writer: &mut W, response: &str) -> anyhow::Result<()> {
+    writer.write_all(response.as_bytes()).await?;
+    writer.write_all(b"\r\n").await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read a line without its line ending, or `None` at the end of the stream.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> anyhow::Result<Option<String>> {
+    let mut line = vec![];
+    let mut limited = (&mut *reader).take(MAX_LINE_BYTES);
+    let read = limited.read_until(b'\n', &mut line);
+    if tokio::time::timeout(TIMEOUT, read).await?? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\n") {
+        anyhow::bail!("line too long");
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// Read a message up to the line with a single `.`, undoing dot-stuffing. Returns
+/// `None` if the message is larger than `max_bytes`, after reading all of it.
+async fn read_data<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_bytes: usize,
+) -> anyhow::Result<Option<String>> {
+    let mut message = String::new();
+    let mut too_big = false;
+    loop {
+        let Some(line) = read_line(reader).await? else {
+            anyhow::bail!("connection closed during DATA");
+        };
+        if line == "." {
+            break;
+        }
+        if too_big {
+            continue;
+        }
+        message.push_str(line.strip_prefix('.').unwrap_or(&line));
+        message.push_str("\r\n");
+        too_big = message.len() > max_bytes;
+    }
+    Ok((!too_big).then_some(message))
+}
+
+/// The address in a `FROM:<address>` or `TO:<address>` argument, ignoring any
+/// parameters after it.
+fn path(argument: &str, prefix: &str) -> Option<String> {
+    let rest = argument.get(..prefix.len())?;
+    if !rest.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    let rest = argument[prefix.len()..].trim_start();
+    let address = rest.strip_prefix('<')?.split_once('>')?.0;
+    Some(address.to_string())
+}
+
+#[tokio::test]
+async fn test_session() {
+    use crate::calendar::EventQuery;
+
+    let (_temp, calendar, inbox) = super::test_inbox(&["lang"]);
+    let server = SmtpServer::new(Arc::new(inbox), &SmtpConfig::default());
+
+    let invite = include_str!("../../test_data/invite.eml")
+        .replacen("To: niko@alum.mit.edu", "To: calendar+lang@example.org", 1)
+        .replace('\n', "\r\n")
+        .replace("\r\n.", "\r\n..");
+    let (client, server_side) = tokio::io::duplex(64 * 1024);
+    let session = tokio::spawn(async move { server.session(server_side).await });
+
+    let (reader, mut writer) = tokio::io::split(client);
+    let mut reader = BufReader::new(reader);
+    let mut exchange = vec![];
+    for command in [
+        "EHLO mail.example.org".to_string(),
+        "MAIL FROM:<rust@nikomatsakis.com>".to_string(),
+        "RCPT TO:<niko@example.org>".to_string(),
+        "RCPT TO:<calendar+lnag@example.org>".to_string(),
+        "RCPT TO:<calendar+lang@example.org> NOTIFY=NEVER".to_string(),
+        "DATA".to_string(),
+        format!("{invite}."),
+        "QUIT".to_string(),
+    ] {
+        // The greeting, then the responses to each command
+        loop {
+            let line = read_line(&mut reader).await.unwrap().unwrap();
+            let last = line.as_bytes().get(3) != Some(&b'-');
+            exchange.push(line);
+            if last {
+                break;
+            }
+        }
+        writer
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await
+            .unwrap();
+    }
+    exchange.push(read_line(&mut reader).await.unwrap().unwrap());
+    session.await.unwrap().unwrap();
+
+    expect_test::expect![[r#"
+        [
+            "220 localhost ESMTP Eventageous",
+            "250-localhost",
+            "250-SIZE 10485760",
+            "250 8BITMIME",
+            "250 2.1.0 OK",
+            "550 5.1.1 No such mailbox here",
+            "550 5.1.1 No such team here: lnag",
+            "250 2.1.5 OK",
+            "354 End data with <CR><LF>.<CR><LF>",
+            "250 2.0.0 Made 1 changes to the calendar",
+            "221 2.0.0 localhost closing",
+        ]
+    "#]]
+    .assert_debug_eq(&exchange);
+
+    let query = EventQuery {
+        from: Some("2024-03-01T00:00:00Z".parse().unwrap()),
+        to: Some("2024-03-08T00:00:00Z".parse().unwrap()),
+        ..EventQuery::default()
+    };
+    let events = calendar.events(&query).await.unwrap().events;
+    assert_eq!(events[0].summary, "Lang team triage");
+    assert_eq!(events[0].teams, ["lang"]);
+}
@@ -0,0 +1,331 @@
+//! The team roster: which teams exist and who is on them.
+//!
+//! Teams are read from a directory in the layout of the
+//! [rust-lang/team](https://github.com/rust-lang/team) repository (a local checkout
+//! works), with a `teams/<name>.toml` per team and a `people/<github>.toml` per person.
+//! The roster is reloaded periodically so that changes to the checkout are picked up
+//! without a restart.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use serde::Deserialize;
+
+use crate::calendar::teams;
+use crate::calendar::Team;
+use crate::store::Store;
+
+/// The roster as it was last loaded, kept up to date by [`Roster::refresh`].
+pub struct Roster {
+    path: Option<PathBuf>,
+    store: Arc<Store>,
+    current: RwLock<Arc<TeamRoster>>,
+}
+
+/// The teams and people of one load of the roster directory.
+#[derive(Debug, Default)]
+pub struct TeamRoster {
+    teams: BTreeMap<String, RosterTeam>,
+    /// Keyed by GitHub login.
+    people: BTreeMap<String, Person>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RosterTeam {
+    /// The team name, normalized like any other team tag.
+    pub name: String,
+    pub description: Option<String>,
+    /// GitHub logins of the leads, who are also members.
+    pub leads: Vec<String>,
+    /// GitHub logins of the current members.
+    pub members: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Person {
+    pub github: String,
+    pub name: String,
+    /// People can opt out of having their email in the roster.
+    pub email: Option<String>,
+}
+
+/// `teams/<name>.toml`; fields we don't use are ignored.
+#[derive(Deserialize)]
+struct TeamFile {
+    name: String,
+    #[serde(default)]
+    people: PeopleSection,
+    website: Option<WebsiteSection>,
+}
+
+#[derive(Default, Deserialize)]
+struct PeopleSection {
+    #[serde(default)]
+    leads: Vec<String>,
+    #[serde(default)]
+    members: Vec<MemberEntry>,
+}
+
+/// Members are listed by login, or as a table when they have roles on the team.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MemberEntry {
+    Login(String),
+    WithRoles { github: String },
+}
+
+#[derive(Deserialize)]
+struct WebsiteSection {
+    name: Option<String>,
+    description: Option<String>,
+}
+
+/// `people/<github>.toml`.
+#[derive(Deserialize)]
+struct PersonFile {
+    name: String,
+    github: String,
+    /// An address, or `false` for people who don't want one listed.
+    email: Option<toml::Value>,
+}
+
+impl Roster {
+    /// A roster loaded from `path`, or an empty one when there is no path. Call
+    /// [`Roster::refresh`] to load it.
+    pub fn new(path: Option<PathBuf>, store: Arc<Store>) -> Self {
+        Self {
+            path,
+            store,
+            current: RwLock::new(Arc::new(TeamRoster::default())),
+        }
+    }
+
+    pub fn current(&self) -> Arc<TeamRoster> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Reload the roster directory and record its teams in the store, so that they are
+    /// listed with their descriptions. Keeps the previous roster if loading fails.
+    pub fn refresh(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let roster = TeamRoster::load(path)?;
+        for team in roster.teams.values() {
+            self.store.upsert_team(&Team {
+                name: team.name.clone(),
+                description: team.description.clone(),
+            })?;
+        }
+        tracing::info!(
+            "loaded {} teams and {} people from {}",
+            roster.teams.len(),
+            roster.people.len(),
+            path.display()
+        );
+        *self.current.write().unwrap() = Arc::new(roster);
+        Ok(())
+    }
+}
+
+/// Run [`Roster::refresh`] every `interval`, logging failures.
+pub async fn refresh_periodically(roster: Arc<Roster>, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately, and startup already loaded the roster
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if let Err(e) = roster.refresh() {
+            tracing::error!("team roster refresh failed: {e:?}");
+        }
+    }
+}
+
+impl TeamRoster {
+    /// Read the `teams` and `people` directories under `path`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut roster = TeamRoster::default();
+        for (file, text) in toml_files(&path.join("teams"))? {
+            let team: TeamFile = toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("invalid team file {}: {e}", file.display()))?;
+            let Some(name) = teams::normalize(&team.name) else {
+                tracing::warn!("skipping team with invalid name `{}`", team.name);
+                continue;
+            };
+            let description = team
+                .website
+                .and_then(|website| website.description.or(website.name));
+            let members = team
+                .people
+                .members
+                .into_iter()
+                .map(|member| match member {
+                    MemberEntry::Login(github) | MemberEntry::WithRoles { github } => github,
+                })
+                .collect();
+            roster.teams.insert(
+                name.clone(),
+                RosterTeam {
+                    name,
+                    description,
+                    leads: team.people.leads,
+                    members,
+                },
+            );
+        }
+        for (file, text) in toml_files(&path.join("people"))? {
+            let person: PersonFile = toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("invalid person file {}: {e}", file.display()))?;
+            let email = person
+                .email
+                .and_then(|email| email.as_str().map(str::to_string));
+            roster.people.insert(
+                person.github.to_lowercase(),
+                Person {
+                    github: person.github,
+                    name: person.name,
+                    email,
+                },
+            );
+        }
+        Ok(roster)
+    }
+
+    pub fn teams(&self) -> impl Iterator<Item = &RosterTeam> {
+        self.teams.values()
+    }
+
+    pub fn team(&self, name: &str) -> Option<&RosterTeam> {
+        self.teams.get(name)
+    }
+
+    pub fn person(&self, github: &str) -> Option<&Person> {
+        self.people.get(&github.to_lowercase())
+    }
+
+    /// The people on `team`, skipping members without a `people` file.
+    pub fn members(&self, team: &str) -> Vec<&Person> {
+        self.team(team)
+            .into_iter()
+            .flat_map(|team| &team.members)
+            .filter_map(|github| self.person(github))
+            .collect()
+    }
+
+    /// Whether the GitHub user `github` leads `team`.
+    pub fn is_lead(&self, github: &str, team: &str) -> bool {
+        self.team(team).is_some_and(|team| {
+            team.leads
+                .iter()
+                .any(|lead| lead.eq_ignore_ascii_case(github))
+        })
+    }
+
+    /// The teams that the GitHub user `github` is a member of.
+    pub fn teams_of(&self, github: &str) -> Vec<String> {
+        self.teams
+            .values()
+            .filter(|team| {
+                team.members
+                    .iter()
+                    .any(|member| member.eq_ignore_ascii_case(github))
+            })
+            .map(|team| team.name.clone())
+            .collect()
+    }
+
+    /// The teams of whoever has `email` in the roster.
+    pub fn teams_of_email(&self, email: &str) -> Vec<String> {
+        let person = self.people.values().find(|person| {
+            person
+                .email
+                .as_deref()
+                .is_some_and(|address| address.eq_ignore_ascii_case(email))
+        });
+        match person {
+            Some(person) => self.teams_of(&person.github),
+            None => vec![],
+        }
+    }
+}
+
+/// The `.toml` files in `dir` along with their contents, ordered by file name.
+fn toml_files(dir: &Path) -> anyhow::Result<Vec<(PathBuf, String)>> {
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(dir)
+        .map_err(|e| anyhow::anyhow!("could not read {}: {e}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let text = std::fs::read_to_string(&path)?;
+            Ok((path, text))
+        })
+        .collect()
+}
+
+#[test]
+fn test_load_roster() {
+    let roster = TeamRoster::load(Path::new("test_data/team")).unwrap();
+    expect_test::expect![[r#"
+        [
+            RosterTeam {
+                name: "lang",
+                description: Some(
+                    "Designing and helping to implement new language features",
+                ),
+                leads: [
+                    "nikomatsakis",
+                    "tmandry",
+                ],
+                members: [
+                    "nikomatsakis",
+                    "tmandry",
+                    "joshtriplett",
+                ],
+            },
+            RosterTeam {
+                name: "wg-async",
+                description: Some(
+                    "Async programming in Rust",
+                ),
+                leads: [
+                    "tmandry",
+                ],
+                members: [
+                    "tmandry",
+                    "nikomatsakis",
+                ],
+            },
+        ]
+    "#]]
+    .assert_debug_eq(&roster.teams().collect::<Vec<_>>());
+
+    let emails: Vec<_> = roster
+        .members("lang")
+        .iter()
+        .map(|person| person.email.as_deref())
+        .collect();
+    assert_eq!(
+        emails,
+        [Some("niko@alum.mit.edu"), Some("tmandry@example.org"), None]
+    );
+    assert_eq!(roster.teams_of("NikoMatsakis"), ["lang", "wg-async"]);
+    assert_eq!(
+        roster.teams_of_email("tmandry@example.org"),
+        ["lang", "wg-async"]
+    );
+    assert!(roster.teams_of("withoutboats").is_empty());
+    assert!(roster.is_lead("TMandry", "wg-async"));
+    assert!(!roster.is_lead("nikomatsakis", "wg-async"));
+}
@@ -0,0 +1,1547 @@
+//! Local SQLite storage.
+//!
+//! The upstream calendar stays the source of truth for events; the store keeps a
+//! normalized copy of them so that requests are served locally (and keep working
+//! when the upstream is down), along with the data that only Eventageous knows
+//! about: teams, attendees and subscriptions.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::calendar::{self, Attendee, Event, EventTime, Recurrence, Team};
+
+/// Schema changes, applied in order. `PRAGMA user_version` records how many have run,
+/// so new migrations must only ever be appended.
+const MIGRATIONS: &[&str] = &[
+    r#"
+    CREATE TABLE events (
+        id TEXT PRIMARY KEY,
+        summary TEXT NOT NULL,
+        description TEXT,
+        location TEXT,
+        creator_email TEXT NOT NULL,
+        creator_name TEXT NOT NULL,
+        start_datetime TEXT NOT NULL,
+        start_timezone TEXT NOT NULL,
+        end_datetime TEXT NOT NULL,
+        end_timezone TEXT NOT NULL,
+        recurrence INTEGER NOT NULL,
+        start_utc TEXT,
+        end_utc TEXT
+    );
+    CREATE INDEX events_start_utc ON events (start_utc);
+
+    CREATE TABLE teams (
+        name TEXT PRIMARY KEY,
+        description TEXT
+    );
+
+    CREATE TABLE event_teams (
+        event_id TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        team TEXT NOT NULL,
+        PRIMARY KEY (event_id, team)
+    );
+
+    CREATE TABLE attendees (
+        event_id TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        email TEXT NOT NULL,
+        name TEXT,
+        status TEXT NOT NULL,
+        PRIMARY KEY (event_id, email)
+    );
+
+    CREATE TABLE subscriptions (
+        email TEXT NOT NULL,
+        event_id TEXT,
+        team TEXT,
+        CHECK ((event_id IS NULL) != (team IS NULL))
+    );
+    CREATE UNIQUE INDEX subscriptions_unique
+        ON subscriptions (email, IFNULL(event_id, ''), IFNULL(team, ''));
+
+    CREATE TABLE sync_state (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+"#,
+    r#"
+    ALTER TABLE events DROP COLUMN recurrence;
+    ALTER TABLE events ADD COLUMN recurrence_rule TEXT;
+    ALTER TABLE events ADD COLUMN recurrence_dates TEXT;
+    ALTER TABLE events ADD COLUMN recurrence_exception_dates TEXT;
+    ALTER TABLE events ADD COLUMN recurring_event_id TEXT;
+    ALTER TABLE events ADD COLUMN original_start_datetime TEXT;
+    ALTER TABLE events ADD COLUMN status TEXT;
+"#,
+    r#"
+    ALTER TABLE events ADD COLUMN kind TEXT NOT NULL DEFAULT 'timed';
+"#,
+    r#"
+    CREATE TABLE processed_messages (
+        message_id TEXT PRIMARY KEY,
+        outcome TEXT NOT NULL,
+        processed_at TEXT NOT NULL
+    );
+"#,
+    r#"
+    ALTER TABLE events ADD COLUMN sequence INTEGER NOT NULL DEFAULT 0;
+
+    CREATE TABLE counter_proposals (
+        event_id TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        attendee TEXT NOT NULL,
+        sequence INTEGER NOT NULL,
+        start_datetime TEXT NOT NULL,
+        start_timezone TEXT NOT NULL,
+        end_datetime TEXT NOT NULL,
+        end_timezone TEXT NOT NULL,
+        comment TEXT,
+        status TEXT NOT NULL,
+        received_at TEXT NOT NULL,
+        PRIMARY KEY (event_id, attendee, sequence)
+    );
+"#,
+    r#"
+    ALTER TABLE events ADD COLUMN url TEXT;
+    ALTER TABLE attendees ADD COLUMN role TEXT;
+"#,
+    r#"
+    ALTER TABLE events ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public';
+"#,
+    r#"
+    CREATE TABLE invitations (
+        event_id TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        email TEXT NOT NULL,
+        sequence INTEGER NOT NULL,
+        method TEXT NOT NULL,
+        sent_at TEXT NOT NULL,
+        PRIMARY KEY (event_id, email)
+    );
+"#,
+    r#"
+    CREATE TABLE deliveries (
+        id INTEGER PRIMARY KEY,
+        message_id TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        recipient TEXT NOT NULL,
+        raw BLOB NOT NULL,
+        status TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        next_attempt_at TEXT NOT NULL,
+        last_error TEXT,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    CREATE INDEX deliveries_due ON deliveries (status, next_attempt_at);
+    CREATE INDEX deliveries_message_id ON deliveries (message_id);
+"#,
+    r#"
+    ALTER TABLE events ADD COLUMN content_hash TEXT;
+    ALTER TABLE events ADD COLUMN updated_at TEXT;
+    UPDATE events SET updated_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now');
+
+    CREATE TABLE feed_tokens (
+        email TEXT PRIMARY KEY,
+        token TEXT NOT NULL UNIQUE,
+        created_at TEXT NOT NULL
+    );
+"#,
+    r#"
+    CREATE TABLE rsvps (
+        event_id TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        email TEXT NOT NULL,
+        name TEXT,
+        status TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        PRIMARY KEY (event_id, email)
+    );
+"#,
+    r#"
+    CREATE TABLE invite_requests (
+        token TEXT PRIMARY KEY,
+        event_id TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        email TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        expires_at TEXT NOT NULL,
+        confirmed_at TEXT
+    );
+    CREATE INDEX invite_requests_email ON invite_requests (email, created_at);
+
+    CREATE TABLE unsubscribe_tokens (
+        token TEXT PRIMARY KEY,
+        email TEXT NOT NULL,
+        event_id TEXT NOT NULL,
+        UNIQUE (email, event_id)
+    );
+"#,
+    r#"
+    CREATE TABLE occurrence_rsvps (
+        series_id TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        original_start TEXT NOT NULL,
+        email TEXT NOT NULL,
+        name TEXT,
+        status TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        PRIMARY KEY (series_id, original_start, email)
+    );
+"#,
+    r#"
+    CREATE TABLE event_senders (
+        event_id TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        email TEXT NOT NULL,
+        PRIMARY KEY (event_id, email)
+    );
+"#,
+];
+
+const SYNC_TOKEN_KEY: &str = "sync_token";
+/// When an event was last removed, which changes feeds as much as an updated event.
+const DELETED_AT_KEY: &str = "deleted_at";
+
+/// Selects events, along with their teams as a comma-separated `teams` column and
+/// their attendees as a JSON array in `attendees`. RSVPs are kept apart from the
+/// attendees the backend knows about, which syncs replace, and win over them.
+const SELECT_EVENTS: &str = "SELECT events.*, (
+        SELECT group_concat(team) FROM event_teams WHERE event_id = events.id
+    ) AS teams, (
+        SELECT json_group_array(json_object(
+            'email', email, 'name', name, 'status', status, 'role', role
+        ))
+        FROM (
+            SELECT attendees.email, IFNULL(rsvps.name, attendees.name) AS name,
+                IFNULL(rsvps.status, attendees.status) AS status, attendees.role
+            FROM attendees LEFT JOIN rsvps ON rsvps.event_id = attendees.event_id
+                AND lower(rsvps.email) = lower(attendees.email)
+            WHERE attendees.event_id = events.id
+            UNION ALL
+            SELECT email, name, status, NULL FROM rsvps
+            WHERE event_id = events.id AND NOT EXISTS (
+                SELECT 1 FROM attendees WHERE attendees.event_id = rsvps.event_id
+                    AND lower(attendees.email) = lower(rsvps.email)
+            )
+            ORDER BY email
+        )
+    ) AS attendees
+    FROM events";
+
+pub struct Store {
+    connection: Mutex<Connection>,
+}
+
+/// What a user has subscribed to: a single event or everything a team organizes.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Subscription {
+    Event(String),
+    Team(String),
+}
+
+/// An attendee's proposal to move an event (an iTIP `COUNTER`), waiting for the
+/// organizer.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CounterProposal {
+    pub attendee: String,
+    /// The `SEQUENCE` of the event the proposal was made against.
+    pub sequence: u32,
+    pub start: EventTime,
+    pub end: EventTime,
+    pub comment: Option<String>,
+    /// `PROPOSED`, or `DECLINED` once the organizer said no.
+    pub status: String,
+}
+
+/// What we last sent someone we forwarded an event to.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Invitation {
+    pub email: String,
+    /// The `SEQUENCE` of the event that was sent.
+    pub sequence: u32,
+    /// The iTIP method: `REQUEST`, or `CANCEL` once they were told it's off.
+    pub method: String,
+}
+
+/// Someone asking for an invite without logging in, waiting for them to confirm their
+/// address, see [`crate::mail::requests`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct InviteRequest {
+    /// The secret in the confirmation link.
+    pub token: String,
+    pub event_id: String,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+/// A message on its way to one recipient, see [`crate::mail::queue`].
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Delivery {
+    pub id: i64,
+    /// The `Message-ID` of the message, without angle brackets.
+    pub message_id: String,
+    pub sender: String,
+    pub recipient: String,
+    #[serde(skip)]
+    pub raw: Vec<u8>,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryStatus {
+    /// Waiting to be sent, or to be retried.
+    Queued,
+    Sent,
+    /// Gave up after too many attempts, or the recipient's server refused it for good.
+    Failed,
+    /// Sent, but a delivery status notification later said it didn't arrive.
+    Bounced,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Queued => "queued",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Bounced => "bounced",
+        }
+    }
+}
+
+impl std::str::FromStr for DeliveryStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(status: &str) -> anyhow::Result<Self> {
+        match status {
+            "queued" => Ok(DeliveryStatus::Queued),
+            "sent" => Ok(DeliveryStatus::Sent),
+            "failed" => Ok(DeliveryStatus::Failed),
+            "bounced" => Ok(DeliveryStatus::Bounced),
+            _ => anyhow::bail!("unknown delivery status `{status}`"),
+        }
+    }
+}
+
+impl Store {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> anyhow::Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> anyhow::Result<Self> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        let store = Self {
+            connection: Mutex::new(connection),
+        };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap()
+    }
+
+    fn migrate(&self) -> anyhow::Result<()> {
+        let mut connection = self.connection();
+        let version: usize =
+            connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        let transaction = connection.transaction()?;
+        for migration in MIGRATIONS.iter().skip(version) {
+            transaction.execute_batch(migration)?;
+        }
+        transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Events overlapping the window between `from` and `to`, ordered by start time,
+    /// plus every recurring series and occurrence override that may have occurrences in
+    /// it. Use [`calendar::recurrence::expand`] to get the actual occurrences.
+    pub fn events(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<Vec<Event>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!(
+            "{SELECT_EVENTS}
+             WHERE (start_utc < ?2 AND (
+                    end_utc >= ?1
+                    OR recurrence_rule IS NOT NULL
+                    OR recurrence_dates IS NOT NULL))
+                OR recurring_event_id IS NOT NULL
+             ORDER BY start_utc, id"
+        ))?;
+        let events = statement
+            .query_map(params![from.to_rfc3339(), to.to_rfc3339()], event_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(events)
+    }
+
+    pub fn event(&self, id: &str) -> anyhow::Result<Option<Event>> {
+        let connection = self.connection();
+        Ok(connection
+            .query_row(
+                &format!("{SELECT_EVENTS} WHERE id = ?1"),
+                [id],
+                event_from_row,
+            )
+            .optional()?)
+    }
+
+    pub fn upsert_event(&self, event: &Event) -> anyhow::Result<()> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        insert_event(&transaction, event)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn delete_event(&self, id: &str) -> anyhow::Result<()> {
+        let connection = self.connection();
+        if connection.execute("DELETE FROM events WHERE id = ?1", [id])? > 0 {
+            record_deletion(&connection)?;
+        }
+        Ok(())
+    }
+
+    /// Apply a batch of upstream changes in one transaction. A `full` batch replaces
+    /// every stored event, so anything it doesn't mention is removed.
+    pub fn apply_changes(&self, changes: &calendar::backend::Changes) -> anyhow::Result<()> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let mut deleted = 0;
+        if changes.full {
+            // Only remove what's gone, so that what we keep about the remaining events
+            // (such as counter proposals) survives a full resync
+            let kept: HashSet<&str> = changes.events.iter().map(|e| e.id.as_str()).collect();
+            let stored: Vec<String> = transaction
+                .prepare("SELECT id FROM events")?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            for id in stored.iter().filter(|id| !kept.contains(id.as_str())) {
+                deleted += transaction.execute("DELETE FROM events WHERE id = ?1", [id])?;
+            }
+        }
+        for id in &changes.deleted {
+            deleted += transaction.execute("DELETE FROM events WHERE id = ?1", [id])?;
+        }
+        if deleted > 0 {
+            record_deletion(&transaction)?;
+        }
+        for event in &changes.events {
+            insert_event(&transaction, event)?;
+        }
+        match &changes.sync_token {
+            Some(token) => transaction.execute(
+                "INSERT OR REPLACE INTO sync_state (key, value) VALUES (?1, ?2)",
+                params![SYNC_TOKEN_KEY, token],
+            )?,
+            None => {
+                transaction.execute("DELETE FROM sync_state WHERE key = ?1", [SYNC_TOKEN_KEY])?
+            }
+        };
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// The token to pass upstream to fetch only what changed since the last sync.
+    pub fn sync_token(&self) -> anyhow::Result<Option<String>> {
+        let connection = self.connection();
+        Ok(connection
+            .query_row(
+                "SELECT value FROM sync_state WHERE key = ?1",
+                [SYNC_TOKEN_KEY],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// When an event was last removed from the store, if ever.
+    pub fn last_deletion(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let connection = self.connection();
+        let deleted_at: Option<String> = connection
+            .query_row(
+                "SELECT value FROM sync_state WHERE key = ?1",
+                [DELETED_AT_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(match deleted_at {
+            Some(deleted_at) => {
+                Some(DateTime::parse_from_rfc3339(&deleted_at)?.with_timezone(&Utc))
+            }
+            None => None,
+        })
+    }
+
+    pub fn event_teams(&self, event_id: &str) -> anyhow::Result<Vec<String>> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare("SELECT team FROM event_teams WHERE event_id = ?1 ORDER BY team")?;
+        let teams = statement
+            .query_map([event_id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(teams)
+    }
+
+    /// Every team with a description, plus the teams that events are tagged with but
+    /// that nobody has described, ordered by name.
+    pub fn teams(&self) -> anyhow::Result<Vec<Team>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT name, description FROM teams
+             UNION
+             SELECT DISTINCT team, NULL FROM event_teams
+             WHERE team NOT IN (SELECT name FROM teams)
+             ORDER BY 1",
+        )?;
+        let teams = statement
+            .query_map([], |row| {
+                Ok(Team {
+                    name: row.get(0)?,
+                    description: row.get(1)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(teams)
+    }
+
+    /// Only the teams of the roster (and others recorded with [`Store::upsert_team`]),
+    /// not the tags that events happen to have.
+    pub fn known_teams(&self) -> anyhow::Result<Vec<Team>> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare("SELECT name, description FROM teams ORDER BY name")?;
+        let teams = statement
+            .query_map([], |row| {
+                Ok(Team {
+                    name: row.get(0)?,
+                    description: row.get(1)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(teams)
+    }
+
+    pub fn upsert_team(&self, team: &Team) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT INTO teams (name, description) VALUES (?1, ?2)
+             ON CONFLICT (name) DO UPDATE SET description = excluded.description",
+            params![team.name, team.description],
+        )?;
+        Ok(())
+    }
+
+    /// Whether a message with this `Message-ID` was already taken in.
+    pub fn message_processed(&self, message_id: &str) -> anyhow::Result<bool> {
+        let connection = self.connection();
+        Ok(connection
+            .query_row(
+                "SELECT 1 FROM processed_messages WHERE message_id = ?1",
+                [message_id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// Remember that the message with this `Message-ID` was taken in, and how that went.
+    pub fn record_message(&self, message_id: &str, outcome: &str) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT OR REPLACE INTO processed_messages (message_id, outcome, processed_at)
+             VALUES (?1, ?2, ?3)",
+            params![message_id, outcome, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn attendees(&self, event_id: &str) -> anyhow::Result<Vec<Attendee>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT email, name, status, role FROM attendees WHERE event_id = ?1 ORDER BY email",
+        )?;
+        let attendees = statement
+            .query_map([event_id], |row| {
+                Ok(Attendee {
+                    email: row.get(0)?,
+                    name: row.get(1)?,
+                    status: row.get(2)?,
+                    role: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(attendees)
+    }
+
+    pub fn upsert_attendee(&self, event_id: &str, attendee: &Attendee) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT INTO attendees (event_id, email, name, status, role)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (event_id, email) DO UPDATE
+             SET name = IFNULL(excluded.name, name), status = excluded.status,
+                 role = IFNULL(excluded.role, role)",
+            params![
+                event_id,
+                attendee.email,
+                attendee.name,
+                attendee.status,
+                attendee.role
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Remember whether `attendee` is coming to an event, as they told us in a reply or
+    /// in the app. This outlasts syncs, see [`SELECT_EVENTS`].
+    pub fn set_rsvp(&self, event_id: &str, attendee: &Attendee) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT INTO rsvps (event_id, email, name, status, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (event_id, email) DO UPDATE
+             SET name = IFNULL(excluded.name, name), status = excluded.status,
+                 updated_at = excluded.updated_at",
+            params![
+                event_id,
+                attendee.email.to_lowercase(),
+                attendee.name,
+                attendee.status,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Like [`Store::set_rsvp`], for an occurrence of the series `series_id` that is
+    /// only stored as the series, by when it originally starts.
+    pub fn set_occurrence_rsvp(
+        &self,
+        series_id: &str,
+        original_start: &DateTime<Utc>,
+        attendee: &Attendee,
+    ) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT INTO occurrence_rsvps
+                (series_id, original_start, email, name, status, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (series_id, original_start, email) DO UPDATE
+             SET name = IFNULL(excluded.name, name), status = excluded.status,
+                 updated_at = excluded.updated_at",
+            params![
+                series_id,
+                original_start.to_rfc3339(),
+                attendee.email.to_lowercase(),
+                attendee.name,
+                attendee.status,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The RSVPs to single occurrences of the series `series_id`, with the original
+    /// start of the occurrence they are about.
+    pub fn occurrence_rsvps(
+        &self,
+        series_id: &str,
+    ) -> anyhow::Result<Vec<(DateTime<Utc>, Attendee)>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT original_start, email, name, status FROM occurrence_rsvps
+             WHERE series_id = ?1 ORDER BY original_start, email",
+        )?;
+        let rsvps = statement
+            .query_map([series_id], |row| {
+                Ok((
+                    utc(row, "original_start")?,
+                    Attendee {
+                        email: row.get("email")?,
+                        name: row.get("name")?,
+                        status: row.get("status")?,
+                        role: None,
+                    },
+                ))
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(rsvps)
+    }
+
+    /// Remember that the invite which added the event `event_id` came from `email`, or
+    /// named it as sending on the organizer's behalf, so that it may change the event
+    /// later on too.
+    pub fn add_event_sender(&self, event_id: &str, email: &str) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT OR IGNORE INTO event_senders (event_id, email) VALUES (?1, ?2)",
+            params![event_id, email.to_lowercase()],
+        )?;
+        Ok(())
+    }
+
+    /// The addresses that may send changes to the event `event_id` on behalf of its
+    /// organizer, see [`Store::add_event_sender`].
+    pub fn event_senders(&self, event_id: &str) -> anyhow::Result<Vec<String>> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT email FROM event_senders WHERE event_id = ?1 ORDER BY email")?;
+        let senders = statement
+            .query_map([event_id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(senders)
+    }
+
+    /// Remember a proposal; one made again against the same sequence replaces it.
+    pub fn add_counter_proposal(
+        &self,
+        event_id: &str,
+        proposal: &CounterProposal,
+    ) -> anyhow::Result<()> {
+        let connection = self.connection();
+        let zone_name = |time: &EventTime| time.zone().map(|zone| zone.name()).unwrap_or_default();
+        connection.execute(
+            "INSERT OR REPLACE INTO counter_proposals (
+                event_id, attendee, sequence, start_datetime, start_timezone, end_datetime,
+                end_timezone, comment, status, received_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                event_id,
+                proposal.attendee,
+                proposal.sequence,
+                proposal.start.to_string(),
+                zone_name(&proposal.start),
+                proposal.end.to_string(),
+                zone_name(&proposal.end),
+                proposal.comment,
+                proposal.status,
+                Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn counter_proposals(&self, event_id: &str) -> anyhow::Result<Vec<CounterProposal>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT * FROM counter_proposals WHERE event_id = ?1 ORDER BY received_at, attendee",
+        )?;
+        let proposals = statement
+            .query_map([event_id], |row| {
+                Ok(CounterProposal {
+                    attendee: row.get("attendee")?,
+                    sequence: row.get("sequence")?,
+                    start: event_time(
+                        &row.get::<_, String>("start_datetime")?,
+                        &row.get::<_, String>("start_timezone")?,
+                    )?,
+                    end: event_time(
+                        &row.get::<_, String>("end_datetime")?,
+                        &row.get::<_, String>("end_timezone")?,
+                    )?,
+                    comment: row.get("comment")?,
+                    status: row.get("status")?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(proposals)
+    }
+
+    /// Mark the pending proposals for an event as declined.
+    pub fn decline_counter_proposals(&self, event_id: &str) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "UPDATE counter_proposals SET status = 'DECLINED'
+             WHERE event_id = ?1 AND status = 'PROPOSED'",
+            [event_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn invitations(&self, event_id: &str) -> anyhow::Result<Vec<Invitation>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT email, sequence, method FROM invitations WHERE event_id = ?1 ORDER BY email",
+        )?;
+        let invitations = statement
+            .query_map([event_id], |row| {
+                Ok(Invitation {
+                    email: row.get(0)?,
+                    sequence: row.get(1)?,
+                    method: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(invitations)
+    }
+
+    pub fn record_invitation(&self, event_id: &str, invitation: &Invitation) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT OR REPLACE INTO invitations (event_id, email, sequence, method, sent_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                event_id,
+                invitation.email.to_lowercase(),
+                invitation.sequence,
+                invitation.method,
+                Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Who subscribed to `event` (or its series) or to one of its teams.
+    pub fn subscribers(&self, event: &Event) -> anyhow::Result<Vec<String>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT DISTINCT email FROM subscriptions
+             WHERE event_id IN (?1, ?2) OR team IN (SELECT value FROM json_each(?3))
+             ORDER BY email",
+        )?;
+        let teams = serde_json::to_string(&event.teams)?;
+        let subscribers = statement
+            .query_map(params![event.id, event.recurring_event_id, teams], |row| {
+                row.get(0)
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(subscribers)
+    }
+
+    /// Queue `raw` for sending to `recipient` right away.
+    pub fn enqueue_delivery(
+        &self,
+        message_id: &str,
+        sender: &str,
+        recipient: &str,
+        raw: &[u8],
+    ) -> anyhow::Result<()> {
+        let connection = self.connection();
+        let now = Utc::now().to_rfc3339();
+        connection.execute(
+            "INSERT INTO deliveries (
+                message_id, sender, recipient, raw, status, attempts, next_attempt_at,
+                created_at, updated_at
+            ) VALUES (?1, ?2, ?3, ?4, 'queued', 0, ?5, ?5, ?5)",
+            params![message_id, sender, recipient, raw, now],
+        )?;
+        Ok(())
+    }
+
+    /// Queued deliveries whose next attempt is due at `now`, oldest first.
+    pub fn due_deliveries(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<Delivery>> {
+        self.query_deliveries(
+            "WHERE status = 'queued' AND next_attempt_at <= ?1 ORDER BY next_attempt_at, id",
+            &now.to_rfc3339(),
+        )
+    }
+
+    /// Deliveries with `status`, most recently changed first.
+    pub fn deliveries(&self, status: DeliveryStatus) -> anyhow::Result<Vec<Delivery>> {
+        self.query_deliveries(
+            "WHERE status = ?1 ORDER BY updated_at DESC, id DESC",
+            status.as_str(),
+        )
+    }
+
+    fn query_deliveries(&self, condition: &str, parameter: &str) -> anyhow::Result<Vec<Delivery>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!("SELECT * FROM deliveries {condition}"))?;
+        let deliveries = statement
+            .query_map([parameter], delivery_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(deliveries)
+    }
+
+    /// Record an attempt at a delivery: `Sent`, `Failed` for good, or still `Queued`
+    /// with another try at `next_attempt_at`.
+    pub fn record_delivery_attempt(
+        &self,
+        id: i64,
+        status: DeliveryStatus,
+        next_attempt_at: DateTime<Utc>,
+        error: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "UPDATE deliveries
+             SET status = ?2, attempts = attempts + 1, next_attempt_at = ?3, last_error = ?4,
+                 updated_at = ?5
+             WHERE id = ?1",
+            params![
+                id,
+                status.as_str(),
+                next_attempt_at.to_rfc3339(),
+                error,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Mark the delivery of the message with `message_id` to `recipient` as bounced,
+    /// returning whether there was such a delivery.
+    pub fn bounce_delivery(
+        &self,
+        message_id: &str,
+        recipient: &str,
+        error: &str,
+    ) -> anyhow::Result<bool> {
+        let connection = self.connection();
+        let updated = connection.execute(
+            "UPDATE deliveries SET status = 'bounced', last_error = ?3, updated_at = ?4
+             WHERE message_id = ?1 AND recipient = ?2 COLLATE NOCASE",
+            params![message_id, recipient, error, Utc::now().to_rfc3339()],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// The secret token in the URL of `email`'s feed, made up the first time it's needed.
+    pub fn feed_token(&self, email: &str) -> anyhow::Result<String> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT OR IGNORE INTO feed_tokens (email, token, created_at) VALUES (?1, ?2, ?3)",
+            params![email, new_token(), Utc::now().to_rfc3339()],
+        )?;
+        Ok(connection.query_row(
+            "SELECT token FROM feed_tokens WHERE email = ?1",
+            [email],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Replace `email`'s feed token, so that the old URL stops working.
+    pub fn reset_feed_token(&self, email: &str) -> anyhow::Result<String> {
+        let connection = self.connection();
+        let token = new_token();
+        connection.execute(
+            "INSERT OR REPLACE INTO feed_tokens (email, token, created_at) VALUES (?1, ?2, ?3)",
+            params![email, token, Utc::now().to_rfc3339()],
+        )?;
+        Ok(token)
+    }
+
+    /// Whose feed `token` is for.
+    pub fn feed_token_email(&self, token: &str) -> anyhow::Result<Option<String>> {
+        let connection = self.connection();
+        Ok(connection
+            .query_row(
+                "SELECT email FROM feed_tokens WHERE token = ?1",
+                [token],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    pub fn add_invite_request(
+        &self,
+        event_id: &str,
+        email: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<InviteRequest> {
+        let request = InviteRequest {
+            token: new_token(),
+            event_id: event_id.to_string(),
+            email: email.to_string(),
+            created_at: now,
+            expires_at,
+            confirmed_at: None,
+        };
+        let connection = self.connection();
+        connection.execute(
+            "INSERT INTO invite_requests (token, event_id, email, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                request.token,
+                request.event_id,
+                request.email,
+                request.created_at.to_rfc3339(),
+                request.expires_at.to_rfc3339()
+            ],
+        )?;
+        Ok(request)
+    }
+
+    /// How many invites `email` asked for since `since`, for rate limiting.
+    pub fn invite_requests_since(
+        &self,
+        email: &str,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<usize> {
+        let connection = self.connection();
+        Ok(connection.query_row(
+            "SELECT count(*) FROM invite_requests WHERE email = ?1 AND created_at >= ?2",
+            params![email, since.to_rfc3339()],
+            |row| row.get(0),
+        )?)
+    }
+
+    pub fn invite_request(&self, token: &str) -> anyhow::Result<Option<InviteRequest>> {
+        let connection = self.connection();
+        Ok(connection
+            .query_row(
+                "SELECT * FROM invite_requests WHERE token = ?1",
+                [token],
+                |row| {
+                    Ok(InviteRequest {
+                        token: row.get("token")?,
+                        event_id: row.get("event_id")?,
+                        email: row.get("email")?,
+                        created_at: utc(row, "created_at")?,
+                        expires_at: utc(row, "expires_at")?,
+                        confirmed_at: match row.get::<_, Option<String>>("confirmed_at")? {
+                            Some(_) => Some(utc(row, "confirmed_at")?),
+                            None => None,
+                        },
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    pub fn confirm_invite_request(&self, token: &str, now: DateTime<Utc>) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "UPDATE invite_requests SET confirmed_at = ?2 WHERE token = ?1",
+            params![token, now.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Forget the requests of `email` for an event that weren't confirmed, so that the
+    /// links in them stop working.
+    pub fn drop_invite_requests(&self, email: &str, event_id: &str) -> anyhow::Result<()> {
+        let connection = self.connection();
+        connection.execute(
+            "DELETE FROM invite_requests
+             WHERE email = ?1 AND event_id = ?2 AND confirmed_at IS NULL",
+            params![email, event_id],
+        )?;
+        Ok(())
+    }
+
+    /// The secret in the link that unsubscribes `email` from an event, made up the first
+    /// time it's needed.
+    pub fn unsubscribe_token(&self, email: &str, event_id: &str) -> anyhow::Result<String> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT OR IGNORE INTO unsubscribe_tokens (token, email, event_id)
+             VALUES (?1, ?2, ?3)",
+            params![new_token(), email, event_id],
+        )?;
+        Ok(connection.query_row(
+            "SELECT token FROM unsubscribe_tokens WHERE email = ?1 AND event_id = ?2",
+            params![email, event_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Who `token` unsubscribes from which event: `(email, event_id)`.
+    pub fn unsubscribe_token_target(
+        &self,
+        token: &str,
+    ) -> anyhow::Result<Option<(String, String)>> {
+        let connection = self.connection();
+        Ok(connection
+            .query_row(
+                "SELECT email, event_id FROM unsubscribe_tokens WHERE token = ?1",
+                [token],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+
+    pub fn subscriptions(&self, email: &str) -> anyhow::Result<Vec<Subscription>> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT event_id, team FROM subscriptions WHERE email = ?1 ORDER BY rowid")?;
+        let subscriptions = statement
+            .query_map([email], |row| {
+                let event_id: Option<String> = row.get(0)?;
+                let team: Option<String> = row.get(1)?;
+                Ok(match (event_id, team) {
+                    (Some(event_id), _) => Subscription::Event(event_id),
+                    (None, team) => Subscription::Team(team.unwrap_or_default()),
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(subscriptions)
+    }
+
+    pub fn subscribe(&self, email: &str, subscription: &Subscription) -> anyhow::Result<()> {
+        let connection = self.connection();
+        let (event_id, team) = subscription.columns();
+        connection.execute(
+            "INSERT OR IGNORE INTO subscriptions (email, event_id, team) VALUES (?1, ?2, ?3)",
+            params![email, event_id, team],
+        )?;
+        Ok(())
+    }
+
+    pub fn unsubscribe(&self, email: &str, subscription: &Subscription) -> anyhow::Result<()> {
+        let connection = self.connection();
+        let (event_id, team) = subscription.columns();
+        connection.execute(
+            "DELETE FROM subscriptions
+             WHERE email = ?1 AND event_id IS ?2 AND team IS ?3",
+            params![email, event_id, team],
+        )?;
+        Ok(())
+    }
+}
+
+impl Subscription {
+    fn columns(&self) -> (Option<&str>, Option<&str>) {
+        match self {
+            Subscription::Event(id) => (Some(id), None),
+            Subscription::Team(team) => (None, Some(team)),
+        }
+    }
+}
+
+/// Remember that events were removed just now; call within a transaction.
+fn record_deletion(connection: &Connection) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT OR REPLACE INTO sync_state (key, value) VALUES (?1, ?2)",
+        params![DELETED_AT_KEY, Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+/// An unguessable token, for URLs that work without logging in.
+fn new_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// A hash of everything about `event`, to tell whether an update changes anything.
+fn content_hash(event: &Event) -> String {
+    let event = Event {
+        updated: None,
+        ..event.clone()
+    };
+    let json = serde_json::to_vec(&event).expect("events serialize");
+    format!("{:x}", Sha256::digest(json))
+}
+
+/// Insert or update `event`, its teams and its attendees; call within a transaction.
+/// Its `updated_at` only moves when something about it changed.
+fn insert_event(connection: &Connection, event: &Event) -> rusqlite::Result<()> {
+    let zone_name = |time: &EventTime| time.zone().map(|zone| zone.name()).unwrap_or_default();
+    let recurrence = event.recurrence.as_ref();
+    let join = |dates: &Vec<EventTime>| {
+        let dates: Vec<String> = dates.iter().map(EventTime::to_string).collect();
+        (!dates.is_empty()).then(|| dates.join(","))
+    };
+    connection.execute(
+        "INSERT INTO events (
+            id, summary, description, location, creator_email, creator_name,
+            start_datetime, start_timezone, end_datetime, end_timezone,
+            recurrence_rule, recurrence_dates, recurrence_exception_dates,
+            recurring_event_id, original_start_datetime, status,
+            start_utc, end_utc, kind, sequence, url, visibility, content_hash, updated_at
+        ) VALUES (
+            ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+            ?20, ?21, ?22, ?23, ?24
+        )
+        ON CONFLICT (id) DO UPDATE SET
+            summary = excluded.summary,
+            description = excluded.description,
+            location = excluded.location,
+            creator_email = excluded.creator_email,
+            creator_name = excluded.creator_name,
+            start_datetime = excluded.start_datetime,
+            start_timezone = excluded.start_timezone,
+            end_datetime = excluded.end_datetime,
+            end_timezone = excluded.end_timezone,
+            recurrence_rule = excluded.recurrence_rule,
+            recurrence_dates = excluded.recurrence_dates,
+            recurrence_exception_dates = excluded.recurrence_exception_dates,
+            recurring_event_id = excluded.recurring_event_id,
+            original_start_datetime = excluded.original_start_datetime,
+            status = excluded.status,
+            start_utc = excluded.start_utc,
+            end_utc = excluded.end_utc,
+            kind = excluded.kind,
+            sequence = excluded.sequence,
+            url = excluded.url,
+            visibility = excluded.visibility,
+            updated_at = CASE WHEN content_hash IS excluded.content_hash
+                THEN updated_at ELSE excluded.updated_at END,
+            content_hash = excluded.content_hash",
+        params![
+            event.id,
+            event.summary,
+            event.description,
+            event.location,
+            event.creator_email,
+            event.creator_name,
+            event.start.to_string(),
+            zone_name(&event.start),
+            event.end.to_string(),
+            zone_name(&event.end),
+            recurrence.and_then(|r| r.rule.clone()),
+            recurrence.and_then(|r| join(&r.dates)),
+            recurrence.and_then(|r| join(&r.exception_dates)),
+            event.recurring_event_id,
+            event.original_start.map(|original| original.to_string()),
+            event.status,
+            event.start.instant().to_rfc3339(),
+            event.end.instant().to_rfc3339(),
+            event.kind().as_str(),
+            event.sequence,
+            event.url,
+            event.visibility.as_str(),
+            content_hash(event),
+            Utc::now().to_rfc3339(),
+        ],
+    )?;
+
+    connection.execute("DELETE FROM event_teams WHERE event_id = ?1", [&event.id])?;
+    for team in &event.teams {
+        connection.execute(
+            "INSERT OR IGNORE INTO event_teams (event_id, team) VALUES (?1, ?2)",
+            params![event.id, team],
+        )?;
+    }
+
+    connection.execute("DELETE FROM attendees WHERE event_id = ?1", [&event.id])?;
+    for attendee in &event.attendees {
+        connection.execute(
+            "INSERT OR REPLACE INTO attendees (event_id, email, name, status, role)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                event.id,
+                attendee.email,
+                attendee.name,
+                attendee.status,
+                attendee.role
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Read back an [`EventTime`] written as its string form plus the name of its zone.
+fn event_time(date_time: &str, zone: &str) -> rusqlite::Result<EventTime> {
+    EventTime::parse(date_time, Some(zone)).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+/// Read back an instant written with `to_rfc3339`.
+fn utc(row: &Row<'_>, column: &str) -> rusqlite::Result<DateTime<Utc>> {
+    let text: String = row.get(column)?;
+    DateTime::parse_from_rfc3339(&text)
+        .map(|date_time| date_time.with_timezone(&Utc))
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+        })
+}
+
+fn delivery_from_row(row: &Row<'_>) -> rusqlite::Result<Delivery> {
+    Ok(Delivery {
+        id: row.get("id")?,
+        message_id: row.get("message_id")?,
+        sender: row.get("sender")?,
+        recipient: row.get("recipient")?,
+        raw: row.get("raw")?,
+        status: row
+            .get::<_, String>("status")?
+            .parse()
+            .map_err(|e: anyhow::Error| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+            })?,
+        attempts: row.get("attempts")?,
+        next_attempt_at: utc(row, "next_attempt_at")?,
+        last_error: row.get("last_error")?,
+        created_at: utc(row, "created_at")?,
+        updated_at: utc(row, "updated_at")?,
+    })
+}
+
+fn event_from_row(row: &Row<'_>) -> rusqlite::Result<Event> {
+    let start_timezone: String = row.get("start_timezone")?;
+    let end_timezone: String = row.get("end_timezone")?;
+    let start = event_time(&row.get::<_, String>("start_datetime")?, &start_timezone)?;
+    let end = event_time(&row.get::<_, String>("end_datetime")?, &end_timezone)?;
+    let original_start = match row.get::<_, Option<String>>("original_start_datetime")? {
+        Some(original) => Some(event_time(&original, &start_timezone)?),
+        None => None,
+    };
+
+    let rule: Option<String> = row.get("recurrence_rule")?;
+    let dates: Option<String> = row.get("recurrence_dates")?;
+    let exception_dates: Option<String> = row.get("recurrence_exception_dates")?;
+    let split = |dates: Option<String>| -> rusqlite::Result<Vec<EventTime>> {
+        dates
+            .iter()
+            .flat_map(|dates| dates.split(','))
+            .map(|date| event_time(date, &start_timezone))
+            .collect()
+    };
+    let recurrence = match rule.is_some() || dates.is_some() {
+        true => {
+            let mut recurrence = Recurrence {
+                rule,
+                description: String::new(),
+                dates: split(dates)?,
+                exception_dates: split(exception_dates)?,
+            };
+            recurrence.describe();
+            Some(recurrence)
+        }
+        false => None,
+    };
+
+    let mut teams: Vec<String> = row
+        .get::<_, Option<String>>("teams")?
+        .iter()
+        .flat_map(|teams| teams.split(','))
+        .map(str::to_string)
+        .collect();
+    teams.sort();
+    let attendees = serde_json::from_str(&row.get::<_, String>("attendees")?).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+    })?;
+
+    Ok(Event {
+        id: row.get("id")?,
+        summary: row.get("summary")?,
+        description: row.get("description")?,
+        location: row.get("location")?,
+        url: row.get("url")?,
+        creator_email: row.get("creator_email")?,
+        creator_name: row.get("creator_name")?,
+        start,
+        end,
+        recurrence,
+        recurring_event_id: row.get("recurring_event_id")?,
+        original_start,
+        status: row.get("status")?,
+        teams,
+        sequence: row.get("sequence")?,
+        attendees,
+        visibility: row
+            .get::<_, String>("visibility")?
+            .parse()
+            .map_err(|e: anyhow::Error| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+            })?,
+        updated: match row.get::<_, Option<String>>("updated_at")? {
+            Some(_) => Some(utc(row, "updated_at")?),
+            None => None,
+        },
+        redacted: false,
+    })
+}
+
+#[test]
+fn test_apply_changes() {
+    use crate::calendar::backend::Changes;
+
+    let store = Store::in_memory().unwrap();
+    let event = |id: &str, start: &str, end: &str| Event {
+        id: id.to_string(),
+        summary: format!("Event {id}"),
+        description: None,
+        location: None,
+        creator_email: "test@example.org".to_string(),
+        creator_name: "Test".to_string(),
+        start: EventTime::parse(start, None).unwrap(),
+        end: EventTime::parse(end, None).unwrap(),
+        teams: vec!["lang".to_string()],
+        ..Event::default()
+    };
+
+    store
+        .apply_changes(&Changes {
+            events: vec![
+                event("b", "2024-03-14T10:00:00Z", "2024-03-14T11:00:00Z"),
+                event(
+                    "a",
+                    "2024-03-13T10:00:00-05:00",
+                    "2024-03-13T11:00:00-05:00",
+                ),
+                event("old", "2023-01-01T10:00:00Z", "2023-01-01T11:00:00Z"),
+            ],
+            deleted: vec![],
+            full: true,
+            sync_token: Some("token-1".to_string()),
+        })
+        .unwrap();
+    store
+        .upsert_attendee(
+            "a",
+            &Attendee {
+                email: "niko@example.org".to_string(),
+                name: None,
+                status: "ACCEPTED".to_string(),
+                role: None,
+            },
+        )
+        .unwrap();
+
+    store
+        .apply_changes(&Changes {
+            events: vec![event("b", "2024-03-15T10:00:00Z", "2024-03-15T11:00:00Z")],
+            deleted: vec!["a".to_string()],
+            full: false,
+            sync_token: Some("token-2".to_string()),
+        })
+        .unwrap();
+
+    let from = "2024-03-01T00:00:00Z".parse().unwrap();
+    let to = "2024-04-01T00:00:00Z".parse().unwrap();
+    let starts: Vec<String> = store
+        .events(from, to)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.start.to_string())
+        .collect();
+    assert_eq!(starts, vec!["2024-03-15T10:00:00Z".to_string()]);
+    assert!(store.attendees("a").unwrap().is_empty());
+    assert_eq!(store.event("b").unwrap().unwrap().teams, ["lang"]);
+
+    store
+        .upsert_team(&Team {
+            name: "compiler".to_string(),
+            description: Some("The compiler team".to_string()),
+        })
+        .unwrap();
+    let teams: Vec<String> = store.teams().unwrap().into_iter().map(|t| t.name).collect();
+    assert_eq!(teams, ["compiler", "lang"]);
+    assert_eq!(store.sync_token().unwrap().as_deref(), Some("token-2"));
+    assert!(store.last_deletion().unwrap().is_some());
+
+    // Syncing an event again only counts as an update if something changed
+    let b = event("b", "2024-03-15T10:00:00Z", "2024-03-15T11:00:00Z");
+    let updated = store.event("b").unwrap().unwrap().updated;
+    assert!(updated.is_some());
+    store.upsert_event(&b).unwrap();
+    assert_eq!(store.event("b").unwrap().unwrap().updated, updated);
+    store
+        .upsert_event(&Event {
+            summary: "Renamed".to_string(),
+            ..b
+        })
+        .unwrap();
+    assert_ne!(store.event("b").unwrap().unwrap().updated, updated);
+}
+
+#[test]
+fn test_subscriptions() {
+    let store = Store::in_memory().unwrap();
+    let team = Subscription::Team("lang".to_string());
+    let event = Subscription::Event("triage@example.org".to_string());
+
+    store.subscribe("niko@example.org", &team).unwrap();
+    store.subscribe("niko@example.org", &event).unwrap();
+    store.subscribe("niko@example.org", &team).unwrap();
+    assert_eq!(
+        store.subscriptions("niko@example.org").unwrap(),
+        vec![team.clone(), event.clone()]
+    );
+    store
+        .subscribe(
+            "tmandry@example.org",
+            &Subscription::Team("wg-async".to_string()),
+        )
+        .unwrap();
+    let triage = Event {
+        id: "triage@example.org".to_string(),
+        teams: vec!["lang".to_string(), "compiler".to_string()],
+        ..Event::default()
+    };
+    assert_eq!(store.subscribers(&triage).unwrap(), ["niko@example.org"]);
+
+    store.unsubscribe("niko@example.org", &team).unwrap();
+    assert_eq!(
+        store.subscriptions("niko@example.org").unwrap(),
+        vec![event]
+    );
+
+    let token = store.feed_token("niko@example.org").unwrap();
+    assert_eq!(store.feed_token("niko@example.org").unwrap(), token);
+    assert_eq!(
+        store.feed_token_email(&token).unwrap().as_deref(),
+        Some("niko@example.org")
+    );
+    let reset = store.reset_feed_token("niko@example.org").unwrap();
+    assert_ne!(reset, token);
+    assert_eq!(store.feed_token_email(&token).unwrap(), None);
+}
+
+#[test]
+fn test_rsvps() {
+    use crate::calendar::Attendance;
+
+    let store = Store::in_memory().unwrap();
+    let attendee = |email: &str, status: &str| Attendee {
+        email: email.to_string(),
+        name: None,
+        status: status.to_string(),
+        role: None,
+    };
+    let event = Event {
+        id: "triage@example.org".to_string(),
+        start: EventTime::parse("2024-03-06T16:00:00Z", None).unwrap(),
+        end: EventTime::parse("2024-03-06T17:00:00Z", None).unwrap(),
+        attendees: vec![
+            Attendee {
+                name: Some("Niko".to_string()),
+                ..attendee("niko@example.org", "NEEDS-ACTION")
+            },
+            attendee("tmandry@example.org", "NEEDS-ACTION"),
+        ],
+        ..Event::default()
+    };
+    store.upsert_event(&event).unwrap();
+    store
+        .set_rsvp(&event.id, &attendee("Niko@example.org", "ACCEPTED"))
+        .unwrap();
+    store
+        .set_rsvp(&event.id, &attendee("jane@example.org", "TENTATIVE"))
+        .unwrap();
+
+    // RSVPs outlast syncs, which only know what the organizer's calendar says
+    store.upsert_event(&event).unwrap();
+    let attendees = store.event(&event.id).unwrap().unwrap().attendees;
+    let statuses: Vec<_> = attendees
+        .iter()
+        .map(|attendee| format!("{} {:?} {}", attendee.email, attendee.name, attendee.status))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            "jane@example.org None TENTATIVE",
+            "niko@example.org Some(\"Niko\") ACCEPTED",
+            "tmandry@example.org None NEEDS-ACTION"
+        ]
+    );
+    assert_eq!(
+        Attendance::of(&attendees),
+        Attendance {
+            going: 1,
+            maybe: 1,
+            not_going: 0,
+            no_reply: 1
+        }
+    );
+}
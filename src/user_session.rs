@@ -1,8 +1,12 @@
 use crate::auth::{Auth, CallbackState};
+use crate::calendar::Viewer;
+use crate::config::Configuration;
+use crate::roster::Roster;
 use axum::extract::Query;
 use axum::{response::IntoResponse, response::Redirect, Extension};
 use serde::Deserialize;
 use serde::Serialize;
+use std::sync::Arc;
 use tower_sessions::Session;
 
 const USER_KEY: &str = "user";
@@ -15,6 +19,8 @@ const PRETEND_TO_LOGIN: bool = false;
 struct User {
     id: i64,
     email: String,
+    #[serde(default)]
+    login: String,
 }
 
 // Don't need this really unless we store the token, leaving for now
@@ -23,6 +29,7 @@ impl std::fmt::Debug for User {
         f.debug_struct("User")
             .field("id", &self.id)
             .field("email", &self.email)
+            .field("login", &self.login)
             //.field("token", &"[redacted]")
             .finish()
     }
@@ -58,6 +65,7 @@ pub async fn pretend_login(session: Session) {
     let user = User {
         id: 1, // This should be a real ID
         email: "test_at_boop".to_string(),
+        login: "test".to_string(),
     };
     session.insert(USER_KEY, user).await.unwrap();
     tracing::info!("Bypassing login, prtending it worked");
@@ -81,9 +89,11 @@ pub async fn github_login_callback(
 
     // Store the user in the session
     // Might not need this struct too, but leaving for now
+    let authenticated_user = authenticated_user.unwrap();
     let user = User {
         id: 1, // This should be a real ID
-        email: authenticated_user.unwrap().email.clone(),
+        email: authenticated_user.email,
+        login: authenticated_user.login,
     };
 
     session.insert(USER_KEY, user).await.unwrap();
@@ -106,9 +116,56 @@ pub async fn logged_in(session: &Session) -> bool {
 // For testing
 pub async fn get_user_email_from_session(session: &Session) -> String {
     let user: Option<User> = session.get(USER_KEY).await.unwrap();
-    if user.is_some() {
-        let user = user.unwrap();
-        return user.email;
+    match user {
+        Some(user) => user.email,
+        None => "no email, user may not be logged in".to_string(),
+    }
+}
+
+/// The email of the logged in user, if there is one and GitHub told us their email.
+pub async fn get_user_email(session: &Session) -> Option<String> {
+    let user: Option<User> = session.get(USER_KEY).await.unwrap();
+    user.map(|user| user.email)
+        .filter(|email| !email.is_empty())
+}
+
+/// The GitHub login of the logged in user, if there is one.
+pub async fn get_user_login(session: &Session) -> Option<String> {
+    let user: Option<User> = session.get(USER_KEY).await.unwrap();
+    user.map(|user| user.login)
+        .filter(|login| !login.is_empty())
+}
+
+/// Whether the logged in user is one of the `admins` in the configuration.
+pub async fn is_admin(session: &Session, config: &Configuration) -> bool {
+    let user: Option<User> = session.get(USER_KEY).await.unwrap();
+    user.is_some_and(|user| {
+        config
+            .admins
+            .iter()
+            .any(|admin| admin.eq_ignore_ascii_case(&user.login))
+    })
+}
+
+/// The teams the logged in user is on according to the roster, by GitHub login or,
+/// failing that, by email.
+pub async fn get_user_teams(session: &Session, roster: &Arc<Roster>) -> Vec<String> {
+    let user: Option<User> = session.get(USER_KEY).await.unwrap();
+    let Some(user) = user else {
+        return vec![];
+    };
+    let roster = roster.current();
+    let teams = roster.teams_of(&user.login);
+    match teams.is_empty() {
+        true => roster.teams_of_email(&user.email),
+        false => teams,
+    }
+}
+
+/// The logged in user, or an anonymous one, as far as seeing private events goes.
+pub async fn get_viewer(session: &Session, roster: &Arc<Roster>) -> Viewer {
+    Viewer {
+        email: get_user_email(session).await,
+        teams: get_user_teams(session, roster).await,
     }
-    return "no email, user may not be logged in".to_string();
 }